@@ -121,6 +121,16 @@ export async function activate(context: ExtensionContext) {
     serverOptions,
     clientOptions
   );
+  // Go to definition on a built-in function opens a read-only document of
+  // its docs, generated by the server.
+  context.subscriptions.push(
+    workspace.registerTextDocumentContentProvider("oel-builtin", {
+      provideTextDocumentContent: async (uri) =>
+        (await client.sendRequest<string | null>("oel/builtinDocument", {
+          uri: uri.toString(),
+        })) ?? "",
+    })
+  );
   // activateInlayHints(context);
   client.start();
 }
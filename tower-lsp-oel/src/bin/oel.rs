@@ -0,0 +1,694 @@
+//! `oel`, a command-line front end to the parser, evaluator and formatter,
+//! meant for CI pipelines.
+//!
+//! Exit codes:
+//!
+//! * `0` success
+//! * `1` syntax errors
+//! * `2` semantic or evaluation errors
+//! * `3` files that are not formatted (`fmt --check`)
+//! * `4` I/O or usage errors
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
+use oel_language_server::builtins::{FixedClock, Registry};
+use oel_language_server::config::Config;
+use oel_language_server::dialect::{self, Dialect};
+use oel_language_server::directory::InMemoryDirectory;
+use oel_language_server::eval::{Context, Interpreter};
+use oel_language_server::expression_context::ExpressionContext;
+use oel_language_server::formatter;
+use oel_language_server::oel::{parse, parse_tree, Entry, ErrorToken, Expr, Spanned};
+use oel_language_server::schema::Schema;
+use oel_language_server::typecheck;
+use tree_sitter::Point;
+
+const SYNTAX_ERROR: u8 = 1;
+const SEMANTIC_ERROR: u8 = 2;
+const UNFORMATTED: u8 = 3;
+const USAGE_ERROR: u8 = 4;
+
+#[derive(Parser)]
+#[command(
+    name = "oel",
+    version,
+    about = "Parse, check, evaluate and format Okta Expression Language files"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the syntax tree of a file.
+    Parse {
+        /// File to parse, or `-` for standard input.
+        file: PathBuf,
+    },
+    /// Report syntax and semantic errors.
+    Check {
+        /// Files to check, or `-` for standard input.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Config file with the profile schemas; defaults to `.oel.json` in
+        /// the current directory.
+        #[arg(long)]
+        config: Option<PathBuf>,
+        /// Check as a `group-rule`, `profile-mapping`, ... regardless of the
+        /// pragma, extension or config of each file.
+        #[arg(long, value_name = "CONTEXT", value_parser = parse_expression_context)]
+        expression_context: Option<ExpressionContext>,
+        /// Check as `classic` or `oie` regardless of the pragma, extension or
+        /// config of each file.
+        #[arg(long, value_parser = parse_dialect)]
+        dialect: Option<Dialect>,
+    },
+    /// Evaluate an expression and print the result as JSON. The entries of
+    /// a file of several are evaluated into an object keyed by name.
+    Eval {
+        /// File to evaluate, or `-` for standard input.
+        file: PathBuf,
+        /// Only evaluate the entry with this name.
+        #[arg(long)]
+        rule: Option<String>,
+        /// JSON file with the `user`, `app`, `org`, ... objects to evaluate against.
+        #[arg(long)]
+        context: Option<PathBuf>,
+        /// JSON or YAML fixture with the users and groups for group functions.
+        #[arg(long)]
+        directory: Option<PathBuf>,
+        /// Evaluate as if it were this RFC 3339 instant, e.g. `2024-01-01T00:00:00Z`.
+        #[arg(long)]
+        now: Option<DateTime<Utc>>,
+        /// Evaluate as a `group-rule`, `profile-mapping`, ... regardless of
+        /// the pragma or extension of the file.
+        #[arg(long, value_name = "CONTEXT", value_parser = parse_expression_context)]
+        expression_context: Option<ExpressionContext>,
+        /// Evaluate as `classic` or `oie` regardless of the pragma or
+        /// extension of the file.
+        #[arg(long, value_parser = parse_dialect)]
+        dialect: Option<Dialect>,
+    },
+    /// Print the expressions of a file as Okta takes them, without comments.
+    /// The entries of a file of several are printed as a JSON object keyed
+    /// by name.
+    Export {
+        /// File to export, or `-` for standard input.
+        file: PathBuf,
+    },
+    /// Format files in place.
+    Fmt {
+        /// Files to format, or `-` to format standard input to standard output.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Only report files that are not formatted.
+        #[arg(long)]
+        check: bool,
+    },
+}
+
+/// A failure that ends the command with the given exit code.
+struct Failure {
+    code: u8,
+    msg: Option<String>,
+}
+
+impl Failure {
+    fn usage(msg: impl Into<String>) -> Self {
+        Failure {
+            code: USAGE_ERROR,
+            msg: Some(msg.into()),
+        }
+    }
+
+    fn code(code: u8) -> Self {
+        Failure { code, msg: None }
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(err) => {
+            let _ = err.print();
+            return if err.use_stderr() {
+                ExitCode::from(USAGE_ERROR)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    let result = match cli.command {
+        Command::Parse { file } => run_parse(&file),
+        Command::Check {
+            files,
+            config,
+            expression_context,
+            dialect,
+        } => run_check(&files, config.as_deref(), expression_context, dialect),
+        Command::Eval {
+            file,
+            rule,
+            context,
+            directory,
+            now,
+            expression_context,
+            dialect,
+        } => run_eval(
+            &file,
+            rule.as_deref(),
+            context.as_deref(),
+            directory.as_deref(),
+            now,
+            expression_context,
+            dialect,
+        ),
+        Command::Export { file } => run_export(&file),
+        Command::Fmt { files, check } => run_fmt(&files, check),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if let Some(msg) = failure.msg {
+                eprintln!("error: {}", msg);
+            }
+            ExitCode::from(failure.code)
+        }
+    }
+}
+
+fn read_source(path: &Path) -> Result<String, Failure> {
+    if path == Path::new("-") {
+        let mut src = String::new();
+        std::io::stdin()
+            .read_to_string(&mut src)
+            .map_err(|err| Failure::usage(format!("<stdin>: {}", err)))?;
+        Ok(src)
+    } else {
+        std::fs::read_to_string(path)
+            .map_err(|err| Failure::usage(format!("{}: {}", path.display(), err)))
+    }
+}
+
+fn display_name(path: &Path) -> String {
+    if path == Path::new("-") {
+        "<stdin>".to_string()
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// Prints `file:line:col: message` followed by the offending line with the
+/// span underlined.
+fn report(file: &str, src: &str, start: Point, end: Point, msg: &str) {
+    eprintln!("{}:{}:{}: {}", file, start.row + 1, start.column + 1, msg);
+    let line = src.lines().nth(start.row).unwrap_or("");
+    let end_column = if end.row == start.row {
+        end.column.min(line.len())
+    } else {
+        line.len()
+    };
+    let padding = line
+        .get(..start.column.min(line.len()))
+        .unwrap_or("")
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    let width = line
+        .get(start.column.min(end_column)..end_column)
+        .map_or(1, |s| s.chars().count().max(1));
+    eprintln!("    {}", line);
+    eprintln!("    {}{}", padding, "^".repeat(width));
+}
+
+/// Parses `src`, reporting syntax errors. Fails with [`SYNTAX_ERROR`] if
+/// there were any.
+fn parse_source(file: &str, src: &str) -> Result<Vec<Entry>, Failure> {
+    let (entries, errors) = parse(src);
+    report_syntax_errors(file, src, &errors);
+    if errors.is_empty() {
+        Ok(entries)
+    } else {
+        Err(Failure::code(SYNTAX_ERROR))
+    }
+}
+
+fn report_syntax_errors(file: &str, src: &str, errors: &[ErrorToken]) {
+    for error in errors {
+        report(file, src, error.start, error.end, &error.message);
+        for related in &error.related {
+            report(
+                file,
+                src,
+                related.start,
+                related.end,
+                &format!("note: {}", related.message),
+            );
+        }
+    }
+}
+
+/// Combines the exit codes of several files. Lower codes are more severe, so
+/// a syntax error in one file is not masked by a formatting issue in another.
+fn worst(code: u8, other: u8) -> u8 {
+    match (code, other) {
+        (0, other) => other,
+        (code, 0) => code,
+        (code, other) => code.min(other),
+    }
+}
+
+fn exit_with(code: u8) -> Result<(), Failure> {
+    match code {
+        0 => Ok(()),
+        code => Err(Failure::code(code)),
+    }
+}
+
+fn run_parse(path: &Path) -> Result<(), Failure> {
+    let src = read_source(path)?;
+    let (entries, errors) = parse(&src);
+    // The tree is printed even with syntax errors, showing where tree-sitter
+    // recovered.
+    for entry in &entries {
+        match &entry.name {
+            Some((name, _)) => {
+                let span = &entry.span;
+                println!(
+                    "Entry {:?} @ {}:{}-{}:{}",
+                    name,
+                    span.start.row + 1,
+                    span.start.column + 1,
+                    span.end.row + 1,
+                    span.end.column + 1
+                );
+                print_tree(&entry.value, 1);
+            }
+            None => print_tree(&entry.value, 0),
+        }
+    }
+    report_syntax_errors(&display_name(path), &src, &errors);
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(Failure::code(SYNTAX_ERROR))
+    }
+}
+
+fn print_tree((expr, span): &Spanned<Expr>, depth: usize) {
+    let label = match expr {
+        Expr::Error => "Error".to_string(),
+        Expr::Value(value) => format!("Value {}", value.to_json()),
+        Expr::Ident(name) => format!("Ident {}", name),
+        Expr::Array(_) => "Array".to_string(),
+        Expr::Map(entries) => format!(
+            "Map {}",
+            entries
+                .iter()
+                .map(|((key, _), _)| key.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expr::Member { property, .. } => format!("Member .{}", property.0),
+        Expr::Subscript { .. } => "Subscript".to_string(),
+        Expr::Call { .. } => "Call".to_string(),
+        Expr::Unary { op, .. } => format!("Unary {}", op),
+        Expr::Binary { op, .. } => format!("Binary {}", op),
+        Expr::Ternary { .. } => "Ternary".to_string(),
+    };
+    println!(
+        "{}{} @ {}:{}-{}:{}",
+        "  ".repeat(depth),
+        label,
+        span.start.row + 1,
+        span.start.column + 1,
+        span.end.row + 1,
+        span.end.column + 1
+    );
+    for child in expr.children() {
+        print_tree(child, depth + 1);
+    }
+}
+
+fn parse_expression_context(id: &str) -> Result<ExpressionContext, String> {
+    ExpressionContext::from_id(id).ok_or_else(|| {
+        format!(
+            "expected one of {}",
+            ExpressionContext::ALL
+                .iter()
+                .map(|context| context.id())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    })
+}
+
+/// The context of a file, unless given on the command line.
+fn expression_context(
+    path: &Path,
+    src: &str,
+    given: Option<ExpressionContext>,
+    default: Option<ExpressionContext>,
+) -> ExpressionContext {
+    given.unwrap_or_else(|| {
+        let path = Some(path).filter(|path| *path != Path::new("-"));
+        ExpressionContext::detect(path, src, default.unwrap_or_default())
+    })
+}
+
+fn parse_dialect(id: &str) -> Result<Dialect, String> {
+    Dialect::from_id(id).ok_or_else(|| "expected classic or oie".to_string())
+}
+
+/// The dialect of a file, unless given on the command line.
+fn dialect(
+    path: &Path,
+    src: &str,
+    given: Option<Dialect>,
+    default: Option<Dialect>,
+    context: ExpressionContext,
+) -> Dialect {
+    given.unwrap_or_else(|| {
+        let path = Some(path).filter(|path| *path != Path::new("-"));
+        Dialect::detect(path, src, default, context)
+    })
+}
+
+fn load_config(config: Option<&Path>) -> Result<Config, Failure> {
+    match config {
+        Some(path) => Config::load(path).map_err(Failure::usage),
+        None => Ok(Config::discover(Path::new("."))
+            .map_err(Failure::usage)?
+            .unwrap_or_default()),
+    }
+}
+
+fn load_schema(config: &Config) -> Result<Schema, Failure> {
+    let (schema, errors) = config.schema();
+    match errors.into_iter().next() {
+        Some(err) => Err(Failure::usage(err)),
+        None => Ok(schema),
+    }
+}
+
+fn run_check(
+    paths: &[PathBuf],
+    config: Option<&Path>,
+    given_context: Option<ExpressionContext>,
+    given_dialect: Option<Dialect>,
+) -> Result<(), Failure> {
+    let registry = Registry::default();
+    let config = load_config(config)?;
+    let schema = load_schema(&config)?;
+    let mut code = 0;
+    for path in paths {
+        let src = read_source(path)?;
+        let file = display_name(path);
+        match parse_source(&file, &src) {
+            Ok(entries) => {
+                let context = expression_context(path, &src, given_context, config.context);
+                let dialect = dialect(path, &src, given_dialect, config.dialect, context);
+                let mut errors = parse_tree(&src, None)
+                    .map(|tree| dialect::check(&tree, &src, dialect))
+                    .unwrap_or_default();
+                errors.extend(typecheck::check_entries(
+                    &entries, &registry, &schema, context, dialect,
+                ));
+                errors.sort_by_key(|error| error.span.start);
+                for error in &errors {
+                    report(&file, &src, error.span.start, error.span.end, &error.msg);
+                    for fix in &error.fixes {
+                        eprintln!("  help: {}", fix.title());
+                    }
+                }
+                if !errors.is_empty() {
+                    code = worst(code, SEMANTIC_ERROR);
+                }
+            }
+            Err(failure) => code = worst(code, failure.code),
+        }
+    }
+    exit_with(code)
+}
+
+fn run_eval(
+    path: &Path,
+    rule: Option<&str>,
+    context: Option<&Path>,
+    directory: Option<&Path>,
+    now: Option<DateTime<Utc>>,
+    given_context: Option<ExpressionContext>,
+    given_dialect: Option<Dialect>,
+) -> Result<(), Failure> {
+    let context = match context {
+        Some(context) => {
+            let json = std::fs::read_to_string(context)
+                .map_err(|err| Failure::usage(format!("{}: {}", context.display(), err)))?;
+            let json = serde_json::from_str(&json)
+                .map_err(|err| Failure::usage(format!("{}: {}", context.display(), err)))?;
+            Context::from_json(json)
+                .map_err(|err| Failure::usage(format!("{}: {}", context.display(), err)))?
+        }
+        None => Context::new(),
+    };
+    let directory = directory
+        .map(InMemoryDirectory::load)
+        .transpose()
+        .map_err(Failure::usage)?
+        .unwrap_or_default();
+    let registry = match now {
+        Some(now) => Registry::new(Arc::new(FixedClock(now))),
+        None => Registry::default(),
+    };
+
+    let src = read_source(path)?;
+    let file = display_name(path);
+    let mut entries = parse_source(&file, &src)?;
+    if let Some(rule) = rule {
+        entries.retain(|entry| entry.name.as_ref().map(|(name, _)| name.as_str()) == Some(rule));
+        if entries.is_empty() {
+            return Err(Failure::usage(format!(
+                "{}: no rule named {:?}",
+                file, rule
+            )));
+        }
+    }
+    if entries.is_empty() {
+        return Err(Failure::usage(format!("{}: empty expression", file)));
+    }
+    let expression_context = expression_context(path, &src, given_context, None);
+    let dialect = dialect(path, &src, given_dialect, None, expression_context);
+    let interpreter = Interpreter::new(&context, &registry)
+        .with_directory(&directory)
+        .with_expression_context(expression_context)
+        .with_dialect(dialect);
+    let mut values = serde_json::Map::new();
+    let mut failed = false;
+    for entry in &entries {
+        match interpreter.eval(&entry.value) {
+            Ok(value) => {
+                let name = entry.name.as_ref().map_or("", |(name, _)| name.as_str());
+                values.insert(name.to_string(), value.to_json());
+            }
+            Err(err) => {
+                report(&file, &src, err.span.start, err.span.end, &err.msg);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        return Err(Failure::code(SEMANTIC_ERROR));
+    }
+    let json = match (&entries[..], rule) {
+        ([entry], _) if entry.name.is_none() || rule.is_some() => values
+            .into_iter()
+            .next()
+            .map(|(_, value)| value)
+            .unwrap_or_default(),
+        _ => serde_json::Value::Object(values),
+    };
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&json).unwrap_or_default()
+    );
+    Ok(())
+}
+
+fn run_export(path: &Path) -> Result<(), Failure> {
+    let src = read_source(path)?;
+    let file = display_name(path);
+    let entries = parse_source(&file, &src)?;
+    match &entries[..] {
+        [] => {}
+        [entry] if entry.name.is_none() => println!("{}", entry.source),
+        entries => {
+            let json = entries
+                .iter()
+                .map(|entry| {
+                    let name = entry.name.as_ref().map_or("", |(name, _)| name.as_str());
+                    (
+                        name.to_string(),
+                        serde_json::Value::String(entry.source.clone()),
+                    )
+                })
+                .collect::<serde_json::Map<_, _>>();
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&json).unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn run_fmt(paths: &[PathBuf], check: bool) -> Result<(), Failure> {
+    let mut code = 0;
+    for path in paths {
+        let src = read_source(path)?;
+        let file = display_name(path);
+        match parse_source(&file, &src) {
+            Ok(entries) if entries.is_empty() => continue,
+            Ok(_) => {}
+            Err(failure) => {
+                code = worst(code, failure.code);
+                continue;
+            }
+        }
+        let formatted = match formatter::format_file(&src) {
+            Some(formatted) => formatted,
+            None => {
+                eprintln!(
+                    "{}: comments inside the expression cannot be formatted",
+                    file
+                );
+                code = worst(code, USAGE_ERROR);
+                continue;
+            }
+        };
+        if check {
+            if formatted != src {
+                eprintln!("{}: not formatted", file);
+                code = worst(code, UNFORMATTED);
+            }
+        } else if path == Path::new("-") {
+            print!("{}", formatted);
+        } else if formatted != src {
+            std::fs::write(path, formatted)
+                .map_err(|err| Failure::usage(format!("{}: {}", file, err)))?;
+        }
+    }
+    exit_with(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(result: Result<(), Failure>) -> u8 {
+        result.err().map_or(0, |failure| failure.code)
+    }
+
+    /// Writes `files` to a directory of their own, named after the test.
+    fn write(test: &str, files: &[(&str, &str)]) -> (PathBuf, Vec<PathBuf>) {
+        let dir = std::env::temp_dir().join(format!("oel-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = files
+            .iter()
+            .map(|(name, src)| {
+                let path = dir.join(name);
+                std::fs::write(&path, src).unwrap();
+                path
+            })
+            .collect();
+        (dir, paths)
+    }
+
+    #[test]
+    fn worst_keeps_the_most_severe_code() {
+        assert_eq!(worst(0, 0), 0);
+        assert_eq!(worst(0, UNFORMATTED), UNFORMATTED);
+        assert_eq!(worst(SEMANTIC_ERROR, 0), SEMANTIC_ERROR);
+        assert_eq!(worst(UNFORMATTED, SYNTAX_ERROR), SYNTAX_ERROR);
+        assert_eq!(worst(USAGE_ERROR, SEMANTIC_ERROR), SEMANTIC_ERROR);
+    }
+
+    #[test]
+    fn check_exit_codes() {
+        let (dir, paths) = write(
+            "check",
+            &[
+                ("valid.oel", "user.login == \"admin\"\n"),
+                ("syntax.oel", "user.login )\n"),
+                ("semantic.oel", "foo()\n"),
+            ],
+        );
+        let [valid, syntax, semantic] = [&paths[0], &paths[1], &paths[2]];
+        let check = |paths: &[&PathBuf]| {
+            let paths = paths
+                .iter()
+                .map(|path| path.to_path_buf())
+                .collect::<Vec<_>>();
+            code(run_check(&paths, None, None, None))
+        };
+        assert_eq!(check(&[valid]), 0);
+        assert_eq!(check(&[syntax]), SYNTAX_ERROR);
+        assert_eq!(check(&[semantic]), SEMANTIC_ERROR);
+        assert_eq!(check(&[semantic, syntax, valid]), SYNTAX_ERROR);
+        assert_eq!(check(&[&dir.join("missing.oel")]), USAGE_ERROR);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fmt_exit_codes() {
+        let (dir, paths) = write(
+            "fmt",
+            &[("formatted.oel", "1 + 2\n"), ("unformatted.oel", "1+2\n")],
+        );
+        let fmt = |path: &PathBuf, check| code(run_fmt(std::slice::from_ref(path), check));
+        assert_eq!(fmt(&paths[0], true), 0);
+        assert_eq!(fmt(&paths[1], true), UNFORMATTED);
+        assert_eq!(std::fs::read_to_string(&paths[1]).unwrap(), "1+2\n");
+        assert_eq!(fmt(&paths[1], false), 0);
+        assert_eq!(std::fs::read_to_string(&paths[1]).unwrap(), "1 + 2\n");
+        assert_eq!(fmt(&paths[1], true), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn eval_exit_codes() {
+        let (dir, paths) = write(
+            "eval",
+            &[
+                ("sum.oel", "1 + 2\n"),
+                ("invalid.oel", "true + 1\n"),
+                ("context.json", "[]"),
+                ("rules.oel", "rule \"a\" = 1\nrule \"b\" = true + 1\n"),
+            ],
+        );
+        let eval = |path: &PathBuf, rule: Option<&str>, context: Option<&PathBuf>| {
+            code(run_eval(
+                path,
+                rule,
+                context.map(PathBuf::as_path),
+                None,
+                None,
+                None,
+                None,
+            ))
+        };
+        assert_eq!(eval(&paths[0], None, None), 0);
+        assert_eq!(eval(&paths[1], None, None), SEMANTIC_ERROR);
+        assert_eq!(eval(&paths[0], None, Some(&paths[2])), USAGE_ERROR);
+        assert_eq!(eval(&dir.join("missing.oel"), None, None), USAGE_ERROR);
+        assert_eq!(eval(&paths[3], None, None), SEMANTIC_ERROR);
+        assert_eq!(eval(&paths[3], Some("a"), None), 0);
+        assert_eq!(eval(&paths[3], Some("c"), None), USAGE_ERROR);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! Native implementations of Okta's built-in functions.
+//!
+//! Each namespace lives in its own module and exposes a `FUNCTIONS` table.
+//! The [`Registry`] collects them and is shared by the evaluator and the
+//! language server, so both agree on which functions exist.
+//!
+//! Identity Engine expressions can also call methods on values, such as
+//! `user.profile.login.substringAfter('@')`. Those are `METHODS` tables
+//! named after the type of the value, `String.substringAfter`.
+
+pub mod arrays;
+pub mod convert;
+pub mod groups;
+pub mod iso3166;
+pub mod string;
+pub mod time;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+use crate::directory::Directory;
+use crate::eval::{EvalError, Result};
+use crate::oel::{Span, Spanned, Value};
+
+pub type NativeFn = fn(&Call) -> Result<Value>;
+
+pub struct Builtin {
+    pub name: &'static str,
+    /// Parameter and return types, e.g. `(string: String) -> Integer`, with
+    /// overloads separated by ` | `. See
+    /// [`Signature`](crate::typecheck::Signature) for the syntax.
+    ///
+    /// Methods are passed the value they are called on as their first
+    /// argument, but it is not part of their signature.
+    pub signature: &'static str,
+    pub func: NativeFn,
+}
+
+/// Source of the current time for `Time.now`, injectable so that evaluation
+/// can be made deterministic.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that is stopped at a given instant.
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+pub struct Registry {
+    functions: BTreeMap<&'static str, &'static Builtin>,
+    methods: BTreeMap<&'static str, &'static Builtin>,
+    clock: Arc<dyn Clock>,
+}
+
+impl std::fmt::Debug for Registry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Registry")
+            .field("functions", &self.functions.keys())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Self::new(Arc::new(SystemClock))
+    }
+}
+
+impl Registry {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        let functions = [
+            string::FUNCTIONS,
+            arrays::FUNCTIONS,
+            convert::FUNCTIONS,
+            time::FUNCTIONS,
+            iso3166::FUNCTIONS,
+            groups::FUNCTIONS,
+        ]
+        .iter()
+        .flat_map(|table| table.iter())
+        .map(|builtin| (builtin.name, builtin))
+        .collect();
+        let methods = [string::METHODS, groups::METHODS]
+            .iter()
+            .flat_map(|table| table.iter())
+            .map(|builtin| (builtin.name, builtin))
+            .collect();
+        Registry {
+            functions,
+            methods,
+            clock,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'static Builtin> {
+        self.functions.get(name).copied()
+    }
+
+    /// Every function, ordered by name.
+    pub fn functions(&self) -> impl Iterator<Item = &'static Builtin> + '_ {
+        self.functions.values().copied()
+    }
+
+    /// The method `name` of values of type `receiver`, e.g. `String` or
+    /// `User`.
+    pub fn method(&self, receiver: &str, name: &str) -> Option<&'static Builtin> {
+        self.methods
+            .get(format!("{}.{}", receiver, name).as_str())
+            .copied()
+    }
+
+    /// The methods of values of type `receiver`, ordered by name.
+    pub fn methods<'a>(&'a self, receiver: &'a str) -> impl Iterator<Item = &'static Builtin> + 'a {
+        self.methods.values().copied().filter(move |builtin| {
+            builtin
+                .name
+                .strip_prefix(receiver)
+                .map_or(false, |name| name.starts_with('.'))
+        })
+    }
+
+    /// A method called `name` on values of any type, for receivers whose type
+    /// is not known.
+    pub fn any_method(&self, name: &str) -> Option<&'static Builtin> {
+        self.methods
+            .values()
+            .copied()
+            .find(|builtin| builtin.name.split_once('.').map(|(_, method)| method) == Some(name))
+    }
+
+    /// The namespaces (`String`, `Arrays`, ...) that functions are grouped under.
+    pub fn namespaces(&self) -> Vec<&'static str> {
+        let mut namespaces = self
+            .functions
+            .keys()
+            .filter_map(|name| name.split_once('.').map(|(namespace, _)| namespace))
+            .collect::<Vec<_>>();
+        namespaces.dedup();
+        namespaces
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        self.clock.as_ref()
+    }
+}
+
+/// A call to a built-in, with its arguments already evaluated.
+pub struct Call<'a> {
+    pub name: &'a str,
+    pub span: &'a Span,
+    pub args: &'a [Spanned<Value>],
+    pub clock: &'a dyn Clock,
+    pub directory: &'a dyn Directory,
+    /// Id or login of the user the expression is evaluated for.
+    pub user: Option<&'a str>,
+}
+
+impl<'a> Call<'a> {
+    /// Checks the number of arguments. `max` is `None` for varargs functions.
+    pub fn arity(&self, min: usize, max: Option<usize>) -> Result<()> {
+        if self.args.len() < min {
+            return Err(EvalError::new(
+                self.span,
+                format!(
+                    "{} expects at least {} argument{}, found {}",
+                    self.name,
+                    min,
+                    if min == 1 { "" } else { "s" },
+                    self.args.len()
+                ),
+            ));
+        }
+        match max {
+            Some(max) if self.args.len() > max => Err(EvalError::new(
+                &self.args[max].1,
+                format!(
+                    "{} expects at most {} argument{}, found {}",
+                    self.name,
+                    max,
+                    if max == 1 { "" } else { "s" },
+                    self.args.len()
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn value(&self, index: usize) -> &Value {
+        &self.args[index].0
+    }
+
+    pub fn error(&self, index: usize, msg: impl Into<String>) -> EvalError {
+        EvalError::new(&self.args[index].1, msg)
+    }
+
+    fn mismatch(&self, index: usize, expected: &str) -> EvalError {
+        self.error(
+            index,
+            format!(
+                "argument {} of {} must be {}, found {}",
+                index + 1,
+                self.name,
+                expected,
+                self.value(index).type_name()
+            ),
+        )
+    }
+
+    /// A String argument; `None` when it is null.
+    pub fn string(&self, index: usize) -> Result<Option<&str>> {
+        match self.value(index) {
+            Value::Null => Ok(None),
+            Value::Str(s) => Ok(Some(s)),
+            _ => Err(self.mismatch(index, "a String")),
+        }
+    }
+
+    /// An Integer argument; `None` when it is null.
+    pub fn integer(&self, index: usize) -> Result<Option<i64>> {
+        match self.value(index) {
+            Value::Null => Ok(None),
+            Value::Int(i) => Ok(Some(*i)),
+            _ => Err(self.mismatch(index, "an Integer")),
+        }
+    }
+
+    /// An Array argument; `None` when it is null.
+    pub fn array(&self, index: usize) -> Result<Option<&[Value]>> {
+        match self.value(index) {
+            Value::Null => Ok(None),
+            Value::List(items) => Ok(Some(items)),
+            _ => Err(self.mismatch(index, "an Array")),
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! The `Arrays` namespace. Arrays are immutable, so `add`, `remove` and
+//! `clear` return a new array rather than changing their argument.
+
+use super::{Builtin, Call};
+use crate::eval::{values_equal, Result};
+use crate::oel::Value;
+
+pub static FUNCTIONS: &[Builtin] = &[
+    Builtin {
+        name: "Arrays.add",
+        signature: "(array: Array<T>, value: T) -> Array<T>",
+        func: add,
+    },
+    Builtin {
+        name: "Arrays.remove",
+        signature: "(array: Array<T>, value: T) -> Array<T>",
+        func: remove,
+    },
+    Builtin {
+        name: "Arrays.clear",
+        signature: "(array: Array<T>) -> Array<T>",
+        func: clear,
+    },
+    Builtin {
+        name: "Arrays.get",
+        signature: "(array: Array<T>, position: Integer) -> T",
+        func: get,
+    },
+    Builtin {
+        name: "Arrays.flatten",
+        signature: "(values: Any...) -> Array<Any>",
+        func: flatten,
+    },
+    Builtin {
+        name: "Arrays.contains",
+        signature: "(array: Array<String>, value: String) -> Boolean | (array: Array<Integer>, value: Integer) -> Boolean",
+        func: contains,
+    },
+    Builtin {
+        name: "Arrays.size",
+        signature: "(array: Array<Any>) -> Integer",
+        func: size,
+    },
+    Builtin {
+        name: "Arrays.isEmpty",
+        signature: "(array: Array<Any>) -> Boolean",
+        func: is_empty,
+    },
+    Builtin {
+        name: "Arrays.toCsvString",
+        signature: "(array: Array<Any>) -> String",
+        func: to_csv_string,
+    },
+];
+
+fn add(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let mut items = call.array(0)?.unwrap_or_default().to_vec();
+    items.push(call.value(1).clone());
+    Ok(Value::List(items))
+}
+
+/// Removes the first occurrence of the value, like `List.remove(Object)`.
+fn remove(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let mut items = match call.array(0)? {
+        Some(items) => items.to_vec(),
+        None => return Ok(Value::Null),
+    };
+    if let Some(at) = items
+        .iter()
+        .position(|item| values_equal(item, call.value(1)))
+    {
+        items.remove(at);
+    }
+    Ok(Value::List(items))
+}
+
+fn clear(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    call.array(0)?;
+    Ok(Value::List(Vec::new()))
+}
+
+/// Out of range positions give null rather than failing.
+fn get(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let items = call.array(0)?.unwrap_or_default();
+    let item = call
+        .integer(1)?
+        .and_then(|index| usize::try_from(index).ok())
+        .and_then(|index| items.get(index).cloned());
+    Ok(item.unwrap_or(Value::Null))
+}
+
+fn flatten_into(value: &Value, out: &mut Vec<Value>) {
+    match value {
+        Value::List(items) => items.iter().for_each(|item| flatten_into(item, out)),
+        other => out.push(other.clone()),
+    }
+}
+
+/// `Arrays.flatten(10, {20}, {{30, 40}})` is `{10, 20, 30, 40}`.
+fn flatten(call: &Call) -> Result<Value> {
+    let mut out = Vec::new();
+    call.args
+        .iter()
+        .for_each(|(value, _)| flatten_into(value, &mut out));
+    Ok(Value::List(out))
+}
+
+fn contains(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let items = call.array(0)?.unwrap_or_default();
+    Ok(Value::Bool(
+        items.iter().any(|item| values_equal(item, call.value(1))),
+    ))
+}
+
+fn size(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(Value::Int(
+        call.array(0)?.map_or(0, |items| items.len()) as i64
+    ))
+}
+
+fn is_empty(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(Value::Bool(
+        call.array(0)?.map_or(true, |items| items.is_empty()),
+    ))
+}
+
+fn to_csv_string(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(match call.array(0)? {
+        Some(items) => Value::Str(
+            items
+                .iter()
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(","),
+        ),
+        None => Value::Null,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::builtins::{NativeFn, SystemClock};
+    use crate::directory::NoDirectory;
+    use crate::oel::Span;
+
+    fn call(func: NativeFn, args: &[Value]) -> Result<Value> {
+        let span = Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 1),
+        };
+        let args = args
+            .iter()
+            .map(|arg| (arg.clone(), span.clone()))
+            .collect::<Vec<_>>();
+        func(&Call {
+            name: "Arrays.test",
+            span: &span,
+            args: &args,
+            clock: &SystemClock,
+            directory: &NoDirectory,
+            user: None,
+        })
+    }
+
+    fn list(items: &[i64]) -> Value {
+        Value::List(items.iter().copied().map(Value::Int).collect())
+    }
+
+    #[test]
+    fn add_remove_and_clear() {
+        assert_eq!(
+            call(add, &[list(&[1, 2]), Value::Int(1)]).unwrap(),
+            list(&[1, 2, 1])
+        );
+        assert_eq!(
+            call(add, &[Value::Null, Value::Int(1)]).unwrap(),
+            list(&[1])
+        );
+        assert_eq!(
+            call(remove, &[list(&[1, 2, 1]), Value::Num(1.0)]).unwrap(),
+            list(&[2, 1])
+        );
+        assert_eq!(
+            call(remove, &[list(&[1]), Value::Int(3)]).unwrap(),
+            list(&[1])
+        );
+        assert_eq!(
+            call(remove, &[Value::Null, Value::Int(3)]).unwrap(),
+            Value::Null
+        );
+        assert_eq!(call(clear, &[list(&[1])]).unwrap(), list(&[]));
+        assert_eq!(
+            call(clear, &[Value::Int(1)]).unwrap_err().msg,
+            "argument 1 of Arrays.test must be an Array, found Integer"
+        );
+    }
+
+    #[test]
+    fn get_is_null_out_of_range() {
+        let get = |index: i64| call(get, &[list(&[10, 20]), Value::Int(index)]).unwrap();
+        assert_eq!(get(1), Value::Int(20));
+        assert_eq!(get(2), Value::Null);
+        assert_eq!(get(-1), Value::Null);
+    }
+
+    #[test]
+    fn flatten_nested_arrays() {
+        let nested = Value::List(vec![list(&[30]), list(&[40])]);
+        assert_eq!(
+            call(flatten, &[Value::Int(10), list(&[20]), nested]).unwrap(),
+            list(&[10, 20, 30, 40])
+        );
+        assert_eq!(call(flatten, &[]).unwrap(), list(&[]));
+    }
+
+    #[test]
+    fn queries() {
+        assert_eq!(
+            call(contains, &[list(&[1, 2]), Value::Num(2.0)]).unwrap(),
+            Value::Bool(true)
+        );
+        assert_eq!(
+            call(contains, &[Value::Null, Value::Int(2)]).unwrap(),
+            Value::Bool(false)
+        );
+        assert_eq!(call(size, &[list(&[1, 2])]).unwrap(), Value::Int(2));
+        assert_eq!(call(size, &[Value::Null]).unwrap(), Value::Int(0));
+        assert_eq!(call(is_empty, &[Value::Null]).unwrap(), Value::Bool(true));
+        assert_eq!(call(is_empty, &[list(&[1])]).unwrap(), Value::Bool(false));
+        let mixed = Value::List(vec![
+            Value::Int(1),
+            Value::Str("a".to_string()),
+            Value::Null,
+        ]);
+        assert_eq!(
+            call(to_csv_string, &[mixed]).unwrap(),
+            Value::Str("1,a,null".to_string())
+        );
+        assert_eq!(call(to_csv_string, &[Value::Null]).unwrap(), Value::Null);
+        assert!(call(size, &[]).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+//! The `Convert` namespace.
+
+use super::{Builtin, Call};
+use crate::eval::Result;
+use crate::oel::Value;
+
+pub static FUNCTIONS: &[Builtin] = &[
+    Builtin {
+        name: "Convert.toInt",
+        signature: "(value: Any) -> Integer",
+        func: to_int,
+    },
+    Builtin {
+        name: "Convert.toNum",
+        signature: "(value: Any) -> Number",
+        func: to_num,
+    },
+];
+
+/// Numbers are rounded half up, like Java's `Math.round`.
+fn to_int(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let number = match call.value(0) {
+        Value::Null => return Ok(Value::Null),
+        Value::Int(i) => return Ok(Value::Int(*i)),
+        Value::Num(n) => *n,
+        Value::Str(s) => match s.trim().parse::<i64>() {
+            Ok(i) => return Ok(Value::Int(i)),
+            Err(_) => s
+                .trim()
+                .parse::<f64>()
+                .map_err(|_| call.error(0, format!("'{}' is not a number", s)))?,
+        },
+        other => {
+            return Err(call.error(
+                0,
+                format!("cannot convert {} to an Integer", other.type_name()),
+            ))
+        }
+    };
+    let rounded = (number + 0.5).floor();
+    if !rounded.is_finite() || rounded < i64::MIN as f64 || rounded >= i64::MAX as f64 {
+        return Err(call.error(0, format!("{} does not fit in an Integer", number)));
+    }
+    Ok(Value::Int(rounded as i64))
+}
+
+fn to_num(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    match call.value(0) {
+        Value::Null => Ok(Value::Null),
+        Value::Int(i) => Ok(Value::Num(*i as f64)),
+        Value::Num(n) => Ok(Value::Num(*n)),
+        Value::Str(s) => s
+            .trim()
+            .parse::<f64>()
+            .map(Value::Num)
+            .map_err(|_| call.error(0, format!("'{}' is not a number", s))),
+        other => Err(call.error(
+            0,
+            format!("cannot convert {} to a Number", other.type_name()),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::builtins::{NativeFn, SystemClock};
+    use crate::directory::NoDirectory;
+    use crate::oel::Span;
+
+    fn call(func: NativeFn, args: &[Value]) -> Result<Value> {
+        let span = Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 1),
+        };
+        let args = args
+            .iter()
+            .map(|arg| (arg.clone(), span.clone()))
+            .collect::<Vec<_>>();
+        func(&Call {
+            name: "Convert.test",
+            span: &span,
+            args: &args,
+            clock: &SystemClock,
+            directory: &NoDirectory,
+            user: None,
+        })
+    }
+
+    fn s(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    #[test]
+    fn to_int_rounds_half_up() {
+        let to_int = |value: Value| call(to_int, &[value]);
+        assert_eq!(to_int(Value::Num(2.5)).unwrap(), Value::Int(3));
+        assert_eq!(to_int(Value::Num(-2.5)).unwrap(), Value::Int(-2));
+        assert_eq!(to_int(Value::Num(-2.6)).unwrap(), Value::Int(-3));
+        assert_eq!(to_int(s(" 42 ")).unwrap(), Value::Int(42));
+        assert_eq!(to_int(s("4.6")).unwrap(), Value::Int(5));
+        assert_eq!(to_int(Value::Null).unwrap(), Value::Null);
+        assert_eq!(to_int(s("four")).unwrap_err().msg, "'four' is not a number");
+        assert_eq!(
+            to_int(Value::Bool(true)).unwrap_err().msg,
+            "cannot convert Boolean to an Integer"
+        );
+    }
+
+    #[test]
+    fn to_int_rejects_numbers_out_of_range() {
+        let to_int = |value: f64| call(to_int, &[Value::Num(value)]);
+        // 2^63 is the first f64 past i64::MAX, and would saturate.
+        let err = to_int(9_223_372_036_854_775_808.0).unwrap_err();
+        assert!(
+            err.msg.ends_with("does not fit in an Integer"),
+            "{}",
+            err.msg
+        );
+        assert!(to_int(f64::NAN).is_err());
+        assert!(to_int(f64::NEG_INFINITY).is_err());
+        assert_eq!(
+            to_int(-9_223_372_036_854_775_808.0).unwrap(),
+            Value::Int(i64::MIN)
+        );
+        assert_eq!(
+            to_int(9_223_372_036_854_774_784.0).unwrap(),
+            Value::Int(9_223_372_036_854_774_784)
+        );
+    }
+
+    #[test]
+    fn to_num_parses_strings() {
+        assert_eq!(call(to_num, &[Value::Int(2)]).unwrap(), Value::Num(2.0));
+        assert_eq!(call(to_num, &[s("1e3")]).unwrap(), Value::Num(1000.0));
+        assert_eq!(call(to_num, &[Value::Null]).unwrap(), Value::Null);
+        assert_eq!(
+            call(to_num, &[Value::List(Vec::new())]).unwrap_err().msg,
+            "cannot convert Array to a Number"
+        );
+    }
+}
@@ -0,0 +1,655 @@
+//! Group membership and manager/assistant lookups, answered by the
+//! [`Directory`](crate::directory::Directory) the expression is evaluated with.
+
+use regex::Regex;
+
+use super::{Builtin, Call};
+use crate::directory::{DirectoryUser, Group};
+use crate::eval::Result;
+use crate::oel::Value;
+
+pub static FUNCTIONS: &[Builtin] = &[
+    Builtin {
+        name: "isMemberOfGroupName",
+        signature: "(groupName: String) -> Boolean",
+        func: is_member_of_group_name,
+    },
+    Builtin {
+        name: "isMemberOfGroup",
+        signature: "(groupId: String) -> Boolean",
+        func: is_member_of_group,
+    },
+    Builtin {
+        name: "isMemberOfAnyGroup",
+        signature: "(groupIds: String...) -> Boolean",
+        func: is_member_of_any_group,
+    },
+    Builtin {
+        name: "isMemberOfGroupNameStartsWith",
+        signature: "(prefix: String) -> Boolean",
+        func: is_member_of_group_name_starts_with,
+    },
+    Builtin {
+        name: "isMemberOfGroupNameContains",
+        signature: "(substring: String) -> Boolean",
+        func: is_member_of_group_name_contains,
+    },
+    Builtin {
+        name: "isMemberOfGroupNameRegex",
+        signature: "(regex: String) -> Boolean",
+        func: is_member_of_group_name_regex,
+    },
+    Builtin {
+        name: "getFilteredGroups",
+        signature: "(groupAllowlist: Array<String>, groupExpression: String, limit: Integer) -> Array<String>",
+        func: get_filtered_groups,
+    },
+    Builtin {
+        name: "Groups.contains",
+        signature: "(app: String, pattern: String, limit: Integer) -> Array<String>",
+        func: groups_contains,
+    },
+    Builtin {
+        name: "Groups.startsWith",
+        signature: "(app: String, pattern: String, limit: Integer) -> Array<String>",
+        func: groups_starts_with,
+    },
+    Builtin {
+        name: "Groups.endsWith",
+        signature: "(app: String, pattern: String, limit: Integer) -> Array<String>",
+        func: groups_ends_with,
+    },
+    Builtin {
+        name: "getManagerUser",
+        signature: "(managerSource: String) -> Object",
+        func: get_manager_user,
+    },
+    Builtin {
+        name: "getManagerAppUser",
+        signature: "(managerSource: String, attributeSource: String) -> Object",
+        func: get_manager_app_user,
+    },
+    Builtin {
+        name: "getAssistantUser",
+        signature: "(assistantSource: String) -> Object",
+        func: get_assistant_user,
+    },
+    Builtin {
+        name: "getAssistantAppUser",
+        signature: "(assistantSource: String, attributeSource: String) -> Object",
+        func: get_assistant_app_user,
+    },
+];
+
+/// Methods of `user` in Identity Engine expressions.
+pub static METHODS: &[Builtin] = &[
+    Builtin {
+        name: "User.getGroups",
+        signature: "(filter: Object) -> Array<Object>",
+        func: get_groups,
+    },
+    Builtin {
+        name: "User.isMemberOf",
+        signature: "(filter: Object) -> Boolean",
+        func: is_member_of,
+    },
+];
+
+/// Okta caps the number of groups returned by a single call.
+const MAX_GROUPS: i64 = 100;
+
+fn user_groups(call: &Call) -> Vec<Group> {
+    call.user
+        .map(|user| call.directory.groups_of(user))
+        .unwrap_or_default()
+}
+
+fn any_group(call: &Call, predicate: impl Fn(&Group) -> bool) -> Value {
+    Value::Bool(user_groups(call).iter().any(predicate))
+}
+
+fn is_member_of_group_name(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let name = call.string(0)?;
+    Ok(any_group(call, |group| Some(group.name.as_str()) == name))
+}
+
+fn is_member_of_group(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let id = call.string(0)?;
+    Ok(any_group(call, |group| Some(group.id.as_str()) == id))
+}
+
+/// `isMemberOfAnyGroup(groupId1, groupId2, ...)`.
+fn is_member_of_any_group(call: &Call) -> Result<Value> {
+    call.arity(1, None)?;
+    let ids = (0..call.args.len())
+        .map(|i| call.string(i))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(any_group(call, |group| {
+        ids.contains(&Some(group.id.as_str()))
+    }))
+}
+
+fn is_member_of_group_name_starts_with(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(match call.string(0)? {
+        Some(prefix) => any_group(call, |group| group.name.starts_with(prefix)),
+        None => Value::Bool(false),
+    })
+}
+
+fn is_member_of_group_name_contains(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(match call.string(0)? {
+        Some(needle) => any_group(call, |group| group.name.contains(needle)),
+        None => Value::Bool(false),
+    })
+}
+
+/// The regex must match the whole group name, as with Java's `String.matches`.
+fn is_member_of_group_name_regex(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let pattern = match call.string(0)? {
+        Some(pattern) => pattern,
+        None => return Ok(Value::Bool(false)),
+    };
+    let regex = Regex::new(&format!("^(?:{})$", pattern))
+        .map_err(|err| call.error(0, format!("invalid regular expression: {}", err)))?;
+    Ok(any_group(call, |group| regex.is_match(&group.name)))
+}
+
+fn limit(call: &Call, index: usize) -> Result<usize> {
+    match call.integer(index)? {
+        Some(limit) if (1..=MAX_GROUPS).contains(&limit) => Ok(limit as usize),
+        _ => Err(call.error(index, format!("limit must be between 1 and {}", MAX_GROUPS))),
+    }
+}
+
+/// `getFilteredGroups(groupAllowlist, groupExpression, limit)` returns
+/// `groupExpression` (e.g. `"group.name"`) for each of the user's groups whose
+/// id is in the allowlist.
+fn get_filtered_groups(call: &Call) -> Result<Value> {
+    call.arity(3, Some(3))?;
+    let allowlist = call.array(0)?.unwrap_or_default();
+    let attribute = match call.string(1)?.map(|expr| expr.trim()) {
+        Some(expr) => expr
+            .strip_prefix("group.")
+            .filter(|attribute| matches!(*attribute, "id" | "name" | "description" | "source"))
+            .ok_or_else(|| {
+                call.error(
+                    1,
+                    "groupExpression must be one of group.id, group.name, group.description or group.source",
+                )
+            })?,
+        None => return Ok(Value::List(Vec::new())),
+    };
+    let limit = limit(call, 2)?;
+    let groups = user_groups(call)
+        .into_iter()
+        .filter(|group| {
+            allowlist
+                .iter()
+                .any(|allowed| matches!(allowed, Value::Str(id) if id == &group.id))
+        })
+        .take(limit)
+        .map(|group| match group.to_value() {
+            Value::Object(mut fields) => fields.remove(attribute).unwrap_or(Value::Null),
+            other => other,
+        })
+        .collect();
+    Ok(Value::List(groups))
+}
+
+/// The attributes an Identity Engine group filter can match on.
+const FILTER_KEYS: &[&str] = &[
+    "group.id",
+    "group.type",
+    "group.profile.name",
+    "group.profile.description",
+    "group.source.id",
+];
+
+fn group_field(group: &Group, key: &str) -> Value {
+    match key {
+        "group.id" => Value::Str(group.id.clone()),
+        "group.type" if group.source == "OKTA" => Value::Str("OKTA_GROUP".to_string()),
+        "group.type" => Value::Str("APP_GROUP".to_string()),
+        "group.profile.name" => Value::Str(group.name.clone()),
+        "group.profile.description" => group.description.clone().map_or(Value::Null, Value::Str),
+        "group.source.id" => Value::Str(group.source.clone()),
+        _ => Value::Null,
+    }
+}
+
+/// The user's groups matching a filter such as
+/// `{'group.profile.name': 'Admins'}`. An Array value matches any of its
+/// elements.
+fn filtered_groups(call: &Call) -> Result<Vec<Group>> {
+    call.arity(2, Some(2))?;
+    let filter = match call.value(1) {
+        Value::Null => return Ok(user_groups(call)),
+        Value::Object(filter) => filter,
+        other => {
+            return Err(call.error(
+                1,
+                format!(
+                    "expected a group filter Object, found {}",
+                    other.type_name()
+                ),
+            ))
+        }
+    };
+    if let Some(key) = filter
+        .keys()
+        .find(|key| !FILTER_KEYS.contains(&key.as_str()))
+    {
+        return Err(call.error(
+            1,
+            format!(
+                "cannot filter groups on `{}`, expected one of {}",
+                key,
+                FILTER_KEYS.join(", ")
+            ),
+        ));
+    }
+    Ok(user_groups(call)
+        .into_iter()
+        .filter(|group| {
+            filter.iter().all(|(key, expected)| {
+                let actual = group_field(group, key);
+                match expected {
+                    Value::List(values) => values.contains(&actual),
+                    value => value == &actual,
+                }
+            })
+        })
+        .collect())
+}
+
+/// `user.getGroups(filter)`.
+fn get_groups(call: &Call) -> Result<Value> {
+    Ok(Value::List(
+        filtered_groups(call)?.iter().map(Group::to_value).collect(),
+    ))
+}
+
+/// `user.isMemberOf(filter)`.
+fn is_member_of(call: &Call) -> Result<Value> {
+    Ok(Value::Bool(!filtered_groups(call)?.is_empty()))
+}
+
+/// `Groups.contains(app, pattern, limit)` and friends return the names of the
+/// user's groups from `app` (`OKTA`, an app type or an app instance id)
+/// whose name matches `pattern`.
+fn matching_groups(call: &Call, matches: fn(&str, &str) -> bool) -> Result<Value> {
+    call.arity(3, Some(3))?;
+    let (app, pattern) = match (call.string(0)?, call.string(1)?) {
+        (Some(app), Some(pattern)) => (app, pattern),
+        _ => return Ok(Value::List(Vec::new())),
+    };
+    let limit = limit(call, 2)?;
+    let names = user_groups(call)
+        .into_iter()
+        .filter(|group| group.source.eq_ignore_ascii_case(app) && matches(&group.name, pattern))
+        .take(limit)
+        .map(|group| Value::Str(group.name))
+        .collect();
+    Ok(Value::List(names))
+}
+
+fn groups_contains(call: &Call) -> Result<Value> {
+    matching_groups(call, |name, pattern| name.contains(pattern))
+}
+
+fn groups_starts_with(call: &Call) -> Result<Value> {
+    matching_groups(call, |name, pattern| name.starts_with(pattern))
+}
+
+fn groups_ends_with(call: &Call) -> Result<Value> {
+    matching_groups(call, |name, pattern| name.ends_with(pattern))
+}
+
+type Lookup = fn(&Call, &str, &str) -> Option<DirectoryUser>;
+
+fn manager(call: &Call, user: &str, source: &str) -> Option<DirectoryUser> {
+    call.directory.manager_of(user, source)
+}
+
+fn assistant(call: &Call, user: &str, source: &str) -> Option<DirectoryUser> {
+    call.directory.assistant_of(user, source)
+}
+
+/// The related user's Okta profile, or with `attributeSource` their profile
+/// in that app.
+fn related_user(call: &Call, lookup: Lookup, app_user: bool) -> Result<Value> {
+    let args = if app_user { 2 } else { 1 };
+    call.arity(args, Some(args))?;
+    let source = match call.string(0)? {
+        Some(source) => source,
+        None => return Ok(Value::Null),
+    };
+    let related = match call.user.and_then(|user| lookup(call, user, source)) {
+        Some(related) => related,
+        None => return Ok(Value::Null),
+    };
+    if app_user {
+        Ok(call
+            .string(1)?
+            .map_or(Value::Null, |app| related.app_profile_value(app)))
+    } else {
+        Ok(related.profile_value())
+    }
+}
+
+fn get_manager_user(call: &Call) -> Result<Value> {
+    related_user(call, manager, false)
+}
+
+fn get_manager_app_user(call: &Call) -> Result<Value> {
+    related_user(call, manager, true)
+}
+
+fn get_assistant_user(call: &Call) -> Result<Value> {
+    related_user(call, assistant, false)
+}
+
+fn get_assistant_app_user(call: &Call) -> Result<Value> {
+    related_user(call, assistant, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::builtins::{FixedClock, NativeFn};
+    use crate::directory::InMemoryDirectory;
+    use crate::oel::Span;
+
+    const DIRECTORY: &str = "\
+groups:
+  - { id: 00g1, name: Engineering }
+  - { id: 00g2, name: AD Admins, source: active_directory, description: Domain admins }
+  - { id: 00g3, name: Eng Leads }
+users:
+  - id: 00u1
+    profile: { login: jo@example.com }
+    groups: [00g1, AD Admins, 00g3]
+    managers: { active_directory: 00u2 }
+    assistants: { OKTA: 00u2 }
+  - id: 00u2
+    profile: { login: sam@example.com }
+    appProfiles: { active_directory: { sAMAccountName: sam } }
+";
+
+    /// Calls `func` as `user` would in `directory`.
+    fn call_as(
+        directory: &InMemoryDirectory,
+        user: Option<&str>,
+        func: NativeFn,
+        args: &[Value],
+    ) -> Result<Value> {
+        let span = Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 1),
+        };
+        let args = args
+            .iter()
+            .map(|arg| (arg.clone(), span.clone()))
+            .collect::<Vec<_>>();
+        func(&Call {
+            name: "Groups.test",
+            span: &span,
+            args: &args,
+            clock: &FixedClock(Utc.with_ymd_and_hms(2024, 3, 5, 14, 7, 9).unwrap()),
+            directory,
+            user,
+        })
+    }
+
+    fn call(func: NativeFn, args: &[Value]) -> Result<Value> {
+        let directory = InMemoryDirectory::from_yaml(DIRECTORY).unwrap();
+        call_as(&directory, Some("jo@example.com"), func, args)
+    }
+
+    fn s(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    fn strings(values: &[&str]) -> Value {
+        Value::List(values.iter().map(|value| s(value)).collect())
+    }
+
+    fn filter(entries: &[(&str, Value)]) -> Value {
+        Value::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn membership() {
+        let yes = Ok(Value::Bool(true));
+        let no = Ok(Value::Bool(false));
+        assert_eq!(call(is_member_of_group_name, &[s("Engineering")]), yes);
+        assert_eq!(call(is_member_of_group_name, &[s("00g1")]), no);
+        assert_eq!(call(is_member_of_group, &[s("00g2")]), yes);
+        assert_eq!(call(is_member_of_any_group, &[s("00g9"), s("00g3")]), yes);
+        assert_eq!(call(is_member_of_any_group, &[s("00g9")]), no);
+        assert_eq!(call(is_member_of_group_name_starts_with, &[s("Eng")]), yes);
+        assert_eq!(call(is_member_of_group_name_contains, &[s("Admin")]), yes);
+        assert_eq!(call(is_member_of_group_name_contains, &[Value::Null]), no);
+        // The regex must match the whole name.
+        assert_eq!(call(is_member_of_group_name_regex, &[s("Eng.*")]), yes);
+        assert_eq!(call(is_member_of_group_name_regex, &[s("Admins")]), no);
+        assert!(call(is_member_of_group_name_regex, &[s("(")]).is_err());
+
+        let directory = InMemoryDirectory::from_yaml(DIRECTORY).unwrap();
+        let anonymous = call_as(
+            &directory,
+            None,
+            is_member_of_group_name,
+            &[s("Engineering")],
+        );
+        assert_eq!(anonymous, no);
+        let sam = call_as(&directory, Some("00u2"), is_member_of_group, &[s("00g1")]);
+        assert_eq!(sam, no);
+    }
+
+    #[test]
+    fn filtered_and_matching_groups() {
+        assert_eq!(
+            call(
+                get_filtered_groups,
+                &[strings(&["00g2", "00g3"]), s("group.name"), Value::Int(10)]
+            ),
+            Ok(strings(&["AD Admins", "Eng Leads"]))
+        );
+        assert_eq!(
+            call(
+                get_filtered_groups,
+                &[
+                    strings(&["00g2", "00g3"]),
+                    s(" group.description "),
+                    Value::Int(1)
+                ]
+            ),
+            Ok(Value::List(vec![s("Domain admins")]))
+        );
+        assert!(call(
+            get_filtered_groups,
+            &[strings(&["00g1"]), s("group.profile.name"), Value::Int(1)]
+        )
+        .is_err());
+
+        assert_eq!(
+            call(groups_starts_with, &[s("okta"), s("Eng"), Value::Int(10)]),
+            Ok(strings(&["Engineering", "Eng Leads"]))
+        );
+        assert_eq!(
+            call(
+                groups_contains,
+                &[s("active_directory"), s("Admin"), Value::Int(10)]
+            ),
+            Ok(strings(&["AD Admins"]))
+        );
+        assert_eq!(
+            call(groups_ends_with, &[s("OKTA"), s("Leads"), Value::Int(10)]),
+            Ok(strings(&["Eng Leads"]))
+        );
+        assert_eq!(
+            call(groups_ends_with, &[Value::Null, s("Leads"), Value::Int(10)]),
+            Ok(Value::List(Vec::new()))
+        );
+    }
+
+    #[test]
+    fn limits_are_capped_at_max_groups() {
+        let mut directory = InMemoryDirectory::default();
+        let mut user = DirectoryUser {
+            id: "00u1".to_string(),
+            ..Default::default()
+        };
+        for i in 0..MAX_GROUPS + 20 {
+            directory.groups.push(Group {
+                id: format!("00g{}", i),
+                name: format!("Group {}", i),
+                description: None,
+                source: "OKTA".to_string(),
+            });
+            user.groups.push(format!("00g{}", i));
+        }
+        directory.users.push(user);
+        let all = Value::List(directory.groups.iter().map(|group| s(&group.id)).collect());
+        let starts_with = |limit| {
+            call_as(
+                &directory,
+                Some("00u1"),
+                groups_starts_with,
+                &[s("OKTA"), s("Group"), Value::Int(limit)],
+            )
+        };
+        let filtered = |limit| {
+            call_as(
+                &directory,
+                Some("00u1"),
+                get_filtered_groups,
+                &[all.clone(), s("group.id"), Value::Int(limit)],
+            )
+        };
+        let len = |value: Result<Value>| match value {
+            Ok(Value::List(values)) => values.len(),
+            other => panic!("expected a list, found {:?}", other),
+        };
+        assert_eq!(len(starts_with(MAX_GROUPS)), 100);
+        assert_eq!(len(filtered(MAX_GROUPS)), 100);
+        assert_eq!(len(starts_with(3)), 3);
+        for limit in [0, MAX_GROUPS + 1] {
+            assert_eq!(
+                starts_with(limit).unwrap_err().msg,
+                "limit must be between 1 and 100"
+            );
+            assert!(filtered(limit).is_err());
+        }
+    }
+
+    #[test]
+    fn identity_engine_group_filters() {
+        let user = Value::Null;
+        let names = |value: Result<Value>| match value {
+            Ok(Value::List(groups)) => groups
+                .into_iter()
+                .map(|group| match group {
+                    Value::Object(mut fields) => fields.remove("name").unwrap(),
+                    other => other,
+                })
+                .collect::<Vec<_>>(),
+            other => panic!("expected a list, found {:?}", other),
+        };
+        assert_eq!(
+            names(call(get_groups, &[user.clone(), Value::Null])).len(),
+            3
+        );
+        assert_eq!(
+            names(call(
+                get_groups,
+                &[
+                    user.clone(),
+                    filter(&[("group.profile.name", strings(&["Engineering", "Eng Leads"]))])
+                ]
+            )),
+            [s("Engineering"), s("Eng Leads")]
+        );
+        assert_eq!(
+            names(call(
+                get_groups,
+                &[user.clone(), filter(&[("group.type", s("APP_GROUP"))])]
+            )),
+            [s("AD Admins")]
+        );
+        // Every key must match.
+        assert_eq!(
+            call(
+                is_member_of,
+                &[
+                    user.clone(),
+                    filter(&[
+                        ("group.source.id", s("active_directory")),
+                        ("group.id", s("00g1")),
+                    ])
+                ]
+            ),
+            Ok(Value::Bool(false))
+        );
+        assert_eq!(
+            call(
+                is_member_of,
+                &[user.clone(), filter(&[("group.id", s("00g1"))])]
+            ),
+            Ok(Value::Bool(true))
+        );
+        let err = call(
+            is_member_of,
+            &[user.clone(), filter(&[("group.name", s("Engineering"))])],
+        )
+        .unwrap_err();
+        assert!(err.msg.starts_with("cannot filter groups on `group.name`"));
+        assert!(call(get_groups, &[user, s("Engineering")]).is_err());
+    }
+
+    #[test]
+    fn managers_and_assistants() {
+        let login =
+            |login: &str| Value::Object([("login".to_string(), s(login))].into_iter().collect());
+        assert_eq!(
+            call(get_manager_user, &[s("active_directory")]),
+            Ok(login("sam@example.com"))
+        );
+        assert_eq!(
+            call(
+                get_manager_app_user,
+                &[s("active_directory"), s("active_directory")]
+            ),
+            Ok(Value::Object(
+                [("sAMAccountName".to_string(), s("sam"))]
+                    .into_iter()
+                    .collect()
+            ))
+        );
+        assert_eq!(call(get_manager_user, &[s("OKTA")]), Ok(Value::Null));
+        assert_eq!(
+            call(get_assistant_user, &[s("OKTA")]),
+            Ok(login("sam@example.com"))
+        );
+        assert_eq!(
+            call(get_assistant_app_user, &[s("OKTA"), s("salesforce")]),
+            Ok(Value::Null)
+        );
+        assert!(call(get_manager_app_user, &[s("active_directory")]).is_err());
+    }
+}
@@ -0,0 +1,458 @@
+//! The `String` namespace.
+//!
+//! Okta implements these on top of Apache Commons `StringUtils`, so null
+//! inputs and out of range indices follow its rules rather than Java's
+//! `String` methods: nothing here throws on a missing attribute.
+
+use regex::Regex;
+
+use super::{Builtin, Call};
+use crate::eval::Result;
+use crate::oel::Value;
+
+pub static FUNCTIONS: &[Builtin] = &[
+    Builtin {
+        name: "String.append",
+        signature: "(str1: String, str2: String) -> String",
+        func: append,
+    },
+    Builtin {
+        name: "String.join",
+        signature: "(separator: String, strings: String...) -> String",
+        func: join,
+    },
+    Builtin {
+        name: "String.len",
+        signature: "(string: String) -> Integer",
+        func: len,
+    },
+    Builtin {
+        name: "String.removeSpaces",
+        signature: "(string: String) -> String",
+        func: remove_spaces,
+    },
+    Builtin {
+        name: "String.replace",
+        signature: "(string: String, regex: String, replacement: String) -> String",
+        func: replace,
+    },
+    Builtin {
+        name: "String.replaceFirst",
+        signature: "(string: String, regex: String, replacement: String) -> String",
+        func: replace_first,
+    },
+    Builtin {
+        name: "String.stringContains",
+        signature: "(string: String, searchString: String) -> Boolean",
+        func: string_contains,
+    },
+    Builtin {
+        name: "String.stringSwitch",
+        signature: "(input: String, defaultString: String, keyValuePairs: String...) -> String",
+        func: string_switch,
+    },
+    Builtin {
+        name: "String.substring",
+        signature: "(string: String, startIndex: Integer, endIndex: Integer) -> String",
+        func: substring,
+    },
+    Builtin {
+        name: "String.substringAfter",
+        signature: "(string: String, searchString: String) -> String",
+        func: substring_after,
+    },
+    Builtin {
+        name: "String.substringBefore",
+        signature: "(string: String, searchString: String) -> String",
+        func: substring_before,
+    },
+    Builtin {
+        name: "String.toLowerCase",
+        signature: "(string: String) -> String",
+        func: to_lower_case,
+    },
+    Builtin {
+        name: "String.toUpperCase",
+        signature: "(string: String) -> String",
+        func: to_upper_case,
+    },
+];
+
+/// Methods of String values in Identity Engine expressions, such as
+/// `user.profile.login.substringAfter('@')`.
+pub static METHODS: &[Builtin] = &[
+    Builtin {
+        name: "String.contains",
+        signature: "(searchString: String) -> Boolean",
+        func: string_contains,
+    },
+    Builtin {
+        name: "String.endsWith",
+        signature: "(suffix: String) -> Boolean",
+        func: ends_with,
+    },
+    Builtin {
+        name: "String.length",
+        signature: "() -> Integer",
+        func: len,
+    },
+    Builtin {
+        name: "String.startsWith",
+        signature: "(prefix: String) -> Boolean",
+        func: starts_with,
+    },
+    Builtin {
+        name: "String.substringAfter",
+        signature: "(searchString: String) -> String",
+        func: substring_after,
+    },
+    Builtin {
+        name: "String.substringBefore",
+        signature: "(searchString: String) -> String",
+        func: substring_before,
+    },
+    Builtin {
+        name: "String.toLowerCase",
+        signature: "() -> String",
+        func: to_lower_case,
+    },
+    Builtin {
+        name: "String.toUpperCase",
+        signature: "() -> String",
+        func: to_upper_case,
+    },
+    Builtin {
+        name: "String.trim",
+        signature: "() -> String",
+        func: trim,
+    },
+];
+
+fn optional_str(value: Option<&str>) -> Value {
+    value.map_or(Value::Null, |s| Value::Str(s.to_string()))
+}
+
+fn append(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let first = call.string(0)?.unwrap_or_default();
+    let second = call.string(1)?.unwrap_or_default();
+    Ok(Value::Str(format!("{}{}", first, second)))
+}
+
+/// `String.join(separator, str1, str2, ...)`. Null elements join as empty strings.
+fn join(call: &Call) -> Result<Value> {
+    call.arity(1, None)?;
+    let separator = call.string(0)?.unwrap_or_default();
+    let parts = (1..call.args.len())
+        .map(|i| call.string(i).map(Option::unwrap_or_default))
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Value::Str(parts.join(separator)))
+}
+
+fn len(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let len = call.string(0)?.map_or(0, |s| s.chars().count());
+    Ok(Value::Int(len as i64))
+}
+
+fn remove_spaces(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(optional_str(
+        call.string(0)?
+            .map(|s| s.chars().filter(|c| !c.is_whitespace()).collect::<String>())
+            .as_deref(),
+    ))
+}
+
+/// Translates a Java replacement string (`$1`, `\$`) into the `regex` crate's
+/// syntax, so that `$1a` means group 1 followed by `a` as it does in Java.
+fn java_replacement(replacement: &str) -> String {
+    let mut out = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('$') => out.push_str("$$"),
+                Some(escaped) => out.push(escaped),
+                None => {}
+            },
+            '$' => {
+                let mut group = String::new();
+                while let Some(digit) = chars.peek().filter(|d| d.is_ascii_digit()) {
+                    group.push(*digit);
+                    chars.next();
+                }
+                if group.is_empty() {
+                    out.push_str("$$");
+                } else {
+                    out.push_str(&format!("${{{}}}", group));
+                }
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `String.replace` and `String.replaceFirst` treat `match` as a regular
+/// expression, like Java's `replaceAll`/`replaceFirst`.
+fn regex_replace(call: &Call, limit: usize) -> Result<Value> {
+    call.arity(3, Some(3))?;
+    let input = match call.string(0)? {
+        Some(input) => input,
+        None => return Ok(Value::Null),
+    };
+    let pattern = match call.string(1)? {
+        Some(pattern) => pattern,
+        None => return Ok(Value::Str(input.to_string())),
+    };
+    let regex = Regex::new(pattern)
+        .map_err(|err| call.error(1, format!("invalid regular expression: {}", err)))?;
+    let replacement = java_replacement(call.string(2)?.unwrap_or_default());
+    Ok(Value::Str(
+        regex
+            .replacen(input, limit, replacement.as_str())
+            .into_owned(),
+    ))
+}
+
+fn replace(call: &Call) -> Result<Value> {
+    regex_replace(call, 0)
+}
+
+fn replace_first(call: &Call) -> Result<Value> {
+    regex_replace(call, 1)
+}
+
+fn string_contains(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let contains = match (call.string(0)?, call.string(1)?) {
+        (Some(input), Some(search)) => input.contains(search),
+        _ => false,
+    };
+    Ok(Value::Bool(contains))
+}
+
+/// `String.stringSwitch(input, defaultValue, key1, value1, ...)` returns the
+/// value of the first key contained in `input`, or `defaultValue`.
+fn string_switch(call: &Call) -> Result<Value> {
+    call.arity(2, None)?;
+    if call.args.len() % 2 != 0 {
+        return Err(call.error(
+            call.args.len() - 1,
+            "String.stringSwitch key has no matching value",
+        ));
+    }
+    let input = call.string(0)?;
+    let default = call.string(1)?;
+    for key in (2..call.args.len()).step_by(2) {
+        if let (Some(input), Some(needle)) = (input, call.string(key)?) {
+            if input.contains(needle) {
+                return Ok(optional_str(call.string(key + 1)?));
+            }
+        }
+    }
+    Ok(optional_str(default))
+}
+
+/// `StringUtils.substring`: negative indices count back from the end and
+/// indices past either end are clamped instead of failing.
+fn substring(call: &Call) -> Result<Value> {
+    call.arity(3, Some(3))?;
+    let input = match call.string(0)? {
+        Some(input) => input,
+        None => return Ok(Value::Null),
+    };
+    let chars = input.chars().collect::<Vec<_>>();
+    let len = chars.len() as i64;
+    let clamp = |index: i64| {
+        let index = if index < 0 { index + len } else { index };
+        index.clamp(0, len) as usize
+    };
+    let start = clamp(call.integer(1)?.unwrap_or(0));
+    let end = clamp(call.integer(2)?.unwrap_or(len));
+    if start >= end {
+        return Ok(Value::Str(String::new()));
+    }
+    Ok(Value::Str(chars[start..end].iter().collect()))
+}
+
+fn substring_after(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let input = match call.string(0)? {
+        Some(input) => input,
+        None => return Ok(Value::Null),
+    };
+    let after = match call.string(1)? {
+        Some(search) => input
+            .find(search)
+            .map_or("", |at| &input[at + search.len()..]),
+        None => "",
+    };
+    Ok(Value::Str(after.to_string()))
+}
+
+fn substring_before(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let input = match call.string(0)? {
+        Some(input) => input,
+        None => return Ok(Value::Null),
+    };
+    let before = match call.string(1)? {
+        Some(search) => input.find(search).map_or(input, |at| &input[..at]),
+        None => input,
+    };
+    Ok(Value::Str(before.to_string()))
+}
+
+fn to_lower_case(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(optional_str(
+        call.string(0)?.map(str::to_lowercase).as_deref(),
+    ))
+}
+
+fn to_upper_case(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(optional_str(
+        call.string(0)?.map(str::to_uppercase).as_deref(),
+    ))
+}
+
+fn starts_with(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let starts_with = match (call.string(0)?, call.string(1)?) {
+        (Some(input), Some(prefix)) => input.starts_with(prefix),
+        _ => false,
+    };
+    Ok(Value::Bool(starts_with))
+}
+
+fn ends_with(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let ends_with = match (call.string(0)?, call.string(1)?) {
+        (Some(input), Some(suffix)) => input.ends_with(suffix),
+        _ => false,
+    };
+    Ok(Value::Bool(ends_with))
+}
+
+fn trim(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    Ok(optional_str(call.string(0)?.map(str::trim)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::builtins::{NativeFn, SystemClock};
+    use crate::directory::NoDirectory;
+    use crate::oel::Span;
+
+    fn call(func: NativeFn, args: &[Value]) -> Result<Value> {
+        let span = Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 1),
+        };
+        let args = args
+            .iter()
+            .map(|arg| (arg.clone(), span.clone()))
+            .collect::<Vec<_>>();
+        func(&Call {
+            name: "String.test",
+            span: &span,
+            args: &args,
+            clock: &SystemClock,
+            directory: &NoDirectory,
+            user: None,
+        })
+    }
+
+    fn s(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    #[test]
+    fn nulls_are_empty_strings() {
+        assert_eq!(call(append, &[s("a"), Value::Null]).unwrap(), s("a"));
+        assert_eq!(
+            call(join, &[s(","), s("a"), Value::Null, s("b")]).unwrap(),
+            s("a,,b")
+        );
+        assert_eq!(call(len, &[Value::Null]).unwrap(), Value::Int(0));
+        assert_eq!(call(to_upper_case, &[Value::Null]).unwrap(), Value::Null);
+        assert_eq!(
+            call(string_contains, &[Value::Null, s("")]).unwrap(),
+            Value::Bool(false)
+        );
+    }
+
+    #[test]
+    fn counts_characters() {
+        assert_eq!(call(len, &[s("Zoë")]).unwrap(), Value::Int(3));
+        assert_eq!(call(remove_spaces, &[s(" a b\t")]).unwrap(), s("ab"));
+    }
+
+    #[test]
+    fn replacements_use_java_syntax() {
+        let args = [s("a1b22"), s("(\\d+)"), s("<$1>")];
+        assert_eq!(call(replace, &args).unwrap(), s("a<1>b<22>"));
+        assert_eq!(call(replace_first, &args).unwrap(), s("a<1>b22"));
+        let dollar = [s("5"), s("\\d"), s("\\$$0")];
+        assert_eq!(call(replace, &dollar).unwrap(), s("$5"));
+        let err = call(replace, &[s("a"), s("("), s("")]).unwrap_err();
+        assert!(
+            err.msg.starts_with("invalid regular expression"),
+            "{}",
+            err.msg
+        );
+    }
+
+    #[test]
+    fn string_switch_returns_the_first_matching_value() {
+        let switch = |input: &str| {
+            call(
+                string_switch,
+                &[s(input), s("other"), s("Eng"), s("E"), s("Sales"), s("S")],
+            )
+            .unwrap()
+        };
+        assert_eq!(switch("Engineering"), s("E"));
+        assert_eq!(switch("Inside Sales"), s("S"));
+        assert_eq!(switch("Legal"), s("other"));
+        assert!(call(string_switch, &[s("a"), s("b"), s("c")]).is_err());
+    }
+
+    #[test]
+    fn substrings_clamp_their_indices() {
+        let substring = |start: i64, end: i64| {
+            call(
+                substring,
+                &[s("abcdef"), Value::Int(start), Value::Int(end)],
+            )
+            .unwrap()
+        };
+        assert_eq!(substring(1, 3), s("bc"));
+        assert_eq!(substring(-2, 10), s("ef"));
+        assert_eq!(substring(4, 2), s(""));
+        assert_eq!(
+            call(substring_after, &[s("jo@example.com"), s("@")]).unwrap(),
+            s("example.com")
+        );
+        assert_eq!(call(substring_after, &[s("jo"), s("@")]).unwrap(), s(""));
+        assert_eq!(call(substring_before, &[s("jo"), s("@")]).unwrap(), s("jo"));
+    }
+
+    #[test]
+    fn checks_argument_types() {
+        let err = call(len, &[Value::Int(1)]).unwrap_err();
+        assert_eq!(
+            err.msg,
+            "argument 1 of String.test must be a String, found Integer"
+        );
+        assert!(call(len, &[]).is_err());
+    }
+}
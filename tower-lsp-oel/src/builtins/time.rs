@@ -0,0 +1,350 @@
+//! The `Time` namespace. Times are exchanged as ISO 8601 strings, and
+//! formats use Joda-Time patterns (`yyyy-MM-dd HH:mm:ss`) as they do in Okta.
+
+use std::str::FromStr;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use super::{Builtin, Call};
+use crate::eval::Result;
+use crate::oel::Value;
+
+pub static FUNCTIONS: &[Builtin] = &[
+    Builtin {
+        name: "Time.now",
+        signature:
+            "() -> Date | (timeZone: String) -> Date | (timeZone: String, format: String) -> Date",
+        func: now,
+    },
+    Builtin {
+        name: "Time.fromWindowsToIso8601",
+        signature: "(time: Any) -> Date",
+        func: from_windows_to_iso8601,
+    },
+    Builtin {
+        name: "Time.fromUnixToIso8601",
+        signature: "(time: Any) -> Date",
+        func: from_unix_to_iso8601,
+    },
+    Builtin {
+        name: "Time.fromStringToIso8601",
+        signature: "(time: String, format: String) -> Date",
+        func: from_string_to_iso8601,
+    },
+];
+
+/// Seconds between the Windows FILETIME epoch (1601) and the Unix epoch.
+const WINDOWS_EPOCH_OFFSET: i64 = 11_644_473_600;
+
+fn iso8601(time: DateTime<Utc>) -> Value {
+    Value::Str(time.to_rfc3339_opts(SecondsFormat::Millis, true))
+}
+
+/// Translates a Joda-Time pattern into a `chrono` format string.
+pub fn joda_to_strftime(pattern: &str) -> String {
+    let chars = pattern.chars().collect::<Vec<_>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let run = chars[i..].iter().take_while(|&&next| next == c).count();
+        i += run;
+        let translated = match (c, run) {
+            ('\'', _) => {
+                // Quoted literal text, where '' is an escaped quote.
+                if run % 2 == 0 {
+                    out.push_str(&"'".repeat(run / 2));
+                    continue;
+                }
+                while i < chars.len() && chars[i] != '\'' {
+                    if chars[i] == '%' {
+                        out.push('%');
+                    }
+                    out.push(chars[i]);
+                    i += 1;
+                }
+                i += 1;
+                continue;
+            }
+            ('y' | 'Y', 2) => "%y",
+            ('y' | 'Y', _) => "%Y",
+            ('M', 1) => "%-m",
+            ('M', 2) => "%m",
+            ('M', 3) => "%b",
+            ('M', _) => "%B",
+            ('d', 1) => "%-d",
+            ('d', _) => "%d",
+            ('H', 1) => "%-H",
+            ('H', _) => "%H",
+            ('h', 1) => "%-I",
+            ('h', _) => "%I",
+            ('m', _) => "%M",
+            ('s', _) => "%S",
+            ('S', _) => "%3f",
+            ('a', _) => "%p",
+            ('E', 1..=3) => "%a",
+            ('E', _) => "%A",
+            ('Z', 1) => "%z",
+            ('Z', _) => "%:z",
+            ('z', _) => "%Z",
+            ('%', _) => {
+                out.push_str(&"%%".repeat(run));
+                continue;
+            }
+            (c, run) => {
+                out.extend(std::iter::repeat(c).take(run));
+                continue;
+            }
+        };
+        out.push_str(translated);
+    }
+    out
+}
+
+/// The items of a `chrono` format string, or `None` if it has a specifier
+/// `chrono` does not know, which would make formatting panic.
+fn strftime_items(format: &str) -> Option<Vec<Item<'_>>> {
+    let items = StrftimeItems::new(format).collect::<Vec<_>>();
+    if items.iter().any(|item| matches!(item, Item::Error)) {
+        return None;
+    }
+    Some(items)
+}
+
+/// `Time.now()`, `Time.now(timeZone)` or `Time.now(timeZone, format)`.
+fn now(call: &Call) -> Result<Value> {
+    call.arity(0, Some(2))?;
+    let now = call.clock.now();
+    let zone = match call.args.first() {
+        Some(_) => match call.string(0)? {
+            Some(name) => Some(
+                Tz::from_str(name)
+                    .map_err(|_| call.error(0, format!("unknown time zone '{}'", name)))?,
+            ),
+            None => None,
+        },
+        None => None,
+    };
+    let pattern = match call.args.get(1) {
+        Some(_) => call.string(1)?,
+        None => None,
+    };
+    let format = pattern.map(joda_to_strftime);
+    let items = match (pattern, &format) {
+        (Some(pattern), Some(format)) => Some(
+            strftime_items(format)
+                .ok_or_else(|| call.error(1, format!("invalid time format '{}'", pattern)))?,
+        ),
+        _ => None,
+    };
+    Ok(match (zone, items) {
+        (Some(zone), Some(items)) => Value::Str(
+            now.with_timezone(&zone)
+                .format_with_items(items.iter())
+                .to_string(),
+        ),
+        (None, Some(items)) => Value::Str(now.format_with_items(items.iter()).to_string()),
+        (Some(zone), None) => Value::Str(
+            now.with_timezone(&zone)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+        ),
+        (None, None) => iso8601(now),
+    })
+}
+
+/// Integers may be passed as Integer or as a String, as they usually come
+/// straight from a directory attribute.
+fn integer_like(call: &Call, index: usize) -> Result<Option<i64>> {
+    match call.value(index) {
+        Value::Str(s) => s
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|_| call.error(index, format!("'{}' is not an integer", s))),
+        _ => call.integer(index),
+    }
+}
+
+fn from_windows_to_iso8601(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let ticks = match integer_like(call, 0)? {
+        Some(ticks) => ticks,
+        None => return Ok(Value::Null),
+    };
+    let seconds = ticks.div_euclid(10_000_000) - WINDOWS_EPOCH_OFFSET;
+    let nanos = ticks.rem_euclid(10_000_000) * 100;
+    match Utc.timestamp_opt(seconds, nanos as u32).single() {
+        Some(time) => Ok(iso8601(time)),
+        None => Err(call.error(0, "timestamp is out of range")),
+    }
+}
+
+fn from_unix_to_iso8601(call: &Call) -> Result<Value> {
+    call.arity(1, Some(1))?;
+    let seconds = match integer_like(call, 0)? {
+        Some(seconds) => seconds,
+        None => return Ok(Value::Null),
+    };
+    match Utc.timestamp_opt(seconds, 0).single() {
+        Some(time) => Ok(iso8601(time)),
+        None => Err(call.error(0, "timestamp is out of range")),
+    }
+}
+
+/// `Time.fromStringToIso8601(string, format)`. Times without an offset are
+/// taken to be UTC.
+fn from_string_to_iso8601(call: &Call) -> Result<Value> {
+    call.arity(2, Some(2))?;
+    let (input, pattern) = match (call.string(0)?, call.string(1)?) {
+        (Some(input), Some(pattern)) => (input, pattern),
+        _ => return Ok(Value::Null),
+    };
+    let format = joda_to_strftime(pattern);
+    let parsed = DateTime::parse_from_str(input, &format)
+        .map(|time| time.with_timezone(&Utc))
+        .or_else(|_| NaiveDateTime::parse_from_str(input, &format).map(|time| time.and_utc()))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(input, &format)
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
+        })
+        .map_err(|err| {
+            call.error(
+                0,
+                format!(
+                    "'{}' does not match the format '{}': {}",
+                    input, pattern, err
+                ),
+            )
+        })?;
+    Ok(iso8601(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::builtins::{FixedClock, NativeFn};
+    use crate::directory::NoDirectory;
+    use crate::oel::Span;
+
+    fn call(func: NativeFn, args: &[Value]) -> Result<Value> {
+        let span = Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 1),
+        };
+        let args = args
+            .iter()
+            .map(|arg| (arg.clone(), span.clone()))
+            .collect::<Vec<_>>();
+        let now = Utc.with_ymd_and_hms(2024, 3, 5, 14, 7, 9).unwrap()
+            + chrono::Duration::milliseconds(123);
+        func(&Call {
+            name: "Time.test",
+            span: &span,
+            args: &args,
+            clock: &FixedClock(now),
+            directory: &NoDirectory,
+            user: None,
+        })
+    }
+
+    fn s(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    #[test]
+    fn joda_patterns() {
+        assert_eq!(
+            joda_to_strftime("yyyy-MM-dd'T'HH:mm:ss.SSSZ"),
+            "%Y-%m-%dT%H:%M:%S.%3f%z"
+        );
+        assert_eq!(joda_to_strftime("d MMM yy, h a"), "%-d %b %y, %-I %p");
+        assert_eq!(joda_to_strftime("EEEE 'at' H"), "%A at %-H");
+        assert_eq!(joda_to_strftime("'100%' yyyy%"), "100%% %Y%%");
+        assert!(strftime_items("%Y-%m-%d %:z").is_some());
+        assert!(strftime_items("%Q").is_none());
+        assert!(strftime_items("%").is_none());
+    }
+
+    #[test]
+    fn now_uses_the_clock() {
+        assert_eq!(call(now, &[]).unwrap(), s("2024-03-05T14:07:09.123Z"));
+        assert_eq!(
+            call(now, &[s("Europe/Paris")]).unwrap(),
+            s("2024-03-05T15:07:09.123+01:00")
+        );
+        assert_eq!(
+            call(now, &[s("Europe/Paris"), s("yyyy-MM-dd HH:mm")]).unwrap(),
+            s("2024-03-05 15:07")
+        );
+        assert_eq!(
+            call(now, &[Value::Null, s("dd/MM/yy")]).unwrap(),
+            s("05/03/24")
+        );
+        assert_eq!(
+            call(now, &[s("Mars/Olympus")]).unwrap_err().msg,
+            "unknown time zone 'Mars/Olympus'"
+        );
+    }
+
+    #[test]
+    fn unix_and_windows_timestamps() {
+        let epoch = s("1970-01-01T00:00:00.000Z");
+        assert_eq!(call(from_unix_to_iso8601, &[Value::Int(0)]).unwrap(), epoch);
+        assert_eq!(
+            call(from_unix_to_iso8601, &[s(" 86400 ")]).unwrap(),
+            s("1970-01-02T00:00:00.000Z")
+        );
+        assert_eq!(
+            call(from_windows_to_iso8601, &[s("116444736000000000")]).unwrap(),
+            epoch
+        );
+        assert_eq!(
+            call(from_windows_to_iso8601, &[Value::Int(116444736012345678)]).unwrap(),
+            s("1970-01-01T00:00:01.234Z")
+        );
+        assert_eq!(
+            call(from_unix_to_iso8601, &[Value::Null]).unwrap(),
+            Value::Null
+        );
+        assert_eq!(
+            call(from_unix_to_iso8601, &[s("soon")]).unwrap_err().msg,
+            "'soon' is not an integer"
+        );
+        assert_eq!(
+            call(from_unix_to_iso8601, &[Value::Int(i64::MAX)])
+                .unwrap_err()
+                .msg,
+            "timestamp is out of range"
+        );
+    }
+
+    #[test]
+    fn parses_strings_with_a_pattern() {
+        let parse =
+            |input: &str, pattern: &str| call(from_string_to_iso8601, &[s(input), s(pattern)]);
+        assert_eq!(
+            parse("2024-03-05 14:07", "yyyy-MM-dd HH:mm").unwrap(),
+            s("2024-03-05T14:07:00.000Z")
+        );
+        assert_eq!(
+            parse("05/03/2024", "dd/MM/yyyy").unwrap(),
+            s("2024-03-05T00:00:00.000Z")
+        );
+        assert_eq!(
+            parse("2024-03-05T14:07:09+0100", "yyyy-MM-dd'T'HH:mm:ssZ").unwrap(),
+            s("2024-03-05T13:07:09.000Z")
+        );
+        let err = parse("05/03", "yyyy-MM-dd").unwrap_err();
+        assert!(
+            err.msg
+                .starts_with("'05/03' does not match the format 'yyyy-MM-dd'"),
+            "{}",
+            err.msg
+        );
+    }
+}
@@ -0,0 +1,90 @@
+//! Documentation of the built-in functions, root objects and operators,
+//! bundled from `catalogue/functions.json`.
+//!
+//! The catalogue only holds documentation; signatures live with the
+//! implementations in [`builtins`](crate::builtins).
+
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct Catalogue {
+    /// Bumped whenever the content is updated from Okta's documentation.
+    pub version: String,
+    pub source: String,
+    /// Display names of the expression contexts, keyed by id.
+    pub contexts: BTreeMap<String, String>,
+    pub roots: Vec<RootDoc>,
+    pub functions: Vec<FunctionDoc>,
+    pub operators: Vec<OperatorDoc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RootDoc {
+    pub name: String,
+    pub description: String,
+    /// Ids of the expression contexts the root is available in.
+    pub contexts: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FunctionDoc {
+    pub name: String,
+    pub description: String,
+    pub examples: Vec<Example>,
+    /// Ids of the expression contexts the function can be used in.
+    pub contexts: Vec<String>,
+    /// What to use instead, if Okta has deprecated the function.
+    #[serde(default)]
+    pub deprecated: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Example {
+    pub expression: String,
+    pub result: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OperatorDoc {
+    pub token: String,
+    /// Written before its single operand, as `!`. `-` has an entry of each
+    /// kind.
+    #[serde(default)]
+    pub unary: bool,
+    /// Higher binds tighter, as in the `precedences` of `grammar.js`.
+    pub precedence: u8,
+    pub description: String,
+}
+
+static CATALOGUE: OnceLock<Catalogue> = OnceLock::new();
+
+impl Catalogue {
+    /// The bundled catalogue.
+    pub fn get() -> &'static Catalogue {
+        CATALOGUE.get_or_init(|| {
+            serde_json::from_str(include_str!("../catalogue/functions.json"))
+                .expect("catalogue/functions.json is malformed")
+        })
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionDoc> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    pub fn root(&self, name: &str) -> Option<&RootDoc> {
+        self.roots.iter().find(|root| root.name == name)
+    }
+
+    pub fn operator(&self, token: &str, unary: bool) -> Option<&OperatorDoc> {
+        self.operators
+            .iter()
+            .find(|operator| operator.token == token && operator.unary == unary)
+    }
+
+    pub fn context_name<'a>(&'a self, id: &'a str) -> &'a str {
+        self.contexts.get(id).map_or(id, String::as_str)
+    }
+}
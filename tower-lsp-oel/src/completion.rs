@@ -1,118 +1,270 @@
 use std::collections::HashMap;
 
-use tree_sitter::Node;
+use tree_sitter::Point;
 
-use crate::oel::{Span, Spanned};
+use crate::builtins::Registry;
+use crate::dialect::Dialect;
+use crate::eval::ROOTS;
+use crate::expression_context::ExpressionContext;
+use crate::oel::{walk, Expr, Spanned};
+use crate::schema::Schema;
+use crate::typecheck::{Signature, Type};
 
 pub enum ImCompleteCompletionItem {
+    /// One of the root objects, `user`, `app`, ...
     Variable(String),
-    Function(String, Vec<String>),
+    /// A profile attribute, after `user.` and the like.
+    Attribute {
+        name: String,
+        ty: Type,
+        title: Option<String>,
+    },
+    /// A namespace of functions, such as `String`.
+    Namespace(String),
+    /// A function, completed as a snippet with a placeholder per parameter.
+    Function { name: String, signature: Signature },
+    /// An Identity Engine method of the value before the `.`.
+    Method { name: String, signature: Signature },
 }
-/// return (need_to_continue_search, founded reference)
+
+impl ImCompleteCompletionItem {
+    /// Snippet text inserting the call with placeholders for the required
+    /// parameters, e.g. `substring(${1:string}, ${2:startIndex}, ${3:endIndex})`.
+    pub fn snippet(name: &str, signature: &Signature) -> String {
+        let params = signature
+            .params
+            .iter()
+            .filter(|param| !param.optional)
+            .enumerate()
+            .map(|(index, param)| format!("${{{}:{}}}", index + 1, param.name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{}({})", name, params)
+    }
+}
+
+/// What the cursor is completing.
+#[derive(Debug, PartialEq)]
+enum Site {
+    /// A property of `object`, e.g. `user.` or `String.sub`.
+    Member(String),
+    /// The start of an expression.
+    Expression,
+}
+
+/// Completions at `position`, keyed by label. Only the roots and functions
+/// available in `context` are offered, and methods only in the Identity
+/// Engine `dialect`.
 pub fn completion(
-    ast: &HashMap<String, Span>,
-    ident_offset: usize,
+    ast: Option<&Spanned<Expr>>,
+    src: &str,
+    position: Point,
+    registry: &Registry,
+    schema: &Schema,
+    context: ExpressionContext,
+    dialect: Dialect,
 ) -> HashMap<String, ImCompleteCompletionItem> {
-    // let mut map = HashMap::new();
-    // for (_, v) in ast.iter() {
-    //     if v.name.1.end < ident_offset {
-    //         map.insert(
-    //             v.name.0.clone(),
-    //             ImCompleteCompletionItem::Function(
-    //                 v.name.0.clone(),
-    //                 v.args.clone().into_iter().map(|(name, _)| name).collect(),
-    //             ),
-    //         );
-    //     }
-    // }
+    let site = ast
+        .and_then(|ast| member_at(ast, position))
+        .or_else(|| member_before(src, position))
+        .unwrap_or(Site::Expression);
+
+    let mut map = HashMap::new();
+    match site {
+        Site::Member(object) => {
+            // Identity Engine attributes live under `user.profile`, and the
+            // values of attributes have methods.
+            let (profile, receiver) = match (dialect, object.split_once('.')) {
+                (Dialect::Classic, _) => (object.as_str(), None),
+                (Dialect::IdentityEngine, None) if object == "user" => ("", Some("User")),
+                (Dialect::IdentityEngine, Some((root, "profile"))) => (root, None),
+                (Dialect::IdentityEngine, Some((root, _))) if ROOTS.contains(&root) => {
+                    ("", Some("String"))
+                }
+                (Dialect::IdentityEngine, _) => ("", None),
+            };
+            for method in receiver
+                .into_iter()
+                .flat_map(|receiver| registry.methods(receiver))
+            {
+                let name = method
+                    .name
+                    .split_once('.')
+                    .map_or(method.name, |(_, name)| name);
+                map.insert(
+                    name.to_string(),
+                    ImCompleteCompletionItem::Method {
+                        name: name.to_string(),
+                        signature: Signature::of(method),
+                    },
+                );
+            }
+            if let Some(profile) = schema.profile(profile) {
+                for attribute in profile.attributes.values() {
+                    map.insert(
+                        attribute.name.clone(),
+                        ImCompleteCompletionItem::Attribute {
+                            name: attribute.name.clone(),
+                            ty: attribute.ty.clone(),
+                            title: attribute.title.clone(),
+                        },
+                    );
+                }
+            }
+            for builtin in registry
+                .functions()
+                .filter(|builtin| context.allows_function(builtin.name))
+            {
+                if let Some(name) = builtin
+                    .name
+                    .strip_prefix(object.as_str())
+                    .and_then(|name| name.strip_prefix('.'))
+                {
+                    map.insert(
+                        name.to_string(),
+                        ImCompleteCompletionItem::Function {
+                            name: name.to_string(),
+                            signature: Signature::of(builtin),
+                        },
+                    );
+                }
+            }
+        }
+        Site::Expression => {
+            for root in context.roots() {
+                map.insert(
+                    root.to_string(),
+                    ImCompleteCompletionItem::Variable(root.to_string()),
+                );
+            }
+            for namespace in registry.namespaces().into_iter().filter(|namespace| {
+                registry.functions().any(|builtin| {
+                    builtin
+                        .name
+                        .strip_prefix(namespace)
+                        .map_or(false, |name| name.starts_with('.'))
+                        && context.allows_function(builtin.name)
+                })
+            }) {
+                map.insert(
+                    namespace.to_string(),
+                    ImCompleteCompletionItem::Namespace(namespace.to_string()),
+                );
+            }
+            for builtin in registry.functions().filter(|builtin| {
+                !builtin.name.contains('.') && context.allows_function(builtin.name)
+            }) {
+                map.insert(
+                    builtin.name.to_string(),
+                    ImCompleteCompletionItem::Function {
+                        name: builtin.name.to_string(),
+                        signature: Signature::of(builtin),
+                    },
+                );
+            }
+        }
+    }
+    map
+}
 
-    // // collect params variable
-    // for (_, v) in ast.iter() {
-    //     if v.span.end > ident_offset && v.span.start < ident_offset {
-    //         // log::debug!("this is completion from body {}", name);
-    //         v.args.iter().for_each(|(item, _)| {
-    //             map.insert(
-    //                 item.clone(),
-    //                 ImCompleteCompletionItem::Variable(item.clone()),
-    //             );
-    //         });
-    //         get_completion_of(&v.body, &mut map, ident_offset);
-    //     }
-    // }
-    // map
-    HashMap::new()
+/// The innermost member expression whose property is under the cursor,
+/// including the empty property tree-sitter inserts for `user.`.
+fn member_at(ast: &Spanned<Expr>, position: Point) -> Option<Site> {
+    let mut site = None;
+    walk(ast, &mut |(expr, _)| {
+        if let Expr::Member { object, property } = expr {
+            if property.1.start <= position && position <= property.1.end {
+                site = object.0.path().map(Site::Member);
+            }
+        }
+    });
+    site
 }
 
-pub fn get_completion_of(
-    expr: &Spanned<Node>,
-    definition_map: &mut HashMap<String, ImCompleteCompletionItem>,
-    ident_offset: usize,
-) -> bool {
-    // match &expr.0 {
-    //     Expr::Error => true,
-    //     Expr::Value(_) => true,
-    //     // Expr::List(exprs) => exprs
-    //     //     .iter()
-    //     //     .for_each(|expr| get_definition(expr, definition_ass_list)),
-    //     Expr::Local(local) => {
-    //         if ident_offset >= local.1.start && ident_offset < local.1.end {
-    //             false
-    //         } else {
-    //             true
-    //         }
-    //     }
-    //     Expr::Let(name, lhs, rest, _name_span) => {
-    //         definition_map.insert(
-    //             name.clone(),
-    //             ImCompleteCompletionItem::Variable(name.clone()),
-    //         );
-    //         match get_completion_of(lhs, definition_map, ident_offset) {
-    //             true => get_completion_of(rest, definition_map, ident_offset),
-    //             false => return false,
-    //         }
-    //     }
-    //     Expr::Then(first, second) => match get_completion_of(first, definition_map, ident_offset) {
-    //         true => get_completion_of(second, definition_map, ident_offset),
-    //         false => false,
-    //     },
-    //     Expr::Binary(lhs, _op, rhs) => match get_completion_of(lhs, definition_map, ident_offset) {
-    //         true => get_completion_of(rhs, definition_map, ident_offset),
-    //         false => false,
-    //     },
-    //     Expr::Call(callee, args) => {
-    //         match get_completion_of(callee, definition_map, ident_offset) {
-    //             true => {}
-    //             false => return false,
-    //         }
-    //         for expr in &args.0 {
-    //             match get_completion_of(&expr, definition_map, ident_offset) {
-    //                 true => continue,
-    //                 false => return false,
-    //             }
-    //         }
-    //         true
-    //     }
-    //     Expr::If(test, consequent, alternative) => {
-    //         match get_completion_of(test, definition_map, ident_offset) {
-    //             true => {}
-    //             false => return false,
-    //         }
-    //         match get_completion_of(consequent, definition_map, ident_offset) {
-    //             true => {}
-    //             false => return false,
-    //         }
-    //         get_completion_of(alternative, definition_map, ident_offset)
-    //     }
-    //     Expr::Print(expr) => get_completion_of(expr, definition_map, ident_offset),
-    //     Expr::List(lst) => {
-    //         for expr in lst {
-    //             match get_completion_of(expr, definition_map, ident_offset) {
-    //                 true => continue,
-    //                 false => return false,
-    //             }
-    //         }
-    //         true
-    //     }
-    // }
-    return false;
+/// Falls back to the text before the cursor when error recovery left no
+/// member expression to find, e.g. `String.` on its own.
+fn member_before(src: &str, position: Point) -> Option<Site> {
+    let line = src.lines().nth(position.row)?;
+    let before = line.get(..position.column.min(line.len()))?;
+    let is_word = |c: char| c.is_alphanumeric() || c == '_' || c == '$';
+    let before = before.trim_end_matches(is_word);
+    let object = before.strip_suffix('.')?;
+    let path_start = object
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !is_word(*c) && *c != '.')
+        .map_or(0, |(at, c)| at + c.len_utf8());
+    let path = &object[path_start..];
+    if path.is_empty() || path.split('.').any(str::is_empty) {
+        return None;
+    }
+    Some(Site::Member(path.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(src: &str, column: usize) -> Vec<String> {
+        let registry = Registry::default();
+        let mut labels = completion(
+            None,
+            src,
+            Point::new(0, column),
+            &registry,
+            &Schema::default(),
+            ExpressionContext::Any,
+            Dialect::Classic,
+        )
+        .into_keys()
+        .collect::<Vec<_>>();
+        labels.sort();
+        labels
+    }
+
+    #[test]
+    fn member_before_finds_the_object_path() {
+        let site = |src: &str| member_before(src, Point::new(0, src.len()));
+        assert_eq!(site("user."), Some(Site::Member("user".to_string())));
+        assert_eq!(
+            site("a AND String.sub"),
+            Some(Site::Member("String".to_string()))
+        );
+        assert_eq!(
+            site("(user.manager.fi"),
+            Some(Site::Member("user.manager".to_string()))
+        );
+        assert_eq!(site("user"), None);
+        assert_eq!(site("1 + ."), None);
+        assert_eq!(site("user..x"), None);
+    }
+
+    #[test]
+    fn completes_attributes_and_functions_of_the_object() {
+        let user = labels("user.", 5);
+        assert!(user.contains(&"login".to_string()));
+        assert!(user.contains(&"costCenter".to_string()));
+        let string = labels("String.", 7);
+        assert!(string.contains(&"substringAfter".to_string()));
+        assert!(!string.iter().any(|label| label.contains('.')));
+    }
+
+    #[test]
+    fn completes_roots_and_namespaces_in_expressions() {
+        let labels = labels("", 0);
+        for label in ["user", "org", "String", "Arrays"] {
+            assert!(labels.contains(&label.to_string()), "{}", label);
+        }
+        assert!(!labels.contains(&"login".to_string()));
+    }
+
+    #[test]
+    fn snippets_skip_optional_parameters() {
+        let signature =
+            Signature::parse("(string: String, start: Integer, end?: Integer) -> String");
+        assert_eq!(
+            ImCompleteCompletionItem::snippet("substring", &signature.unwrap()),
+            "substring(${1:string}, ${2:start})"
+        );
+    }
 }
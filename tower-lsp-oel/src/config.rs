@@ -0,0 +1,145 @@
+//! Settings shared by the language server and the `oel` tool, read from the
+//! LSP initialization options and from a `.oel.json` file at the root of the
+//! workspace:
+//!
+//! ```json
+//! {
+//!   "schemas": { "user": "schemas/user.json", "appuser": "schemas/app-user.json" },
+//!   "context": "profile-mapping",
+//!   "dialect": "classic",
+//!   "directory": "fixtures/groups.yaml"
+//! }
+//! ```
+//!
+//! Relative paths are resolved against the workspace root.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::dialect::Dialect;
+use crate::expression_context::ExpressionContext;
+use crate::schema::{Profile, Schema};
+
+pub const CONFIG_FILE: &str = ".oel.json";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct Config {
+    /// Profile schema file per root object.
+    #[serde(default)]
+    pub schemas: BTreeMap<String, PathBuf>,
+    /// The expression context of files that do not choose one themselves.
+    #[serde(default)]
+    pub context: Option<ExpressionContext>,
+    /// The dialect of files that do not choose one themselves.
+    #[serde(default)]
+    pub dialect: Option<Dialect>,
+    /// The users and groups fixture, as passed to `oel eval --directory`.
+    #[serde(default)]
+    pub directory: Option<PathBuf>,
+}
+
+impl Config {
+    pub fn from_json(json: serde_json::Value) -> Result<Self, String> {
+        serde_json::from_value(json).map_err(|err| err.to_string())
+    }
+
+    /// Reads `.oel.json` from `dir`, if there is one.
+    pub fn discover(dir: &Path) -> Result<Option<Self>, String> {
+        let path = dir.join(CONFIG_FILE);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        Self::load(&path).map(Some)
+    }
+
+    /// Reads a config file, resolving its paths against its directory.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let json =
+            serde_json::from_str(&src).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let config = Self::from_json(json).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(config.resolve(path.parent().unwrap_or_else(|| Path::new("."))))
+    }
+
+    /// Settings in `other` take precedence.
+    pub fn merge(mut self, other: Config) -> Self {
+        self.schemas.extend(other.schemas);
+        self.context = other.context.or(self.context);
+        self.dialect = other.dialect.or(self.dialect);
+        self.directory = other.directory.or(self.directory);
+        self
+    }
+
+    pub fn resolve(mut self, base: &Path) -> Self {
+        for path in self.schemas.values_mut().chain(self.directory.as_mut()) {
+            if path.is_relative() {
+                *path = base.join(&*path);
+            }
+        }
+        self
+    }
+
+    /// Loads the configured schemas. Schemas that fail to load are left out
+    /// and their errors returned alongside.
+    pub fn schema(&self) -> (Schema, Vec<String>) {
+        let mut schema = Schema::default();
+        let mut errors = Vec::new();
+        for (root, path) in &self.schemas {
+            match Profile::load(path) {
+                Ok(profile) => schema.insert(root, profile),
+                Err(err) => errors.push(err),
+            }
+        }
+        (schema, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(json: serde_json::Value) -> Config {
+        Config::from_json(json).unwrap()
+    }
+
+    #[test]
+    fn resolves_relative_paths_against_the_base() {
+        let resolved = config(serde_json::json!({
+            "schemas": { "user": "schemas/user.json", "appuser": "/abs/app-user.json" }
+        }))
+        .resolve(Path::new("/workspace"));
+        assert_eq!(
+            resolved.schemas["user"],
+            Path::new("/workspace/schemas/user.json")
+        );
+        assert_eq!(resolved.schemas["appuser"], Path::new("/abs/app-user.json"));
+    }
+
+    #[test]
+    fn later_settings_win() {
+        let merged =
+            config(serde_json::json!({ "schemas": { "user": "a.json", "app": "b.json" } })).merge(
+                config(serde_json::json!({ "schemas": { "user": "c.json" } })),
+            );
+        assert_eq!(merged.schemas["user"], Path::new("c.json"));
+        assert_eq!(merged.schemas["app"], Path::new("b.json"));
+    }
+
+    #[test]
+    fn schemas_that_fail_to_load_are_reported() {
+        let (schema, errors) =
+            config(serde_json::json!({ "schemas": { "appuser": "/missing/schema.json" } }))
+                .schema();
+        assert!(schema.profile("appuser").is_none());
+        assert!(schema.profile("user").is_some());
+        assert_eq!(errors.len(), 1);
+        assert!(
+            errors[0].starts_with("/missing/schema.json: "),
+            "{}",
+            errors[0]
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! The two flavours of the expression language. Classic expressions (group
+//! rules, profile mappings, ...) and Okta Identity Engine expressions
+//! (policies) share the grammar, but Identity Engine, being closer to SpEL,
+//! also has
+//!
+//! * the `and` and `or` logical operators,
+//! * the `eq`, `ne`, `lt`, `le`, `gt` and `ge` comparisons,
+//! * `matches` for regular expressions,
+//! * map literals, `{'group.profile.name': 'Admins'}`,
+//! * methods on values, `user.profile.login.substringAfter('@')`, and
+//! * attributes under `user.profile` rather than directly on `user`.
+//!
+//! A file picks its dialect, in order of precedence, with
+//!
+//! * a pragma comment at the top of the file, `// @dialect oie`,
+//! * a second extension, `policies/mfa.oie.oel`,
+//! * `"dialect": "oie"` in `.oel.json`,
+//! * its [`ExpressionContext`]: policies are always Identity Engine.
+//!
+//! Otherwise it is [`Dialect::Classic`].
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tree_sitter::{Node, Tree};
+use tree_sitter_traversal::{traverse, Order};
+
+use crate::expression_context::{pragma, ExpressionContext};
+use crate::oel::Span;
+use crate::typecheck::{Fix, TypeError};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Dialect {
+    #[default]
+    Classic,
+    IdentityEngine,
+}
+
+pub const PRAGMA: &str = "@dialect";
+
+impl Dialect {
+    pub const ALL: &'static [Dialect] = &[Dialect::Classic, Dialect::IdentityEngine];
+
+    /// The id used in pragmas and config, e.g. `oie`.
+    pub fn id(self) -> &'static str {
+        match self {
+            Dialect::Classic => "classic",
+            Dialect::IdentityEngine => "oie",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Self> {
+        match id.trim() {
+            "classic" => Some(Dialect::Classic),
+            "oie" | "identity-engine" | "spel" => Some(Dialect::IdentityEngine),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Classic => "Classic",
+            Dialect::IdentityEngine => "Identity Engine",
+        }
+    }
+
+    /// The dialect named by a `// @dialect <id>` comment before the
+    /// expression.
+    pub fn from_pragma(src: &str) -> Option<Self> {
+        pragma(src, PRAGMA).and_then(|(id, _)| Self::from_id(id))
+    }
+
+    /// The dialect named by the second extension of `path`, as in
+    /// `mfa.oie.oel`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let stem = Path::new(path.file_stem()?);
+        Self::from_id(stem.extension()?.to_str()?)
+    }
+
+    /// The dialect of a file: its pragma, else its extension, else `default`
+    /// (from the configuration), else the one its `context` requires.
+    pub fn detect(
+        path: Option<&Path>,
+        src: &str,
+        default: Option<Self>,
+        context: ExpressionContext,
+    ) -> Self {
+        Self::from_pragma(src)
+            .or_else(|| path.and_then(Self::from_path))
+            .or(default)
+            .unwrap_or(match context {
+                ExpressionContext::Policy => Dialect::IdentityEngine,
+                _ => Dialect::Classic,
+            })
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl Serialize for Dialect {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Dialect {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Self::from_id(&id).ok_or_else(|| {
+            serde::de::Error::custom(format!("unknown dialect `{}`, expected classic or oie", id))
+        })
+    }
+}
+
+/// The Identity Engine operators, with their Classic spelling if there is
+/// one.
+const OPERATORS: &[(&str, Option<&str>)] = &[
+    ("and", Some("AND")),
+    ("or", Some("OR")),
+    ("eq", Some("==")),
+    ("ne", Some("!=")),
+    ("lt", Some("<")),
+    ("le", Some("<=")),
+    ("gt", Some(">")),
+    ("ge", Some(">=")),
+    ("matches", None),
+];
+
+/// Errors for the Identity Engine syntax used in a Classic file. Methods on
+/// values are left to the type checker, which knows the type of the value.
+pub fn check(tree: &Tree, src: &str, dialect: Dialect) -> Vec<TypeError> {
+    if dialect == Dialect::IdentityEngine {
+        return Vec::new();
+    }
+    let text = |node: Node| node.utf8_text(src.as_bytes()).unwrap_or_default();
+    let mut errors = Vec::new();
+    for node in traverse(tree.walk(), Order::Pre) {
+        let (span, msg, replacement) = match node.kind() {
+            "binary_expression" => {
+                let operator = match node.child_by_field_name("operator") {
+                    Some(operator) => operator,
+                    None => continue,
+                };
+                match OPERATORS.iter().find(|(oie, _)| *oie == text(operator)) {
+                    Some((oie, classic)) => (
+                        Span::from(operator),
+                        format!("`{}` is only available in Identity Engine expressions", oie),
+                        *classic,
+                    ),
+                    None => continue,
+                }
+            }
+            "map" => (
+                Span::from(node),
+                "map literals are only available in Identity Engine expressions".to_string(),
+                None,
+            ),
+            _ => continue,
+        };
+        let mut fixes = Vec::new();
+        if let Some(replacement) = replacement {
+            fixes.push(Fix::Replace(replacement.to_string()));
+        }
+        fixes.push(Fix::SetDialect(Dialect::IdentityEngine));
+        errors.push(TypeError {
+            fixes,
+            ..TypeError::new(&span, msg)
+        });
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::expression_context::pragma_edit;
+    use crate::oel::parse_tree;
+    use tree_sitter::Point;
+
+    fn errors(src: &str, dialect: Dialect) -> Vec<(String, Vec<Fix>)> {
+        let tree = parse_tree(src, None).unwrap();
+        check(&tree, src, dialect)
+            .into_iter()
+            .map(|error| (error.msg, error.fixes))
+            .collect()
+    }
+
+    #[test]
+    fn ids_pragmas_and_extensions() {
+        assert_eq!(Dialect::from_id(" spel"), Some(Dialect::IdentityEngine));
+        assert_eq!(Dialect::from_id("classic"), Some(Dialect::Classic));
+        assert_eq!(Dialect::from_id("sql"), None);
+        assert_eq!(
+            Dialect::from_pragma("\n// @dialect oie\nuser.login"),
+            Some(Dialect::IdentityEngine)
+        );
+        assert_eq!(Dialect::from_pragma("user.login\n// @dialect oie"), None);
+        assert_eq!(
+            Dialect::from_path(Path::new("policies/mfa.oie.oel")),
+            Some(Dialect::IdentityEngine)
+        );
+        assert_eq!(Dialect::from_path(Path::new("mfa.oel")), None);
+        assert!(serde_json::from_str::<Dialect>("\"sql\"").is_err());
+        assert_eq!(
+            serde_json::to_string(&Dialect::IdentityEngine).unwrap(),
+            "\"oie\""
+        );
+    }
+
+    #[test]
+    fn detect_follows_the_precedence() {
+        let path = Path::new("mfa.oie.oel");
+        let policy = ExpressionContext::Policy;
+        let any = ExpressionContext::Any;
+        let pragma = "// @dialect classic\nuser.login";
+        assert_eq!(
+            Dialect::detect(Some(path), pragma, None, policy),
+            Dialect::Classic
+        );
+        assert_eq!(
+            Dialect::detect(Some(path), "x", Some(Dialect::Classic), any),
+            Dialect::IdentityEngine
+        );
+        assert_eq!(
+            Dialect::detect(None, "x", Some(Dialect::Classic), policy),
+            Dialect::Classic
+        );
+        assert_eq!(
+            Dialect::detect(None, "x", None, policy),
+            Dialect::IdentityEngine
+        );
+        assert_eq!(Dialect::detect(None, "x", None, any), Dialect::Classic);
+    }
+
+    #[test]
+    fn classic_files_reject_identity_engine_syntax() {
+        assert_eq!(
+            errors("user.isActive and true", Dialect::Classic),
+            [(
+                "`and` is only available in Identity Engine expressions".to_string(),
+                vec![
+                    Fix::Replace("AND".to_string()),
+                    Fix::SetDialect(Dialect::IdentityEngine)
+                ]
+            )]
+        );
+        assert_eq!(
+            errors("user.login matches '.*'", Dialect::Classic)[0].1,
+            [Fix::SetDialect(Dialect::IdentityEngine)]
+        );
+        assert_eq!(
+            errors("{'a': 1}", Dialect::Classic)[0].0,
+            "map literals are only available in Identity Engine expressions"
+        );
+        assert!(errors("user.login AND true", Dialect::Classic).is_empty());
+        assert!(errors("user.isActive && {'a': 1}", Dialect::IdentityEngine).is_empty());
+    }
+
+    #[test]
+    fn pragma_edit_inserts_or_replaces_the_pragma() {
+        assert_eq!(
+            pragma_edit("user.login", PRAGMA, "oie"),
+            (
+                Point::new(0, 0),
+                Point::new(0, 0),
+                "// @dialect oie\n".to_string()
+            )
+        );
+        assert_eq!(
+            pragma_edit("// @context policy\n// @dialect classic\nx", PRAGMA, "oie"),
+            (
+                Point::new(1, 0),
+                Point::new(1, 19),
+                "// @dialect oie".to_string()
+            )
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Users and groups that group and manager functions are evaluated against.
+//!
+//! [`InMemoryDirectory`] is loaded from a JSON or YAML fixture so that group
+//! rules can be checked without an Okta tenant:
+//!
+//! ```yaml
+//! groups:
+//!   - id: 00g1
+//!     name: Engineering
+//!   - id: 00g2
+//!     name: AD Admins
+//!     source: active_directory
+//! users:
+//!   - id: 00u1
+//!     profile: { login: jo@example.com, department: Engineering }
+//!     groups: [00g1, AD Admins]
+//!     managers: { active_directory: 00u2 }
+//!   - id: 00u2
+//!     profile: { login: sam@example.com }
+//!     appProfiles: { active_directory: { sAMAccountName: sam } }
+//! ```
+//!
+//! Users are referred to by id or login, and groups by id or name.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::oel::Value;
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Group {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `OKTA` for Okta groups, otherwise the app type or app instance id the
+    /// group is imported from.
+    #[serde(default = "okta_source")]
+    pub source: String,
+}
+
+fn okta_source() -> String {
+    "OKTA".to_string()
+}
+
+impl Group {
+    pub fn to_value(&self) -> Value {
+        Value::Object(
+            [
+                ("id", Value::Str(self.id.clone())),
+                ("name", Value::Str(self.name.clone())),
+                (
+                    "description",
+                    self.description.clone().map_or(Value::Null, Value::Str),
+                ),
+                ("source", Value::Str(self.source.clone())),
+            ]
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DirectoryUser {
+    pub id: String,
+    #[serde(default)]
+    pub profile: serde_json::Map<String, serde_json::Value>,
+    /// Ids or names of the groups the user belongs to.
+    #[serde(default)]
+    pub groups: Vec<String>,
+    /// Manager per source, e.g. `active_directory`.
+    #[serde(default)]
+    pub managers: HashMap<String, String>,
+    /// Assistant per source.
+    #[serde(default)]
+    pub assistants: HashMap<String, String>,
+    /// App user profiles per app, used by `getManagerAppUser` and friends.
+    #[serde(default)]
+    pub app_profiles: HashMap<String, serde_json::Map<String, serde_json::Value>>,
+}
+
+impl DirectoryUser {
+    pub fn login(&self) -> Option<&str> {
+        self.profile.get("login").and_then(|login| login.as_str())
+    }
+
+    pub fn profile_value(&self) -> Value {
+        Value::from(serde_json::Value::Object(self.profile.clone()))
+    }
+
+    pub fn app_profile_value(&self, app: &str) -> Value {
+        self.app_profiles.get(app).map_or(Value::Null, |profile| {
+            Value::from(serde_json::Value::Object(profile.clone()))
+        })
+    }
+}
+
+/// Where group and manager functions look users and groups up. `user` is the
+/// id or login of a user.
+pub trait Directory: Send + Sync {
+    fn user(&self, user: &str) -> Option<DirectoryUser>;
+    fn groups_of(&self, user: &str) -> Vec<Group>;
+    fn manager_of(&self, user: &str, source: &str) -> Option<DirectoryUser>;
+    fn assistant_of(&self, user: &str, source: &str) -> Option<DirectoryUser>;
+}
+
+/// A directory with no users or groups, so every membership check is false.
+pub struct NoDirectory;
+
+impl Directory for NoDirectory {
+    fn user(&self, _: &str) -> Option<DirectoryUser> {
+        None
+    }
+
+    fn groups_of(&self, _: &str) -> Vec<Group> {
+        Vec::new()
+    }
+
+    fn manager_of(&self, _: &str, _: &str) -> Option<DirectoryUser> {
+        None
+    }
+
+    fn assistant_of(&self, _: &str, _: &str) -> Option<DirectoryUser> {
+        None
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct InMemoryDirectory {
+    #[serde(default)]
+    pub groups: Vec<Group>,
+    #[serde(default)]
+    pub users: Vec<DirectoryUser>,
+}
+
+impl InMemoryDirectory {
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        serde_json::from_str(src).map_err(|err| err.to_string())
+    }
+
+    pub fn from_yaml(src: &str) -> Result<Self, String> {
+        serde_yaml::from_str(src).map_err(|err| err.to_string())
+    }
+
+    /// Loads a fixture, choosing the format from the file extension.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let directory = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Self::from_yaml(&src),
+            _ => Self::from_json(&src),
+        };
+        directory.map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    fn find_user(&self, user: &str) -> Option<&DirectoryUser> {
+        self.users
+            .iter()
+            .find(|candidate| candidate.id == user || candidate.login() == Some(user))
+    }
+
+    pub fn find_group(&self, group: &str) -> Option<&Group> {
+        self.groups
+            .iter()
+            .find(|candidate| candidate.id == group || candidate.name == group)
+    }
+}
+
+impl Directory for InMemoryDirectory {
+    fn user(&self, user: &str) -> Option<DirectoryUser> {
+        self.find_user(user).cloned()
+    }
+
+    fn groups_of(&self, user: &str) -> Vec<Group> {
+        self.find_user(user)
+            .map(|user| {
+                user.groups
+                    .iter()
+                    .filter_map(|group| self.find_group(group).cloned())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn manager_of(&self, user: &str, source: &str) -> Option<DirectoryUser> {
+        let manager = self.find_user(user)?.managers.get(source)?;
+        self.user(manager)
+    }
+
+    fn assistant_of(&self, user: &str, source: &str) -> Option<DirectoryUser> {
+        let assistant = self.find_user(user)?.assistants.get(source)?;
+        self.user(assistant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The fixture of the module docs.
+    const YAML: &str = "\
+groups:
+  - id: 00g1
+    name: Engineering
+  - id: 00g2
+    name: AD Admins
+    source: active_directory
+users:
+  - id: 00u1
+    profile: { login: jo@example.com, department: Engineering }
+    groups: [00g1, AD Admins]
+    managers: { active_directory: 00u2 }
+  - id: 00u2
+    profile: { login: sam@example.com }
+    appProfiles: { active_directory: { sAMAccountName: sam } }
+";
+
+    const JSON: &str = r#"{
+  "groups": [
+    { "id": "00g1", "name": "Engineering" },
+    { "id": "00g2", "name": "AD Admins", "source": "active_directory" }
+  ],
+  "users": [
+    {
+      "id": "00u1",
+      "profile": { "login": "jo@example.com", "department": "Engineering" },
+      "groups": ["00g1", "AD Admins"],
+      "managers": { "active_directory": "00u2" }
+    },
+    {
+      "id": "00u2",
+      "profile": { "login": "sam@example.com" },
+      "appProfiles": { "active_directory": { "sAMAccountName": "sam" } }
+    }
+  ]
+}"#;
+
+    fn ids(groups: Vec<Group>) -> Vec<String> {
+        groups.into_iter().map(|group| group.id).collect()
+    }
+
+    fn s(value: &str) -> Value {
+        Value::Str(value.to_string())
+    }
+
+    #[test]
+    fn json_and_yaml_fixtures_agree() {
+        for directory in [
+            InMemoryDirectory::from_yaml(YAML).unwrap(),
+            InMemoryDirectory::from_json(JSON).unwrap(),
+        ] {
+            let sources = directory
+                .groups
+                .iter()
+                .map(|group| group.source.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(sources, ["OKTA", "active_directory"]);
+            assert_eq!(directory.users.len(), 2);
+            assert_eq!(
+                directory.user("00u1").unwrap().profile_value(),
+                directory.user("jo@example.com").unwrap().profile_value()
+            );
+            assert_eq!(ids(directory.groups_of("jo@example.com")), ["00g1", "00g2"]);
+            assert!(directory.groups_of("sam@example.com").is_empty());
+            assert!(directory.groups_of("nobody").is_empty());
+        }
+        assert!(InMemoryDirectory::from_json("{ \"groups\": [{ \"id\": \"00g1\" }] }").is_err());
+        assert!(InMemoryDirectory::from_yaml("users: {").is_err());
+    }
+
+    #[test]
+    fn managers_and_assistants() {
+        let directory = InMemoryDirectory::from_yaml(YAML).unwrap();
+        let manager = directory.manager_of("00u1", "active_directory").unwrap();
+        assert_eq!(manager.login(), Some("sam@example.com"));
+        assert_eq!(
+            manager.app_profile_value("active_directory"),
+            Value::Object(
+                [("sAMAccountName".to_string(), s("sam"))]
+                    .into_iter()
+                    .collect()
+            )
+        );
+        assert_eq!(manager.app_profile_value("salesforce"), Value::Null);
+        assert!(directory.manager_of("jo@example.com", "OKTA").is_none());
+        assert!(directory.manager_of("00u2", "active_directory").is_none());
+        assert!(directory.assistant_of("00u1", "active_directory").is_none());
+        assert!(NoDirectory.user("00u1").is_none());
+    }
+
+    #[test]
+    fn fixtures_load_by_extension() {
+        let dir = std::env::temp_dir().join(format!("oel-directory-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let yaml = dir.join("directory.yml");
+        std::fs::write(&yaml, YAML).unwrap();
+        assert_eq!(InMemoryDirectory::load(&yaml).unwrap().groups.len(), 2);
+        // Anything but YAML is read as JSON.
+        let json = dir.join("directory.fixture");
+        std::fs::write(&json, YAML).unwrap();
+        let err = InMemoryDirectory::load(&json).unwrap_err();
+        assert!(err.starts_with(&json.display().to_string()), "{}", err);
+        assert!(InMemoryDirectory::load(&dir.join("missing.json")).is_err());
+    }
+}
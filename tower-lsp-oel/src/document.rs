@@ -0,0 +1,246 @@
+//! Applying incremental `textDocument/didChange` events to the text of a
+//! document and to its tree-sitter tree.
+
+use ropey::Rope;
+use tower_lsp::lsp_types::{Position, TextDocumentContentChangeEvent};
+use tree_sitter::{InputEdit, Point};
+
+use crate::position::PositionEncoding;
+
+/// Byte offset of an LSP position, clamped to the end of its line.
+pub fn position_to_byte(rope: &Rope, position: Position, encoding: PositionEncoding) -> usize {
+    let line = position.line as usize;
+    if line >= rope.len_lines() {
+        return rope.len_bytes();
+    }
+    rope.line_to_byte(line) + encoding.bytes_in(rope.line(line).chars(), position.character)
+}
+
+/// The tree-sitter point of a byte offset.
+pub fn byte_to_point(rope: &Rope, byte: usize) -> Point {
+    let row = rope.byte_to_line(byte);
+    Point::new(row, byte - rope.line_to_byte(row))
+}
+
+/// Applies `change` to `rope` and returns the matching edit for the tree of
+/// the old text, or `None` when the change replaces the whole document.
+pub fn apply_change(
+    rope: &mut Rope,
+    change: &TextDocumentContentChangeEvent,
+    encoding: PositionEncoding,
+) -> Option<InputEdit> {
+    let range = match change.range {
+        Some(range) => range,
+        None => {
+            *rope = Rope::from_str(&change.text);
+            return None;
+        }
+    };
+    let start_byte = position_to_byte(rope, range.start, encoding);
+    let old_end_byte = position_to_byte(rope, range.end, encoding).max(start_byte);
+    let start_position = byte_to_point(rope, start_byte);
+    let old_end_position = byte_to_point(rope, old_end_byte);
+
+    let start_char = rope.byte_to_char(start_byte);
+    rope.remove(start_char..rope.byte_to_char(old_end_byte));
+    rope.insert(start_char, &change.text);
+
+    let new_end_byte = start_byte + change.text.len();
+    Some(InputEdit {
+        start_byte,
+        old_end_byte,
+        new_end_byte,
+        start_position,
+        old_end_position,
+        new_end_position: byte_to_point(rope, new_end_byte),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_lsp::lsp_types::Range;
+    use tree_sitter::Tree;
+    use tree_sitter_traversal::{traverse, Order};
+
+    use crate::oel::parse_tree;
+    use crate::position::LineIndex;
+
+    const SRC: &str = "user.login == 'é😀'\r\n  && user.email != ''\n";
+
+    /// The start and end of a range, as line and UTF-16 character, the text
+    /// that replaces it, and the text left after the edit.
+    type Edit = ((u32, u32), (u32, u32), &'static str, &'static str);
+
+    /// The edits of [`SRC`], in order.
+    const EDITS: &[Edit] = &[
+        // After the emoji, which is two UTF-16 code units.
+        (
+            (0, 18),
+            (0, 18),
+            "x",
+            "user.login == 'é😀x'\r\n  && user.email != ''\n",
+        ),
+        (
+            (1, 10),
+            (1, 15),
+            "login",
+            "user.login == 'é😀x'\r\n  && user.login != ''\n",
+        ),
+        // Joins the lines, CRLF included.
+        (
+            (0, 20),
+            (1, 2),
+            "",
+            "user.login == 'é😀x'&& user.login != ''\n",
+        ),
+        // Past the end of the line, so before its line break.
+        (
+            (0, 99),
+            (0, 99),
+            " || user.nickName == 'ü'",
+            "user.login == 'é😀x'&& user.login != '' || user.nickName == 'ü'\n",
+        ),
+        // Past the last line, so at the end.
+        (
+            (7, 0),
+            (7, 0),
+            "|| true",
+            "user.login == 'é😀x'&& user.login != '' || user.nickName == 'ü'\n|| true",
+        ),
+        // Across a multi-byte character and the line break.
+        (
+            (0, 60),
+            (1, 2),
+            "'é'",
+            "user.login == 'é😀x'&& user.login != '' || user.nickName == 'é' true",
+        ),
+    ];
+
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(Range::new(
+                Position::new(start.0, start.1),
+                Position::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    /// Every node of `tree` with its kind and where it is.
+    fn nodes(tree: &Tree) -> Vec<(&'static str, usize, usize, Point, Point)> {
+        traverse(tree.walk(), Order::Pre)
+            .map(|node| {
+                (
+                    node.kind(),
+                    node.start_byte(),
+                    node.end_byte(),
+                    node.start_position(),
+                    node.end_position(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn positions_to_bytes() {
+        let rope = Rope::from_str(SRC);
+        let byte = |line, character, encoding| {
+            position_to_byte(&rope, Position::new(line, character), encoding)
+        };
+        // `é` is two bytes and `😀` four, two UTF-16 code units.
+        assert_eq!(byte(0, 17, PositionEncoding::Utf16), 17);
+        assert_eq!(byte(0, 18, PositionEncoding::Utf16), 21);
+        assert_eq!(byte(0, 17, PositionEncoding::Utf32), 21);
+        assert_eq!(byte(0, 21, PositionEncoding::Utf8), 21);
+        // Halfway through the emoji stays before it.
+        assert_eq!(byte(0, 17, PositionEncoding::Utf8), 17);
+        // Clamped before the CRLF.
+        assert_eq!(byte(0, 99, PositionEncoding::Utf16), 22);
+        assert_eq!(byte(1, 0, PositionEncoding::Utf16), 24);
+        assert_eq!(byte(2, 0, PositionEncoding::Utf16), SRC.len());
+        assert_eq!(byte(9, 0, PositionEncoding::Utf16), SRC.len());
+
+        assert_eq!(byte_to_point(&rope, 21), Point::new(0, 21));
+        assert_eq!(byte_to_point(&rope, 26), Point::new(1, 2));
+        assert_eq!(byte_to_point(&rope, SRC.len()), Point::new(2, 0));
+    }
+
+    #[test]
+    fn ranged_edits_apply_to_the_rope() {
+        let mut rope = Rope::from_str(SRC);
+        let mut edits = Vec::new();
+        for &(start, end, text, expected) in EDITS {
+            let old = rope.to_string();
+            let edit = apply_change(
+                &mut rope,
+                &change(start, end, text),
+                PositionEncoding::Utf16,
+            )
+            .unwrap();
+            let new = rope.to_string();
+            assert_eq!(new, expected);
+            // The edit describes the change in both the old and new text.
+            assert_eq!(&old[..edit.start_byte], &new[..edit.start_byte]);
+            assert_eq!(&old[edit.old_end_byte..], &new[edit.new_end_byte..]);
+            assert_eq!(&new[edit.start_byte..edit.new_end_byte], text);
+            let old_index = LineIndex::new(&old, PositionEncoding::Utf16);
+            let new_index = LineIndex::new(&new, PositionEncoding::Utf16);
+            assert_eq!(edit.start_position, old_index.point_at(edit.start_byte));
+            assert_eq!(edit.old_end_position, old_index.point_at(edit.old_end_byte));
+            assert_eq!(edit.new_end_position, new_index.point_at(edit.new_end_byte));
+            edits.push(edit);
+        }
+        assert_eq!(
+            (
+                edits[0].start_byte,
+                edits[0].old_end_byte,
+                edits[0].new_end_byte
+            ),
+            (21, 21, 22)
+        );
+        assert_eq!(
+            (
+                edits[2].start_position,
+                edits[2].old_end_position,
+                edits[2].new_end_position
+            ),
+            (Point::new(0, 23), Point::new(1, 2), Point::new(0, 23))
+        );
+
+        let whole = TextDocumentContentChangeEvent {
+            range: None,
+            range_length: None,
+            text: SRC.to_string(),
+        };
+        assert_eq!(
+            apply_change(&mut rope, &whole, PositionEncoding::Utf16),
+            None
+        );
+        assert_eq!(rope.to_string(), SRC);
+    }
+
+    #[test]
+    fn incremental_reparses_match_full_ones() {
+        let mut rope = Rope::from_str(SRC);
+        let mut tree = parse_tree(SRC, None).unwrap();
+        for &(start, end, text, _) in EDITS {
+            let edit = apply_change(
+                &mut rope,
+                &change(start, end, text),
+                PositionEncoding::Utf16,
+            )
+            .unwrap();
+            tree.edit(&edit);
+            let src = rope.to_string();
+            tree = parse_tree(&src, Some(&tree)).unwrap();
+            assert_eq!(
+                nodes(&tree),
+                nodes(&parse_tree(&src, None).unwrap()),
+                "{}",
+                src
+            );
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! The outline of a document: a symbol per entry of the file, with the
+//! branches of ternaries, the clauses of `AND`/`OR` chains and the calls
+//! nested below it. Long `String.stringSwitch` mappings get a symbol per
+//! key.
+
+use tower_lsp::lsp_types::{DocumentSymbol, SymbolKind};
+
+use crate::eval::ROOTS;
+use crate::oel::{BinaryOp, Entry, Expr, Span, Spanned};
+use crate::position::LineIndex;
+
+/// Longest expression shown as a name or detail.
+const MAX_DETAIL: usize = 60;
+
+/// The entries of a file, in source order. The entry of a file holding a
+/// single expression is named after the expression.
+pub fn document_symbols(entries: &[Entry], index: &LineIndex) -> Vec<DocumentSymbol> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (name, detail, selection) = match &entry.name {
+                Some((name, span)) => (name.clone(), Some(abbreviate(&entry.source)), span),
+                None => (abbreviate(&entry.source), None, &entry.span),
+            };
+            symbol(
+                name,
+                detail,
+                SymbolKind::FUNCTION,
+                (&entry.span, selection),
+                outline(&entry.value, index),
+                index,
+            )
+        })
+        .collect()
+}
+
+/// The symbols below `expr`.
+fn outline(expr: &Spanned<Expr>, index: &LineIndex) -> Vec<DocumentSymbol> {
+    let text = |(_, span): &Spanned<Expr>| abbreviate(index.text(span.start, span.end));
+    match &expr.0 {
+        Expr::Ternary { .. } => {
+            // `a ? x : b ? y : z` reads as a list of cases, not a tree.
+            let mut branches = Vec::new();
+            let mut rest = expr;
+            while let (
+                Expr::Ternary {
+                    condition,
+                    consequence,
+                    alternative,
+                },
+                _,
+            ) = rest
+            {
+                let span = Span {
+                    end: consequence.1.end,
+                    ..condition.1.clone()
+                };
+                let mut children = outline(condition, index);
+                children.extend(outline(consequence, index));
+                branches.push(symbol(
+                    text(condition),
+                    Some(text(consequence)),
+                    SymbolKind::BOOLEAN,
+                    (&span, &condition.1),
+                    children,
+                    index,
+                ));
+                rest = alternative;
+            }
+            branches.push(symbol(
+                "else".to_string(),
+                Some(text(rest)),
+                SymbolKind::BOOLEAN,
+                (&rest.1, &rest.1),
+                outline(rest, index),
+                index,
+            ));
+            branches
+        }
+        Expr::Binary {
+            op: op @ (BinaryOp::And | BinaryOp::Or),
+            ..
+        } => {
+            let mut clauses = Vec::new();
+            collect_clauses(expr, *op, &mut clauses);
+            let children = clauses
+                .into_iter()
+                .map(|clause| {
+                    symbol(
+                        text(clause),
+                        None,
+                        SymbolKind::BOOLEAN,
+                        (&clause.1, &clause.1),
+                        outline(clause, index),
+                        index,
+                    )
+                })
+                .collect();
+            vec![symbol(
+                op.to_string(),
+                None,
+                SymbolKind::OPERATOR,
+                (&expr.1, &expr.1),
+                children,
+                index,
+            )]
+        }
+        Expr::Call {
+            function,
+            arguments,
+        } => {
+            let (name, kind) = match &function.0 {
+                // A method of a value rather than a function of a namespace.
+                Expr::Member { object, property }
+                    if object.0.path().map_or(true, |path| {
+                        ROOTS.contains(&path.split('.').next().unwrap_or_default())
+                    }) =>
+                {
+                    (property.0.clone(), SymbolKind::METHOD)
+                }
+                callee => (
+                    callee.path().unwrap_or_else(|| text(function)),
+                    SymbolKind::FUNCTION,
+                ),
+            };
+            let mut children = outline(function, index);
+            match (name.as_str(), arguments.as_slice()) {
+                ("String.stringSwitch", [input, default, cases @ ..]) => {
+                    children.extend(outline(input, index));
+                    children.push(symbol(
+                        "default".to_string(),
+                        Some(text(default)),
+                        SymbolKind::KEY,
+                        (&default.1, &default.1),
+                        outline(default, index),
+                        index,
+                    ));
+                    for case in cases.chunks(2) {
+                        let (key, value) = match case {
+                            [key, value] => (key, Some(value)),
+                            [key] => (key, None),
+                            _ => continue,
+                        };
+                        children.push(symbol(
+                            text(key),
+                            value.map(text),
+                            SymbolKind::KEY,
+                            (&key.1, &key.1),
+                            value.map(|value| outline(value, index)).unwrap_or_default(),
+                            index,
+                        ));
+                    }
+                }
+                _ => children.extend(
+                    arguments
+                        .iter()
+                        .flat_map(|argument| outline(argument, index)),
+                ),
+            }
+            vec![symbol(
+                name,
+                Some(text(expr)),
+                kind,
+                (&expr.1, &function.1),
+                children,
+                index,
+            )]
+        }
+        _ => expr
+            .0
+            .children()
+            .into_iter()
+            .flat_map(|child| outline(child, index))
+            .collect(),
+    }
+}
+
+/// The operands of a chain of the same logical operator, `a AND b AND c`.
+fn collect_clauses<'a>(
+    expr: &'a Spanned<Expr>,
+    op: BinaryOp,
+    clauses: &mut Vec<&'a Spanned<Expr>>,
+) {
+    match &expr.0 {
+        Expr::Binary {
+            op: inner,
+            left,
+            right,
+        } if *inner == op => {
+            collect_clauses(left, op, clauses);
+            collect_clauses(right, op, clauses);
+        }
+        _ => clauses.push(expr),
+    }
+}
+
+fn symbol(
+    name: String,
+    detail: Option<String>,
+    kind: SymbolKind,
+    (range, selection): (&Span, &Span),
+    children: Vec<DocumentSymbol>,
+    index: &LineIndex,
+) -> DocumentSymbol {
+    // `deprecated` is superseded by `tags`, but has to be set.
+    #[allow(deprecated)]
+    DocumentSymbol {
+        // Clients reject symbols without a name.
+        name: if name.is_empty() {
+            "...".to_string()
+        } else {
+            name
+        },
+        detail,
+        kind,
+        tags: None,
+        deprecated: None,
+        range: index.range(range.start, range.end),
+        selection_range: index.range(selection.start, selection.end),
+        children: Some(children).filter(|children| !children.is_empty()),
+    }
+}
+
+/// `source` on one line, shortened to [`MAX_DETAIL`] characters.
+fn abbreviate(source: &str) -> String {
+    let line = source.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() > MAX_DETAIL {
+        format!("{}...", line.chars().take(MAX_DETAIL).collect::<String>())
+    } else {
+        line
+    }
+}
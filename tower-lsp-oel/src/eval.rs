@@ -0,0 +1,605 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+
+use regex::Regex;
+
+use crate::builtins::{Builtin, Call, Registry};
+use crate::dialect::Dialect;
+use crate::directory::{Directory, NoDirectory};
+use crate::expression_context::ExpressionContext;
+use crate::oel::{BinaryOp, Expr, Span, Spanned, UnaryOp, Value};
+
+/// The objects an expression can be evaluated against. Which of them are
+/// available depends on the [`ExpressionContext`].
+pub const ROOTS: &[&str] = &[
+    "user", "appuser", "idpuser", "app", "org", "session", "source", "access", "security", "device",
+];
+
+/// The `user`, `app` and other root objects that an expression is evaluated
+/// against. Roots that are not supplied evaluate to `null`, as they do in
+/// Okta.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    roots: HashMap<String, Value>,
+}
+
+impl Context {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, root: &str, value: Value) -> Self {
+        self.roots.insert(root.to_string(), value);
+        self
+    }
+
+    /// Builds a context from a JSON object keyed by root name, e.g.
+    /// `{ "user": { "login": "jo@example.com" } }`.
+    pub fn from_json(json: serde_json::Value) -> std::result::Result<Self, String> {
+        match json {
+            serde_json::Value::Object(roots) => Ok(Context {
+                roots: roots
+                    .into_iter()
+                    .map(|(root, value)| (root, Value::from(value)))
+                    .collect(),
+            }),
+            other => Err(format!(
+                "expected the context to be an object, found {}",
+                Value::from(other).type_name()
+            )),
+        }
+    }
+
+    pub fn get(&self, root: &str) -> Option<&Value> {
+        self.roots.get(root)
+    }
+
+    /// The id, or failing that the login, of `user`, used to look the user up
+    /// in a [`Directory`].
+    pub fn user_key(&self) -> Option<&str> {
+        match self.get("user")? {
+            Value::Object(fields) => {
+                ["id", "login"]
+                    .iter()
+                    .find_map(|key| match fields.get(*key) {
+                        Some(Value::Str(value)) => Some(value.as_str()),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalError {
+    pub span: Span,
+    pub msg: String,
+}
+
+impl EvalError {
+    pub fn new(span: &Span, msg: impl Into<String>) -> Self {
+        EvalError {
+            span: span.clone(),
+            msg: msg.into(),
+        }
+    }
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.start.row + 1,
+            self.span.start.column + 1,
+            self.msg
+        )
+    }
+}
+
+pub type Result<T> = std::result::Result<T, EvalError>;
+
+/// Evaluates `expr` against `context` with the default built-in functions.
+pub fn eval(expr: &Spanned<Expr>, context: &Context) -> Result<Value> {
+    Interpreter::new(context, &Registry::default()).eval(expr)
+}
+
+pub struct Interpreter<'a> {
+    context: &'a Context,
+    registry: &'a Registry,
+    directory: &'a dyn Directory,
+    expression_context: ExpressionContext,
+    dialect: Dialect,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(context: &'a Context, registry: &'a Registry) -> Self {
+        Interpreter {
+            context,
+            registry,
+            directory: &NoDirectory,
+            expression_context: ExpressionContext::Any,
+            dialect: Dialect::Classic,
+        }
+    }
+
+    pub fn with_directory(mut self, directory: &'a dyn Directory) -> Self {
+        self.directory = directory;
+        self
+    }
+
+    /// Rejects roots and functions that are not available in `context`.
+    pub fn with_expression_context(mut self, context: ExpressionContext) -> Self {
+        self.expression_context = context;
+        self
+    }
+
+    /// Enables the Identity Engine methods on values and `user.profile`.
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    fn unavailable(&self, span: &Span, name: &str) -> EvalError {
+        EvalError::new(
+            span,
+            format!(
+                "`{}` is not available in {}",
+                name,
+                self.expression_context.name().to_lowercase()
+            ),
+        )
+    }
+
+    pub fn eval(&self, expr: &Spanned<Expr>) -> Result<Value> {
+        let (expr, span) = expr;
+        match expr {
+            Expr::Error => Err(EvalError::new(
+                span,
+                "cannot evaluate an expression with syntax errors",
+            )),
+            Expr::Value(value) => Ok(value.clone()),
+            Expr::Ident(name)
+                if ROOTS.contains(&name.as_str()) && !self.expression_context.allows_root(name) =>
+            {
+                Err(self.unavailable(span, name))
+            }
+            Expr::Ident(name) => match self.context.get(name) {
+                Some(value) => Ok(value.clone()),
+                None if ROOTS.contains(&name.as_str()) => Ok(Value::Null),
+                None => Err(EvalError::new(
+                    span,
+                    format!("unknown identifier `{}`", name),
+                )),
+            },
+            Expr::Array(items) => Ok(Value::List(
+                items
+                    .iter()
+                    .map(|item| self.eval(item))
+                    .collect::<Result<_>>()?,
+            )),
+            Expr::Map(entries) => Ok(Value::Object(
+                entries
+                    .iter()
+                    .map(|((key, _), value)| Ok((key.name.clone(), self.eval(value)?)))
+                    .collect::<Result<_>>()?,
+            )),
+            // Identity Engine keeps profile attributes under `user.profile`,
+            // while the context holds them directly on `user`.
+            Expr::Member { object, property }
+                if self.dialect == Dialect::IdentityEngine
+                    && property.0 == "profile"
+                    && matches!(&object.0, Expr::Ident(root) if ROOTS.contains(&root.as_str())) =>
+            {
+                match self.eval(object)? {
+                    Value::Object(mut fields) => {
+                        Ok(fields.remove("profile").unwrap_or(Value::Object(fields)))
+                    }
+                    other => Ok(other),
+                }
+            }
+            Expr::Member { object, property } => match self.eval(object)? {
+                Value::Null => Ok(Value::Null),
+                Value::Object(fields) => {
+                    Ok(fields.get(&property.0).cloned().unwrap_or(Value::Null))
+                }
+                other => Err(EvalError::new(
+                    &property.1,
+                    format!(
+                        "cannot access `{}` on a value of type {}",
+                        property.0,
+                        other.type_name()
+                    ),
+                )),
+            },
+            Expr::Subscript { object, index } => {
+                let object = self.eval(object)?;
+                let index_value = self.eval(index)?;
+                match (object, index_value) {
+                    (Value::Null, _) => Ok(Value::Null),
+                    (Value::List(items), Value::Int(i)) => Ok(usize::try_from(i)
+                        .ok()
+                        .and_then(|i| items.get(i).cloned())
+                        .unwrap_or(Value::Null)),
+                    (Value::List(_), other) => Err(EvalError::new(
+                        &index.1,
+                        format!("expected an Integer index, found {}", other.type_name()),
+                    )),
+                    (other, _) => Err(EvalError::new(
+                        span,
+                        format!("cannot index a value of type {}", other.type_name()),
+                    )),
+                }
+            }
+            Expr::Call {
+                function,
+                arguments,
+            } => {
+                let path = function.0.path();
+                if let (Dialect::IdentityEngine, Expr::Member { object, property }) =
+                    (self.dialect, &function.0)
+                {
+                    if path
+                        .as_ref()
+                        .map_or(true, |name| self.registry.get(name).is_none())
+                    {
+                        return self.eval_method(object, property, arguments, span);
+                    }
+                }
+                let name =
+                    path.ok_or_else(|| EvalError::new(&function.1, "expression is not callable"))?;
+                let builtin = self.registry.get(&name).ok_or_else(|| {
+                    EvalError::new(&function.1, format!("unknown function `{}`", name))
+                })?;
+                if !self.expression_context.allows_function(&name) {
+                    return Err(self.unavailable(&function.1, &name));
+                }
+                let args = arguments
+                    .iter()
+                    .map(|argument| Ok((self.eval(argument)?, argument.1.clone())))
+                    .collect::<Result<Vec<_>>>()?;
+                self.call(builtin, &name, args, span)
+            }
+            Expr::Unary { op, argument } => match (op, self.eval(argument)?) {
+                (_, Value::Null) => Ok(Value::Null),
+                (UnaryOp::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+                (UnaryOp::Not, other) => Err(EvalError::new(
+                    &argument.1,
+                    format!("expected a Boolean, found {}", other.type_name()),
+                )),
+                (UnaryOp::Neg, Value::Int(x)) => x
+                    .checked_neg()
+                    .map(Value::Int)
+                    .ok_or_else(|| EvalError::new(span, "integer overflow")),
+                (UnaryOp::Neg, Value::Num(x)) => Ok(Value::Num(-x)),
+                (UnaryOp::Neg, other) => Err(EvalError::new(
+                    &argument.1,
+                    format!("expected a number, found {}", other.type_name()),
+                )),
+            },
+            Expr::Binary { op, left, right } => self.eval_binary(*op, left, right, span),
+            Expr::Ternary {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                if self.truthy(condition)? {
+                    self.eval(consequence)
+                } else {
+                    self.eval(alternative)
+                }
+            }
+        }
+    }
+
+    fn call(
+        &self,
+        builtin: &Builtin,
+        name: &str,
+        args: Vec<Spanned<Value>>,
+        span: &Span,
+    ) -> Result<Value> {
+        (builtin.func)(&Call {
+            name,
+            span,
+            args: &args,
+            clock: self.registry.clock(),
+            directory: self.directory,
+            user: self.context.user_key(),
+        })
+    }
+
+    /// Calls an Identity Engine method, passing the value it is called on as
+    /// the first argument. Methods on `null` return `null`, as attributes
+    /// that are not set would otherwise fail the whole expression.
+    fn eval_method(
+        &self,
+        object: &Spanned<Expr>,
+        (method, method_span): &Spanned<String>,
+        arguments: &[Spanned<Expr>],
+        span: &Span,
+    ) -> Result<Value> {
+        let receiver = self.eval(object)?;
+        let receiver_type = match (&object.0, &receiver) {
+            (_, Value::Null) => return Ok(Value::Null),
+            (Expr::Ident(root), _) if root == "user" => "User",
+            (_, value) => value.type_name(),
+        };
+        let builtin = self.registry.method(receiver_type, method).ok_or_else(|| {
+            EvalError::new(
+                method_span,
+                format!("unknown method `{}` on {}", method, receiver_type),
+            )
+        })?;
+        let args = std::iter::once(Ok((receiver, object.1.clone())))
+            .chain(
+                arguments
+                    .iter()
+                    .map(|argument| Ok((self.eval(argument)?, argument.1.clone()))),
+            )
+            .collect::<Result<Vec<_>>>()?;
+        self.call(builtin, builtin.name, args, span)
+    }
+
+    /// Conditions treat `null` as false, so that a missing attribute does not
+    /// fail the whole rule.
+    fn truthy(&self, expr: &Spanned<Expr>) -> Result<bool> {
+        match self.eval(expr)? {
+            Value::Bool(b) => Ok(b),
+            Value::Null => Ok(false),
+            other => Err(EvalError::new(
+                &expr.1,
+                format!("expected a Boolean, found {}", other.type_name()),
+            )),
+        }
+    }
+
+    fn eval_binary(
+        &self,
+        op: BinaryOp,
+        left: &Spanned<Expr>,
+        right: &Spanned<Expr>,
+        span: &Span,
+    ) -> Result<Value> {
+        // AND and OR short-circuit, so the right hand side is only evaluated when needed.
+        match op {
+            BinaryOp::And => {
+                return Ok(Value::Bool(self.truthy(left)? && self.truthy(right)?));
+            }
+            BinaryOp::Or => {
+                return Ok(Value::Bool(self.truthy(left)? || self.truthy(right)?));
+            }
+            _ => {}
+        }
+
+        let lhs = self.eval(left)?;
+        let rhs = self.eval(right)?;
+        match op {
+            BinaryOp::Eq => Ok(Value::Bool(values_equal(&lhs, &rhs))),
+            BinaryOp::NotEq => Ok(Value::Bool(!values_equal(&lhs, &rhs))),
+            BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+                let ordering = match (&lhs, &rhs) {
+                    // Comparisons against a missing value never match.
+                    (Value::Null, _) | (_, Value::Null) => return Ok(Value::Bool(false)),
+                    (Value::Str(a), Value::Str(b)) => a.cmp(b),
+                    _ => match (as_f64(&lhs), as_f64(&rhs)) {
+                        (Some(a), Some(b)) => a.partial_cmp(&b).unwrap_or(Ordering::Equal),
+                        _ => {
+                            return Err(EvalError::new(
+                                span,
+                                format!(
+                                    "cannot compare {} with {}",
+                                    lhs.type_name(),
+                                    rhs.type_name()
+                                ),
+                            ))
+                        }
+                    },
+                };
+                Ok(Value::Bool(match op {
+                    BinaryOp::Lt => ordering == Ordering::Less,
+                    BinaryOp::LtEq => ordering != Ordering::Greater,
+                    BinaryOp::Gt => ordering == Ordering::Greater,
+                    _ => ordering != Ordering::Less,
+                }))
+            }
+            // `+` concatenates as soon as either side is a String, rendering
+            // null as "null" the way Okta does.
+            BinaryOp::Add => match (&lhs, &rhs) {
+                (Value::Str(_), _) | (_, Value::Str(_)) => {
+                    Ok(Value::Str(format!("{}{}", lhs, rhs)))
+                }
+                _ => arithmetic(op, lhs, rhs, span),
+            },
+            // Like Java's `String.matches`, the whole string has to match.
+            BinaryOp::Matches => match (&lhs, &rhs) {
+                (Value::Null, _) | (_, Value::Null) => Ok(Value::Bool(false)),
+                (Value::Str(input), Value::Str(pattern)) => {
+                    let regex = Regex::new(&format!("^(?:{})$", pattern)).map_err(|err| {
+                        EvalError::new(&right.1, format!("invalid regular expression: {}", err))
+                    })?;
+                    Ok(Value::Bool(regex.is_match(input)))
+                }
+                _ => Err(EvalError::new(
+                    span,
+                    format!(
+                        "cannot apply `matches` to {} and {}",
+                        lhs.type_name(),
+                        rhs.type_name()
+                    ),
+                )),
+            },
+            BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => {
+                arithmetic(op, lhs, rhs, span)
+            }
+            BinaryOp::And | BinaryOp::Or => unreachable!("handled above"),
+        }
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Int(x) => Some(*x as f64),
+        Value::Num(x) => Some(*x),
+        _ => None,
+    }
+}
+
+/// Integers and Numbers compare by value, everything else structurally.
+pub fn values_equal(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Int(_), Value::Num(_)) | (Value::Num(_), Value::Int(_)) => {
+            as_f64(lhs) == as_f64(rhs)
+        }
+        _ => lhs == rhs,
+    }
+}
+
+/// Java semantics: Integers stay Integers, dividing towards zero and failing
+/// on overflow, while a Number on either side makes the result a Number.
+fn arithmetic(op: BinaryOp, lhs: Value, rhs: Value, span: &Span) -> Result<Value> {
+    match (&lhs, &rhs) {
+        (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+        (Value::Int(a), Value::Int(b)) => {
+            let result = match op {
+                BinaryOp::Add => a.checked_add(*b),
+                BinaryOp::Sub => a.checked_sub(*b),
+                BinaryOp::Mul => a.checked_mul(*b),
+                _ if *b == 0 => return Err(EvalError::new(span, "division by zero")),
+                BinaryOp::Mod => a.checked_rem(*b),
+                _ => a.checked_div(*b),
+            };
+            result
+                .map(Value::Int)
+                .ok_or_else(|| EvalError::new(span, "integer overflow"))
+        }
+        _ => match (as_f64(&lhs), as_f64(&rhs)) {
+            (Some(a), Some(b)) => Ok(Value::Num(match op {
+                BinaryOp::Add => a + b,
+                BinaryOp::Sub => a - b,
+                BinaryOp::Mul => a * b,
+                BinaryOp::Mod => a % b,
+                _ => a / b,
+            })),
+            _ => Err(EvalError::new(
+                span,
+                format!(
+                    "cannot apply `{}` to {} and {}",
+                    op,
+                    lhs.type_name(),
+                    rhs.type_name()
+                ),
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oel::parse;
+
+    fn run(src: &str, context: &Context) -> Result<Value> {
+        let (entries, errors) = parse(src);
+        assert!(errors.is_empty(), "{}: {:?}", src, errors);
+        eval(&entries[0].value, context)
+    }
+
+    fn value(src: &str) -> Value {
+        run(src, &Context::new()).unwrap()
+    }
+
+    fn error(src: &str) -> String {
+        run(src, &Context::new()).unwrap_err().msg
+    }
+
+    #[test]
+    fn addition_and_concatenation() {
+        assert_eq!(value("1 + 2"), Value::Int(3));
+        assert_eq!(value("1 + 0.5"), Value::Num(1.5));
+        assert_eq!(value("\"a\" + 1"), Value::Str("a1".into()));
+        assert_eq!(value("\"a\" + null"), Value::Str("anull".into()));
+        assert_eq!(error("9223372036854775807 + 1"), "integer overflow");
+        assert_eq!(error("true + 1"), "cannot apply `+` to Boolean and Integer");
+    }
+
+    #[test]
+    fn comparisons_and_logic() {
+        assert_eq!(value("1 == 1.0"), Value::Bool(true));
+        assert_eq!(value("\"b\" > \"a\" AND 1 <= 2"), Value::Bool(true));
+        assert_eq!(value("null < 1 OR null >= 1"), Value::Bool(false));
+        assert_eq!(
+            value("!(1 != 1) ? \"yes\" : \"no\""),
+            Value::Str("yes".into())
+        );
+        assert_eq!(error("\"a\" < 1"), "cannot compare String with Integer");
+        // The right hand side is never evaluated.
+        assert_eq!(value("false AND missing"), Value::Bool(false));
+    }
+
+    #[test]
+    fn arithmetic_follows_java() {
+        assert_eq!(value("7 / 2"), Value::Int(3));
+        assert_eq!(value("-7 / 2"), Value::Int(-3));
+        assert_eq!(value("-7 % 3"), Value::Int(-1));
+        assert_eq!(value("7 / 2.0"), Value::Num(3.5));
+        assert_eq!(value("2 + 3 * 4 % 5"), Value::Int(4));
+        assert_eq!(value("-(1 - 3)"), Value::Int(2));
+        assert_eq!(value("1 / 0.0"), Value::Num(f64::INFINITY));
+        assert_eq!(value("null * 2"), Value::Null);
+        assert_eq!(error("1 / 0"), "division by zero");
+        assert_eq!(error("1 % 0"), "division by zero");
+        assert_eq!(error("-\"a\""), "expected a number, found String");
+        assert_eq!(error("!1"), "expected a Boolean, found Integer");
+    }
+
+    #[test]
+    fn arrays() {
+        assert_eq!(value("{1, 2}[1]"), Value::Int(2));
+        assert_eq!(value("{1, 2}[2]"), Value::Null);
+        assert_eq!(error("1[0]"), "cannot index a value of type Integer");
+    }
+
+    #[test]
+    fn context_and_null() {
+        let user = [("department".to_string(), Value::Str("Eng".into()))];
+        let context = Context::new().with("user", Value::Object(user.into_iter().collect()));
+        let value = |src| run(src, &context).unwrap();
+        assert_eq!(value("user.department == \"Eng\""), Value::Bool(true));
+        assert_eq!(value("user.title"), Value::Null);
+        assert_eq!(value("user.title.name"), Value::Null);
+        assert_eq!(value("org.name"), Value::Null);
+        assert_eq!(
+            run("missing", &context).unwrap_err().msg,
+            "unknown identifier `missing`"
+        );
+        assert_eq!(
+            run("user.department.name", &context).unwrap_err().msg,
+            "cannot access `name` on a value of type String"
+        );
+    }
+
+    #[test]
+    fn json_context() {
+        let json = serde_json::json!({ "user": { "login": "jo", "age": 42, "score": 1.5 } });
+        let context = Context::from_json(json).unwrap();
+        let value = |src| run(src, &context).unwrap();
+        assert_eq!(value("user.login"), Value::Str("jo".into()));
+        assert_eq!(value("user.age"), Value::Int(42));
+        assert_eq!(value("user.score"), Value::Num(1.5));
+        assert_eq!(
+            Context::from_json(serde_json::json!([])).unwrap_err(),
+            "expected the context to be an object, found Array"
+        );
+    }
+
+    #[test]
+    fn string_literals() {
+        assert_eq!(value(r#""a" + "b" == "ab""#), Value::Bool(true));
+        assert_eq!(value(r#""Engineering""#), Value::Str("Engineering".into()));
+        assert_eq!(value(r"'It\'s' + 'é\t'"), Value::Str("It's é\t".into()));
+        assert_eq!(value(r#""\uD83D\uDE00""#), Value::Str("😀".into()));
+    }
+}
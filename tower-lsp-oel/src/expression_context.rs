@@ -0,0 +1,311 @@
+//! Where an expression is used, which decides the roots and functions it
+//! may use: a group rule cannot read `appuser`, a token claim cannot call
+//! `getManagerUser()`, and so on. What each context allows is data in the
+//! [`Catalogue`].
+//!
+//! A file picks its context, in order of precedence, with
+//!
+//! * a pragma comment at the top of the file, `// @context group-rule`,
+//! * a second extension, `rules/engineering.grouprule.oel`,
+//! * `"context": "group-rule"` in `.oel.json`.
+//!
+//! Otherwise it is [`ExpressionContext::Any`] and nothing is restricted.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tree_sitter::Point;
+
+use crate::catalogue::Catalogue;
+use crate::eval::ROOTS;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ExpressionContext {
+    /// No context chosen: every root and function is allowed.
+    #[default]
+    Any,
+    GroupRule,
+    ProfileMapping,
+    AttributeStatement,
+    /// Identity Engine policy conditions.
+    Policy,
+    /// Custom claims of an authorization server.
+    TokenClaim,
+}
+
+pub const PRAGMA: &str = "@context";
+
+impl ExpressionContext {
+    pub const ALL: &'static [ExpressionContext] = &[
+        ExpressionContext::GroupRule,
+        ExpressionContext::ProfileMapping,
+        ExpressionContext::AttributeStatement,
+        ExpressionContext::Policy,
+        ExpressionContext::TokenClaim,
+    ];
+
+    /// The id used in pragmas, config and the catalogue, e.g. `group-rule`.
+    pub fn id(self) -> &'static str {
+        match self {
+            ExpressionContext::Any => "any",
+            ExpressionContext::GroupRule => "group-rule",
+            ExpressionContext::ProfileMapping => "profile-mapping",
+            ExpressionContext::AttributeStatement => "attribute-statement",
+            ExpressionContext::Policy => "policy",
+            ExpressionContext::TokenClaim => "token-claim",
+        }
+    }
+
+    /// Parses an id, with or without its dashes (`grouprule` is the form
+    /// used in file extensions).
+    pub fn from_id(id: &str) -> Option<Self> {
+        let id = id.trim();
+        std::iter::once(ExpressionContext::Any)
+            .chain(Self::ALL.iter().copied())
+            .find(|context| context.id() == id || context.id().replace('-', "") == id)
+    }
+
+    /// The display name, e.g. "Group rules".
+    pub fn name(self) -> &'static str {
+        match self {
+            ExpressionContext::Any => "any context",
+            context => Catalogue::get().context_name(context.id()),
+        }
+    }
+
+    /// The context named by a `// @context <id>` comment before the
+    /// expression, and the row of that comment.
+    pub fn from_pragma(src: &str) -> Option<(Self, usize)> {
+        let (id, row) = pragma(src, PRAGMA)?;
+        Self::from_id(id).map(|context| (context, row))
+    }
+
+    /// The context named by the second extension of `path`, as in
+    /// `engineering.grouprule.oel`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let stem = Path::new(path.file_stem()?);
+        Self::from_id(stem.extension()?.to_str()?)
+    }
+
+    /// The context of a file: its pragma, else its extension, else `default`
+    /// (from the configuration).
+    pub fn detect(path: Option<&Path>, src: &str, default: Self) -> Self {
+        Self::from_pragma(src)
+            .map(|(context, _)| context)
+            .or_else(|| path.and_then(Self::from_path))
+            .unwrap_or(default)
+    }
+
+    /// The roots available in this context.
+    pub fn roots(self) -> Vec<&'static str> {
+        ROOTS
+            .iter()
+            .copied()
+            .filter(|root| self.allows_root(root))
+            .collect()
+    }
+
+    pub fn allows_root(self, root: &str) -> bool {
+        match (self, Catalogue::get().root(root)) {
+            (ExpressionContext::Any, _) | (_, None) => true,
+            (context, Some(doc)) => doc.contexts.iter().any(|id| id == context.id()),
+        }
+    }
+
+    /// Whether the built-in `function` can be called. Functions the catalogue
+    /// does not document are not restricted.
+    pub fn allows_function(self, function: &str) -> bool {
+        match (self, Catalogue::get().function(function)) {
+            (ExpressionContext::Any, _) | (_, None) => true,
+            (context, Some(doc)) => doc.contexts.iter().any(|id| id == context.id()),
+        }
+    }
+
+    /// The contexts in which `root` is available.
+    pub fn with_root(root: &str) -> Vec<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(|context| context.allows_root(root))
+            .collect()
+    }
+
+    /// The contexts in which `function` can be called.
+    pub fn with_function(function: &str) -> Vec<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .filter(|context| context.allows_function(function))
+            .collect()
+    }
+}
+
+impl fmt::Display for ExpressionContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl Serialize for ExpressionContext {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for ExpressionContext {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let id = String::deserialize(deserializer)?;
+        Self::from_id(&id).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "unknown expression context `{}`, expected one of {}",
+                id,
+                Self::ALL
+                    .iter()
+                    .map(|context| context.id())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ))
+        })
+    }
+}
+
+/// The value of the `// <name> <value>` comment before the expression, and
+/// the row of that comment. `#` comments are read the same way.
+pub fn pragma<'a>(src: &'a str, name: &str) -> Option<(&'a str, usize)> {
+    for (row, line) in src.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let comment = line
+            .strip_prefix("//")
+            .or_else(|| line.strip_prefix('#'))?
+            .trim();
+        if let Some(value) = comment.strip_prefix(name) {
+            return Some((value.trim(), row));
+        }
+    }
+    None
+}
+
+/// The edit setting the `name` pragma of `src` to `value`: the region to
+/// replace and its new text. An existing pragma is replaced, otherwise one
+/// is inserted on the first line.
+pub fn pragma_edit(src: &str, name: &str, value: &str) -> (Point, Point, String) {
+    let pragma_line = format!("// {} {}", name, value);
+    match pragma(src, name) {
+        Some((_, row)) => {
+            let len = src.lines().nth(row).map_or(0, str::len);
+            (Point::new(row, 0), Point::new(row, len), pragma_line)
+        }
+        None => (Point::new(0, 0), Point::new(0, 0), pragma_line + "\n"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_with_or_without_dashes() {
+        assert_eq!(
+            ExpressionContext::from_id("group-rule"),
+            Some(ExpressionContext::GroupRule)
+        );
+        assert_eq!(
+            ExpressionContext::from_id(" tokenclaim "),
+            Some(ExpressionContext::TokenClaim)
+        );
+        assert_eq!(
+            ExpressionContext::from_id("any"),
+            Some(ExpressionContext::Any)
+        );
+        assert_eq!(ExpressionContext::from_id("group_rule"), None);
+        assert_eq!(ExpressionContext::GroupRule.name(), "Group rules");
+        assert_eq!(
+            serde_json::from_str::<ExpressionContext>("\"policy\"").unwrap(),
+            ExpressionContext::Policy
+        );
+        let err = serde_json::from_str::<ExpressionContext>("\"rule\"").unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("unknown expression context `rule`, expected one of group-rule"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn detect_follows_the_precedence() {
+        let path = Path::new("rules/engineering.grouprule.oel");
+        assert_eq!(
+            ExpressionContext::from_path(path),
+            Some(ExpressionContext::GroupRule)
+        );
+        assert_eq!(ExpressionContext::from_path(Path::new("a.oel")), None);
+        let pragma = "\n// @context token-claim\nuser.login";
+        assert_eq!(
+            ExpressionContext::from_pragma(pragma),
+            Some((ExpressionContext::TokenClaim, 1))
+        );
+        assert_eq!(
+            ExpressionContext::from_pragma("user.login\n// @context policy"),
+            None
+        );
+        let default = ExpressionContext::Policy;
+        assert_eq!(
+            ExpressionContext::detect(Some(path), pragma, default),
+            ExpressionContext::TokenClaim
+        );
+        assert_eq!(
+            ExpressionContext::detect(Some(path), "user.login", default),
+            ExpressionContext::GroupRule
+        );
+        assert_eq!(
+            ExpressionContext::detect(None, "user.login", default),
+            ExpressionContext::Policy
+        );
+    }
+
+    #[test]
+    fn roots_and_functions_follow_the_catalogue() {
+        let group_rule = ExpressionContext::GroupRule;
+        assert!(group_rule.allows_root("user"));
+        assert!(!group_rule.allows_root("appuser"));
+        assert!(!group_rule.roots().contains(&"appuser"));
+        assert!(ExpressionContext::Any.allows_root("appuser"));
+        assert!(!ExpressionContext::TokenClaim.allows_function("getManagerUser"));
+        assert!(ExpressionContext::ProfileMapping.allows_function("getManagerUser"));
+        // Functions the catalogue does not know are not restricted.
+        assert!(group_rule.allows_function("String.unknown"));
+        assert_eq!(
+            ExpressionContext::with_root("idpuser"),
+            [ExpressionContext::ProfileMapping]
+        );
+        assert_eq!(
+            ExpressionContext::with_function("getManagerUser"),
+            [ExpressionContext::ProfileMapping]
+        );
+    }
+
+    #[test]
+    fn pragma_edit_inserts_or_replaces_the_pragma() {
+        assert_eq!(
+            pragma_edit("user.login", PRAGMA, ExpressionContext::Policy.id()),
+            (
+                Point::new(0, 0),
+                Point::new(0, 0),
+                "// @context policy\n".to_string()
+            )
+        );
+        assert_eq!(
+            pragma_edit("\n// @context any\nuser.login", PRAGMA, "policy"),
+            (
+                Point::new(1, 0),
+                Point::new(1, 15),
+                "// @context policy".to_string()
+            )
+        );
+    }
+}
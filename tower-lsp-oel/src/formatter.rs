@@ -0,0 +1,369 @@
+//! Pretty printer for OEL expressions.
+//!
+//! Formatting works from the typed AST, so redundant parentheses are dropped
+//! and required ones are put back based on the precedences in `grammar.js`.
+//! Ternaries that do not fit on one line are broken before `?` and `:`.
+//!
+//! Comments are not part of the AST. Those outside expressions, such as a
+//! `// @context` pragma or the notes on the entries of a file, are kept as
+//! they are by [`format_file`].
+
+use tree_sitter_traversal::{traverse, Order};
+
+use crate::oel::{analyze, escape, parse_tree, BinaryOp, Entry, Expr, Spanned, Value};
+
+const MAX_WIDTH: usize = 80;
+const INDENT: &str = "  ";
+
+/// Binding strength, mirroring the `precedences` table in `grammar.js`.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Ternary { .. } => 1,
+        Expr::Binary { op, .. } => match op {
+            BinaryOp::Or => 2,
+            BinaryOp::And => 3,
+            BinaryOp::Eq | BinaryOp::NotEq => 4,
+            BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq | BinaryOp::Matches => 5,
+            BinaryOp::Add | BinaryOp::Sub => 6,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 7,
+        },
+        Expr::Unary { .. } => 8,
+        _ => 9,
+    }
+}
+
+/// Formats an expression. Returns `None` if it contains syntax errors, as
+/// there is no way to print the parts tree-sitter could not recover.
+pub fn format(expr: &Spanned<Expr>) -> Option<String> {
+    let mut has_error = false;
+    crate::oel::walk(expr, &mut |(expr, _)| {
+        has_error |= matches!(expr, Expr::Error);
+    });
+    if has_error {
+        return None;
+    }
+    Some(format_block(&expr.0, 0))
+}
+
+/// Formats the source of a file, keeping the comments between entries, and
+/// before and after the expression. Entries are separated by a blank line.
+/// Returns `None` if the file has syntax errors or comments inside an
+/// expression, which would be lost.
+pub fn format_file(src: &str) -> Option<String> {
+    let tree = parse_tree(src, None)?;
+    let root = tree.root_node();
+    if root.has_error() {
+        return None;
+    }
+    let (entries, _) = analyze(&tree, src);
+    let mut entries = entries.iter();
+    let mut formatted = String::new();
+    // The last row and kind of what was written, in the source.
+    let mut previous: Option<(usize, bool)> = None;
+    let mut body_end = 0;
+    for node in traverse(tree.walk(), Order::Pre) {
+        let is_body = node.is_named() && !node.is_extra() && node.parent() == Some(root);
+        let text = if node.kind() == "comment" {
+            if node.start_byte() < body_end {
+                return None;
+            }
+            node.utf8_text(src.as_bytes()).ok()?.trim_end().to_string()
+        } else if is_body {
+            body_end = node.end_byte();
+            format_entry(entries.next()?)?
+        } else {
+            continue;
+        };
+        let row = node.start_position().row;
+        match previous {
+            None => {}
+            // A comment after an expression stays on its line.
+            Some((last_row, _)) if row == last_row && !is_body => formatted.push(' '),
+            Some((last_row, was_body)) => {
+                formatted.push('\n');
+                if row > last_row + 1 || (was_body && is_body) {
+                    formatted.push('\n');
+                }
+            }
+        }
+        formatted.push_str(&text);
+        previous = Some((node.end_position().row, is_body));
+    }
+    formatted.push('\n');
+    Some(formatted)
+}
+
+fn format_entry(entry: &Entry) -> Option<String> {
+    let value = format(&entry.value)?;
+    Some(match &entry.name {
+        Some((name, _)) => format!("rule {} = {}", escape(name, '"'), value),
+        None => value,
+    })
+}
+
+fn format_value(value: &Value) -> String {
+    match value {
+        // Single quotes save escaping the double quotes.
+        Value::Str(s) if s.contains('"') && !s.contains('\'') => escape(s, '\''),
+        Value::Str(s) => escape(s, '"'),
+        // Keep the decimal point so the literal stays a Number. `{:.1}` never
+        // switches to an exponent, which the grammar has no syntax for.
+        Value::Num(n) if n.fract() == 0.0 => format!("{:.1}", n),
+        other => other.to_string(),
+    }
+}
+
+/// Wraps `child` in parentheses when it binds looser than its parent allows.
+fn operand(child: &Expr, min_precedence: u8) -> String {
+    let flat = format_flat(child);
+    if precedence(child) < min_precedence {
+        format!("({})", flat)
+    } else {
+        flat
+    }
+}
+
+fn format_flat(expr: &Expr) -> String {
+    match expr {
+        Expr::Error => String::new(),
+        Expr::Value(value) => format_value(value),
+        Expr::Ident(name) => name.clone(),
+        Expr::Array(items) => format!(
+            "{{{}}}",
+            items
+                .iter()
+                .map(|(item, _)| format_flat(item))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expr::Map(entries) if entries.is_empty() => "{:}".to_string(),
+        Expr::Map(entries) => format!(
+            "{{{}}}",
+            entries
+                .iter()
+                .map(|((key, _), (value, _))| {
+                    let key = if key.quoted {
+                        format_value(&Value::Str(key.name.clone()))
+                    } else {
+                        key.name.clone()
+                    };
+                    format!("{}: {}", key, format_flat(value))
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expr::Member { object, property } => {
+            format!("{}.{}", operand(&object.0, 9), property.0)
+        }
+        Expr::Subscript { object, index } => {
+            format!("{}[{}]", operand(&object.0, 9), format_flat(&index.0))
+        }
+        Expr::Call {
+            function,
+            arguments,
+        } => format!(
+            "{}({})",
+            operand(&function.0, 9),
+            arguments
+                .iter()
+                .map(|(argument, _)| format_flat(argument))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        Expr::Unary { op, argument } => format!("{}{}", op, operand(&argument.0, 8)),
+        Expr::Binary { op, left, right } => {
+            let precedence = precedence(expr);
+            // Operators are left associative, so only the right operand needs
+            // parentheses at equal precedence.
+            format!(
+                "{} {} {}",
+                operand(&left.0, precedence),
+                op,
+                operand(&right.0, precedence + 1)
+            )
+        }
+        Expr::Ternary {
+            condition,
+            consequence,
+            alternative,
+        } => format!(
+            "{} ? {} : {}",
+            operand(&condition.0, 2),
+            operand(&consequence.0, 1),
+            operand(&alternative.0, 1)
+        ),
+    }
+}
+
+fn format_block(expr: &Expr, depth: usize) -> String {
+    let flat = format_flat(expr);
+    let indent = INDENT.repeat(depth);
+    match expr {
+        Expr::Ternary {
+            condition,
+            consequence,
+            alternative,
+        } if indent.len() + flat.len() > MAX_WIDTH => {
+            let branch_indent = INDENT.repeat(depth + 1);
+            format!(
+                "{}\n{}? {}\n{}: {}",
+                operand(&condition.0, 2),
+                branch_indent,
+                format_block(&consequence.0, depth + 1),
+                branch_indent,
+                format_block(&alternative.0, depth + 1)
+            )
+        }
+        _ => flat,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tree_sitter::Point;
+
+    use super::*;
+    use crate::oel::{parse, Span};
+
+    fn ast(src: &str) -> Spanned<Expr> {
+        let (entries, errors) = parse(src);
+        assert!(errors.is_empty(), "{}: {:?}", src, errors);
+        entries.into_iter().next().unwrap().value
+    }
+
+    fn fmt(src: &str) -> String {
+        format(&ast(src)).unwrap()
+    }
+
+    /// The expression with every span replaced, to compare trees parsed
+    /// from differently formatted sources.
+    fn strip((expr, _): &Spanned<Expr>) -> Spanned<Expr> {
+        let span = || Span {
+            id: 0,
+            start: Point::new(0, 0),
+            end: Point::new(0, 0),
+        };
+        let boxed = |expr: &Spanned<Expr>| Box::new(strip(expr));
+        let expr = match expr {
+            Expr::Array(items) => Expr::Array(items.iter().map(strip).collect()),
+            Expr::Map(entries) => Expr::Map(
+                entries
+                    .iter()
+                    .map(|((key, _), value)| ((key.clone(), span()), strip(value)))
+                    .collect(),
+            ),
+            Expr::Member { object, property } => Expr::Member {
+                object: boxed(object),
+                property: (property.0.clone(), span()),
+            },
+            Expr::Subscript { object, index } => Expr::Subscript {
+                object: boxed(object),
+                index: boxed(index),
+            },
+            Expr::Call {
+                function,
+                arguments,
+            } => Expr::Call {
+                function: boxed(function),
+                arguments: arguments.iter().map(strip).collect(),
+            },
+            Expr::Unary { op, argument } => Expr::Unary {
+                op: *op,
+                argument: boxed(argument),
+            },
+            Expr::Binary { op, left, right } => Expr::Binary {
+                op: *op,
+                left: boxed(left),
+                right: boxed(right),
+            },
+            Expr::Ternary {
+                condition,
+                consequence,
+                alternative,
+            } => Expr::Ternary {
+                condition: boxed(condition),
+                consequence: boxed(consequence),
+                alternative: boxed(alternative),
+            },
+            other => other.clone(),
+        };
+        (expr, span())
+    }
+
+    #[test]
+    fn numbers_keep_their_type() {
+        assert_eq!(format_value(&Value::Int(3)), "3");
+        assert_eq!(format_value(&Value::Num(2.5)), "2.5");
+        assert_eq!(format_value(&Value::Num(-3.0)), "-3.0");
+        assert_eq!(format_value(&Value::Num(1e15)), "1000000000000000.0");
+        assert_eq!(format_value(&Value::Num(1e20)), "100000000000000000000.0");
+        assert_eq!(format_value(&Value::Num(1e-7)), "0.0000001");
+    }
+
+    #[test]
+    fn strings_pick_the_quote_needing_fewer_escapes() {
+        assert_eq!(fmt("'Eng'"), "\"Eng\"");
+        assert_eq!(fmt(r#""say \"hi\"""#), r#"'say "hi"'"#);
+        assert_eq!(fmt(r#"'it\'s "A"'"#), r#""it's \"A\"""#);
+    }
+
+    #[test]
+    fn map_keys_keep_their_kind() {
+        assert_eq!(
+            fmt("{'a.b':1,c:  2, 3 :{\"d\": 4}}"),
+            "{\"a.b\": 1, c: 2, 3: {\"d\": 4}}"
+        );
+        assert_eq!(fmt("{:}"), "{:}");
+    }
+
+    #[test]
+    fn parentheses_follow_precedence() {
+        assert_eq!(fmt("(1 + 2) * 3"), "(1 + 2) * 3");
+        assert_eq!(fmt("((user.login))"), "user.login");
+        assert_eq!(fmt("(a - b) - c"), "a - b - c");
+        assert_eq!(fmt("a - (b - c)"), "a - (b - c)");
+        assert_eq!(fmt("-(1 + 2)"), "-(1 + 2)");
+        assert_eq!(fmt("(a ? b : c) ? d : e"), "(a ? b : c) ? d : e");
+        assert_eq!(fmt("a AND (b OR c)"), "a AND (b OR c)");
+    }
+
+    #[test]
+    fn long_ternaries_break_before_their_branches() {
+        let src = "user.department == \"Engineering\" ? String.toUpperCase(user.login) : String.toLowerCase(user.email)";
+        assert_eq!(
+            fmt(src),
+            "user.department == \"Engineering\"\n  ? String.toUpperCase(user.login)\n  : String.toLowerCase(user.email)"
+        );
+    }
+
+    #[test]
+    fn formatting_round_trips() {
+        for src in [
+            "1+2*3",
+            "1000000000000000.0 + 123456789012345678.0",
+            "-(-1) % 3 == 0 ? 'a' : \"b\"",
+            "{'group.profile.name': {'A', \"B\"}, id: 1, 2: true}",
+            r#"String.join("\",", {'it\'s', "\\"})"#,
+            "user.isMemberOf({'group.profile.name': 'Admins'}) && !user.locked",
+            "user.department == \"Engineering\" ? String.toUpperCase(user.login) : (user.a ? 1 : 2.0)",
+            "arr[0].name",
+        ] {
+            let formatted = fmt(src);
+            assert_eq!(fmt(&formatted), formatted, "{}", src);
+            assert_eq!(strip(&ast(&formatted)), strip(&ast(src)), "{}", src);
+        }
+    }
+
+    #[test]
+    fn files_keep_their_comments() {
+        let src = "// @context group-rule\n\n# Engineering\nrule \"A\" = user.a==1\nrule \"B\" = 2 # note\n";
+        assert_eq!(
+            format_file(src).unwrap(),
+            "// @context group-rule\n\n# Engineering\nrule \"A\" = user.a == 1\n\nrule \"B\" = 2 # note\n"
+        );
+        let formatted = format_file(src).unwrap();
+        assert_eq!(format_file(&formatted).unwrap(), formatted);
+        assert_eq!(format_file("user.a ==\n # inside\n 1\n"), None);
+        assert_eq!(format_file("user.a ==\n"), None);
+    }
+}
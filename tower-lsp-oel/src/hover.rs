@@ -0,0 +1,302 @@
+//! Hover documentation for the built-in function, profile attribute, root
+//! object or operator under the cursor. Function and operator docs come from
+//! the [`Catalogue`], attribute docs from the loaded [`Schema`].
+
+use tree_sitter::Point;
+
+use crate::builtins::Registry;
+use crate::catalogue::Catalogue;
+use crate::eval::ROOTS;
+use crate::oel::{Expr, Span, Spanned};
+use crate::schema::Schema;
+use crate::typecheck::Signature;
+
+/// What the cursor is on.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Function(String),
+    Attribute { root: String, name: String },
+    Root(String),
+    Operator(String),
+    UnaryOperator(String),
+}
+
+fn contains(span: &Span, position: Point) -> bool {
+    span.start <= position && position <= span.end
+}
+
+fn between(from: &Span, to: &Span) -> Span {
+    Span {
+        id: from.id,
+        start: from.end,
+        end: to.start,
+    }
+}
+
+/// Markdown documentation for the node at `position`, and the range it
+/// documents.
+pub fn hover(
+    ast: &Spanned<Expr>,
+    position: Point,
+    registry: &Registry,
+    schema: &Schema,
+) -> Option<(String, Span)> {
+    let (target, span) = target(ast, position)?;
+    let markdown = match target {
+        Target::Function(name) => function_doc(&name, registry)?,
+        Target::Attribute { root, name } => attribute_doc(&root, &name, schema)?,
+        Target::Root(name) => {
+            let catalogue = Catalogue::get();
+            let doc = catalogue.root(&name)?;
+            let contexts = doc
+                .contexts
+                .iter()
+                .map(|context| catalogue.context_name(context))
+                .collect::<Vec<_>>()
+                .join(", ");
+            format!(
+                "```oel\n{}\n```\n\n{}\n\nAvailable in: {}",
+                name, doc.description, contexts
+            )
+        }
+        Target::Operator(token) => operator_doc(&token, false)?,
+        Target::UnaryOperator(token) => operator_doc(&token, true)?,
+    };
+    Some((markdown, span))
+}
+
+fn target((expr, span): &Spanned<Expr>, position: Point) -> Option<(Target, Span)> {
+    if !contains(span, position) {
+        return None;
+    }
+    let in_children = || {
+        expr.children()
+            .into_iter()
+            .find_map(|child| target(child, position))
+    };
+    match expr {
+        Expr::Call { function, .. } if contains(&function.1, position) => match function.0.path() {
+            Some(name) => Some((Target::Function(name), function.1.clone())),
+            None => in_children(),
+        },
+        Expr::Member { object, property } if contains(&property.1, position) => match &object.0 {
+            Expr::Ident(root) if ROOTS.contains(&root.as_str()) => Some((
+                Target::Attribute {
+                    root: root.clone(),
+                    name: property.0.clone(),
+                },
+                property.1.clone(),
+            )),
+            _ => None,
+        },
+        Expr::Ident(name) if ROOTS.contains(&name.as_str()) => {
+            Some((Target::Root(name.clone()), span.clone()))
+        }
+        Expr::Binary { op, left, right } => in_children()
+            .or_else(|| Some((Target::Operator(op.to_string()), between(&left.1, &right.1)))),
+        Expr::Unary { op, argument } if position < argument.1.start => Some((
+            Target::UnaryOperator(op.to_string()),
+            Span {
+                id: span.id,
+                start: span.start,
+                end: argument.1.start,
+            },
+        )),
+        Expr::Ternary {
+            condition,
+            consequence,
+            alternative,
+        } => in_children().or_else(|| {
+            if position < consequence.1.start {
+                Some((
+                    Target::Operator("?".to_string()),
+                    between(&condition.1, &consequence.1),
+                ))
+            } else {
+                Some((
+                    Target::Operator(":".to_string()),
+                    between(&consequence.1, &alternative.1),
+                ))
+            }
+        }),
+        _ => in_children(),
+    }
+}
+
+/// Markdown documentation for the built-in function `name`: its signatures,
+/// description, examples and contexts.
+pub fn function_doc(name: &str, registry: &Registry) -> Option<String> {
+    let builtin = registry.get(name)?;
+    let catalogue = Catalogue::get();
+    let signatures = Signature::all(builtin)
+        .iter()
+        .map(|signature| format!("{}{}", name, signature))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut markdown = format!("```oel\n{}\n```", signatures);
+    if let Some(doc) = catalogue.function(name) {
+        markdown.push_str(&format!("\n\n{}", doc.description));
+        if let Some(instead) = &doc.deprecated {
+            markdown.push_str(&format!("\n\n**Deprecated.** {}", instead));
+        }
+        if !doc.examples.is_empty() {
+            markdown.push_str("\n\n**Examples**\n\n```oel\n");
+            for example in &doc.examples {
+                markdown.push_str(&format!("{}  // {}\n", example.expression, example.result));
+            }
+            markdown.push_str("```");
+        }
+        if !doc.contexts.is_empty() {
+            let contexts = doc
+                .contexts
+                .iter()
+                .map(|context| catalogue.context_name(context))
+                .collect::<Vec<_>>()
+                .join(", ");
+            markdown.push_str(&format!("\n\nAvailable in: {}", contexts));
+        }
+    }
+    Some(markdown)
+}
+
+fn attribute_doc(root: &str, name: &str, schema: &Schema) -> Option<String> {
+    let attribute = schema.attribute(root, name)?;
+    let mut markdown = format!("```oel\n{}.{}: {}\n```", root, name, attribute.ty);
+    if let Some(title) = &attribute.title {
+        markdown.push_str(&format!("\n\n**{}**", title));
+    }
+    if let Some(description) = &attribute.description {
+        markdown.push_str(&format!("\n\n{}", description));
+    }
+    let mut facts = vec![format!(
+        "- {} attribute{}",
+        if attribute.custom { "Custom" } else { "Base" },
+        if attribute.required { ", required" } else { "" }
+    )];
+    if attribute.deprecated {
+        facts.push("- Deprecated".to_string());
+    }
+    if let Some(mutability) = &attribute.mutability {
+        facts.push(format!("- Mutability: `{}`", mutability));
+    }
+    if let Some(master) = &attribute.master {
+        let meaning = match master.as_str() {
+            "PROFILE_MASTER" => " (the HR source or directory the user is imported from)",
+            "OKTA" => " (Okta)",
+            "OVERRIDE" => " (per-attribute override)",
+            _ => "",
+        };
+        facts.push(format!("- Master: `{}`{}", master, meaning));
+    }
+    markdown.push_str(&format!("\n\n{}", facts.join("\n")));
+    Some(markdown)
+}
+
+fn operator_doc(token: &str, unary: bool) -> Option<String> {
+    let doc = Catalogue::get().operator(token, unary)?;
+    Some(format!(
+        "**`{}`** operator\n\n{}\n\nPrecedence {} (higher binds tighter)",
+        doc.token, doc.description, doc.precedence
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oel::{parse, BinaryOp, UnaryOp, Value};
+
+    /// The hover at byte `column` of the single line `src`.
+    fn hover_at(src: &str, column: usize) -> Option<(String, (usize, usize))> {
+        let (entries, _) = parse(src);
+        let (markdown, span) = hover(
+            &entries.first()?.value,
+            Point::new(0, column),
+            &Registry::default(),
+            &Schema::default(),
+        )?;
+        Some((markdown, (span.start.column, span.end.column)))
+    }
+
+    fn span(start: usize, end: usize) -> Span {
+        Span {
+            id: 0,
+            start: Point::new(0, start),
+            end: Point::new(0, end),
+        }
+    }
+
+    #[test]
+    fn every_operator_is_documented() {
+        use BinaryOp::*;
+        for op in [
+            Add, Sub, Mul, Div, Mod, Eq, NotEq, Lt, LtEq, Gt, GtEq, And, Or, Matches,
+        ] {
+            assert!(operator_doc(&op.to_string(), false).is_some(), "{}", op);
+        }
+        for op in [UnaryOp::Not, UnaryOp::Neg] {
+            assert!(operator_doc(&op.to_string(), true).is_some(), "{}", op);
+        }
+        assert!(operator_doc("-", false).unwrap().contains("Subtraction"));
+        assert!(operator_doc("-", true).unwrap().contains("Negation"));
+        assert_eq!(operator_doc("!", false), None);
+    }
+
+    #[test]
+    fn unary_minus_is_negation() {
+        // `-x - 1`
+        let negation = (
+            Expr::Unary {
+                op: UnaryOp::Neg,
+                argument: Box::new((Expr::Ident("x".to_string()), span(1, 2))),
+            },
+            span(0, 2),
+        );
+        let ast = (
+            Expr::Binary {
+                op: BinaryOp::Sub,
+                left: Box::new(negation),
+                right: Box::new((Expr::Value(Value::Int(1)), span(5, 6))),
+            },
+            span(0, 6),
+        );
+        let at = |column| {
+            let (markdown, span) = hover(
+                &ast,
+                Point::new(0, column),
+                &Registry::default(),
+                &Schema::default(),
+            )?;
+            Some((markdown, span.start.column, span.end.column))
+        };
+        let (markdown, start, end) = at(0).unwrap();
+        assert!(markdown.contains("Negation"), "{}", markdown);
+        assert_eq!((start, end), (0, 1));
+        let (markdown, start, end) = at(3).unwrap();
+        assert!(markdown.contains("Subtraction"), "{}", markdown);
+        assert_eq!((start, end), (2, 5));
+    }
+
+    #[test]
+    fn hovers() {
+        let src = "-user.age - 1 > 0 && !String.len(user.login)";
+        let at = |needle: &str| hover_at(src, src.find(needle).unwrap());
+
+        let (markdown, span) = at("-user").unwrap();
+        assert!(markdown.starts_with("**`-`** operator\n\nNegation"));
+        assert_eq!(span, (0, 1));
+        let (markdown, span) = at("- 1").unwrap();
+        assert!(markdown.starts_with("**`-`** operator\n\nSubtraction"));
+        assert_eq!(span, (9, 12));
+        assert!(at("!").unwrap().0.contains("Logical not"));
+        assert!(at("&&").unwrap().0.starts_with("**`AND`** operator"));
+
+        let (markdown, span) = at("len").unwrap();
+        assert!(markdown.starts_with("```oel\nString.len("));
+        assert_eq!(span, (22, 32));
+        let (markdown, span) = at("login").unwrap();
+        assert!(markdown.starts_with("```oel\nuser.login: String\n```"));
+        assert_eq!(span, (38, 43));
+        assert!(at("user").unwrap().0.starts_with("```oel\nuser\n```"));
+        assert_eq!(hover_at(src, src.len() + 5), None);
+    }
+}
@@ -1,144 +1,501 @@
-use std::collections::HashMap;
-
-use im_rc::Vector;
-use log::debug;
-use tower_lsp::{
-    lsp_types::{MessageType, Position, Range},
-    Client,
-};
-use tree_sitter::Node;
-
-use crate::oel::{Span, Spanned};
-/// return (need_to_continue_search, founded reference)
-pub fn get_definition(ast: &HashMap<String, Span>, ident_offset: Position) -> Option<Range> {
-    for (_, v) in ast.iter() {
-        if ident_offset.line >= std::convert::TryInto::<u32>::try_into(v.start.row).unwrap()
-            && ident_offset.line <= std::convert::TryInto::<u32>::try_into(v.end.row).unwrap()
-            && ident_offset.character
-                >= std::convert::TryInto::<u32>::try_into(v.end.column).unwrap()
-            && ident_offset.character
-                <= std::convert::TryInto::<u32>::try_into(v.end.column).unwrap()
-        {
-            return Some(Range {
-                start: Position {
-                    // TODO: This is horrible
-                    line: v.start.row.try_into().unwrap(),
-                    character: v.start.column.try_into().unwrap(),
-                },
-                end: Position {
-                    line: v.end.row.try_into().unwrap(),
-                    character: v.end.column.try_into().unwrap(),
-                },
-            });
+//! Go to definition. A profile attribute is defined by its property in the
+//! profile schema file and a group name by its group in the directory
+//! fixture, both from the [`Config`](crate::config::Config). Built-in
+//! functions have no source, so they jump to a virtual document in the
+//! [`BUILTIN_SCHEME`], whose markdown the client requests with
+//! [`BUILTIN_DOCUMENT_METHOD`] and shows read-only.
+
+use std::path::Path;
+
+use tower_lsp::lsp_types::{Location, Position, Range, Url};
+use tree_sitter::Point;
+
+use crate::builtins::Registry;
+use crate::hover::function_doc;
+use crate::oel::{walk, Expr};
+use crate::position::{LineIndex, PositionEncoding};
+use crate::schema::Schema;
+use crate::workspace::{FileIndex, Name, WorkspaceIndex};
+
+/// The URI scheme of the documents of built-in functions,
+/// `oel-builtin:/String.len.md`.
+pub const BUILTIN_SCHEME: &str = "oel-builtin";
+
+/// The request for the markdown of a [`BUILTIN_SCHEME`] document.
+pub const BUILTIN_DOCUMENT_METHOD: &str = "oel/builtinDocument";
+
+/// Where the attribute, group name or built-in function at `position` is
+/// defined. `directory` is the users and groups fixture, if configured.
+pub fn get_definition(
+    workspace: &WorkspaceIndex,
+    registry: &Registry,
+    schema: &Schema,
+    directory: Option<&Path>,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<Location> {
+    let file = workspace.get(uri)?;
+    let point = LineIndex::new(&file.src, encoding).point(position);
+    match file.occurrence_at(point).map(|occurrence| &occurrence.name) {
+        Some(Name::Attribute { root, attribute }) => {
+            let path = schema.profile(root)?.source.as_ref()?;
+            let custom = schema
+                .attribute(root, attribute)
+                .map(|attribute| attribute.custom);
+            locate(path, encoding, |src| property(src, attribute, custom))
+        }
+        Some(Name::Group(name)) => {
+            let directory = directory?;
+            locate(directory, encoding, |src| {
+                if is_yaml(directory) {
+                    yaml_group(src, name)
+                } else {
+                    json_group(src, name)
+                }
+            })
+        }
+        None => {
+            let uri = builtin_uri(&function_at(&file, registry, point)?)?;
+            let start = Position::new(0, 0);
+            Some(Location::new(uri, Range::new(start, start)))
+        }
+    }
+}
+
+/// Reads `path` and turns the byte range `find` returns into a location.
+fn locate(
+    path: &Path,
+    encoding: PositionEncoding,
+    find: impl FnOnce(&str) -> Option<(usize, usize)>,
+) -> Option<Location> {
+    let src = std::fs::read_to_string(path).ok()?;
+    let (start, end) = find(&src)?;
+    let index = LineIndex::new(&src, encoding);
+    let range = index.range(index.point_at(start), index.point_at(end));
+    Some(Location::new(Url::from_file_path(path).ok()?, range))
+}
+
+/// The key of property `attribute` in a profile schema, in the `custom` or
+/// `base` section as `custom` says, or in either if unknown.
+fn property(src: &str, attribute: &str, custom: Option<bool>) -> Option<(usize, usize)> {
+    members(src)
+        .into_iter()
+        .find(|member| {
+            matches!(
+                member.path.as_slice(),
+                [definitions, section, properties]
+                    if definitions == "definitions"
+                        && properties == "properties"
+                        && custom.map_or(true, |custom| (section == "custom") == custom)
+            ) && member.key == attribute
+        })
+        .map(|member| member.key_span)
+}
+
+/// The value of the `name` of group `name` in a JSON fixture.
+fn json_group(src: &str, name: &str) -> Option<(usize, usize)> {
+    members(src)
+        .into_iter()
+        .find(|member| {
+            matches!(member.path.as_slice(), [groups, _] if groups == "groups")
+                && member.key == "name"
+                && member.value.as_ref().map(|(value, _)| value.as_str()) == Some(name)
+        })
+        .and_then(|member| member.value)
+        .map(|(_, span)| span)
+}
+
+fn is_yaml(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("yaml") | Some("yml")
+    )
+}
+
+/// The built-in function called at `point`, e.g. `String.len`.
+fn function_at(file: &FileIndex, registry: &Registry, point: Point) -> Option<String> {
+    let mut name = None;
+    for entry in &file.entries {
+        walk(&entry.value, &mut |(expr, _)| {
+            if let Expr::Call { function, .. } = expr {
+                if function.1.start <= point && point <= function.1.end {
+                    if let Some(path) = function
+                        .0
+                        .path()
+                        .filter(|path| registry.get(path).is_some())
+                    {
+                        name = Some(path);
+                    }
+                }
+            }
+        });
+    }
+    name
+}
+
+fn builtin_uri(name: &str) -> Option<Url> {
+    Url::parse(&format!("{}:/{}.md", BUILTIN_SCHEME, name)).ok()
+}
+
+/// The markdown of the [`BUILTIN_SCHEME`] document `uri`, generated from the
+/// catalogue. `None` for any other URI.
+pub fn builtin_document(uri: &Url, registry: &Registry) -> Option<String> {
+    if uri.scheme() != BUILTIN_SCHEME {
+        return None;
+    }
+    let name = uri.path().strip_prefix('/')?.strip_suffix(".md")?;
+    Some(format!("# {}\n\n{}\n", name, function_doc(name, registry)?))
+}
+
+/// An object member of a JSON document.
+#[derive(Debug, PartialEq)]
+struct Member {
+    /// The keys of the enclosing objects, and the indices of the enclosing
+    /// arrays.
+    path: Vec<String>,
+    key: String,
+    /// Quotes included.
+    key_span: (usize, usize),
+    /// The value, when it is a string, and its span.
+    value: Option<(String, (usize, usize))>,
+}
+
+/// The members of every object in `src`, with the byte range of each key.
+/// `serde_json` does not keep positions, hence this scanner. Scanning stops
+/// at the first syntax error.
+fn members(src: &str) -> Vec<Member> {
+    let mut scanner = Scanner {
+        src,
+        offset: 0,
+        members: Vec::new(),
+    };
+    scanner.value(&mut Vec::new());
+    scanner.members
+}
+
+struct Scanner<'a> {
+    src: &'a str,
+    offset: usize,
+    members: Vec<Member>,
+}
+
+impl Scanner<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.src.as_bytes().get(self.offset).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().map_or(false, |c| c.is_ascii_whitespace()) {
+            self.offset += 1;
         }
     }
 
+    fn eat(&mut self, c: u8) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(c);
+        if found {
+            self.offset += 1;
+        }
+        found
+    }
+
+    /// Scans a value, returning it when it is a string. `None` also ends
+    /// the scan on a syntax error, as the offset does not move.
+    fn value(&mut self, path: &mut Vec<String>) -> Option<(String, (usize, usize))> {
+        self.skip_whitespace();
+        match self.peek()? {
+            b'{' => {
+                self.offset += 1;
+                if self.eat(b'}') {
+                    return None;
+                }
+                loop {
+                    self.skip_whitespace();
+                    let (key, key_span) = self.string()?;
+                    if !self.eat(b':') {
+                        return None;
+                    }
+                    path.push(key.clone());
+                    let start = self.offset;
+                    let value = self.value(path);
+                    path.pop();
+                    self.members.push(Member {
+                        path: path.clone(),
+                        key,
+                        key_span,
+                        value,
+                    });
+                    if self.offset == start || !self.eat(b',') {
+                        break;
+                    }
+                }
+                self.eat(b'}');
+                None
+            }
+            b'[' => {
+                self.offset += 1;
+                let mut index = 0;
+                while !self.eat(b']') {
+                    path.push(index.to_string());
+                    let start = self.offset;
+                    self.value(path);
+                    path.pop();
+                    if self.offset == start {
+                        break;
+                    }
+                    self.eat(b',');
+                    index += 1;
+                }
+                None
+            }
+            b'"' => self.string(),
+            _ => {
+                while self.peek().map_or(false, |c| {
+                    !matches!(c, b',' | b'}' | b']') && !c.is_ascii_whitespace()
+                }) {
+                    self.offset += 1;
+                }
+                None
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<(String, (usize, usize))> {
+        let start = self.offset;
+        if self.peek()? != b'"' {
+            return None;
+        }
+        let mut end = start + 1;
+        loop {
+            match self.src.as_bytes().get(end)? {
+                b'"' => break,
+                b'\\' => end += 2,
+                _ => end += 1,
+            }
+        }
+        end += 1;
+        let value = serde_json::from_str(&self.src[start..end]).ok()?;
+        self.offset = end;
+        Some((value, (start, end)))
+    }
+}
+
+/// The value of the `name:` of group `name` in a YAML fixture laid out as in
+/// the [`directory`](crate::directory) docs, one key per line.
+fn yaml_group(src: &str, name: &str) -> Option<(usize, usize)> {
+    let mut in_groups = false;
+    let mut offset = 0;
+    for line in src.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let content = line.trim_end();
+        if content.is_empty() || content.trim_start().starts_with('#') {
+            continue;
+        }
+        if !content.starts_with(char::is_whitespace) && !content.starts_with('-') {
+            in_groups = content == "groups:";
+            continue;
+        }
+        if !in_groups {
+            continue;
+        }
+        let item = content.trim_start().trim_start_matches("- ").trim_start();
+        let value = match item.strip_prefix("name:") {
+            Some(value) => value.trim(),
+            None => continue,
+        };
+        let unquoted = ['"', '\'']
+            .iter()
+            .find_map(|quote| value.strip_prefix(*quote)?.strip_suffix(*quote))
+            .unwrap_or(value);
+        if unquoted == name {
+            // The value runs to the end of the trimmed line.
+            let end = start + content.len();
+            return Some((end - value.len(), end));
+        }
+    }
     None
 }
 
-pub fn get_definition_of_expr(
-    expr: &Spanned<Node>,
-    definition_ass_list: Vector<Spanned<String>>,
-    ident_offset: usize,
-) -> (bool, Option<Spanned<String>>) {
-    (true, None)
-    // match &expr.0 {
-    //     Expr::Error => (true, None),
-    //     Expr::Value(_) => (true, None),
-    //     // Expr::List(exprs) => exprs
-    //     //     .iter()
-    //     //     .for_each(|expr| get_definition(expr, definition_ass_list)),
-    //     Expr::Local(local) => {
-    //         if ident_offset >= local.1.start && ident_offset < local.1.end {
-    //             let index = definition_ass_list
-    //                 .iter()
-    //                 .position(|decl| decl.0 == local.0);
-    //             (
-    //                 false,
-    //                 index.map(|i| definition_ass_list.get(i).unwrap().clone()),
-    //             )
-    //         } else {
-    //             (true, None)
-    //         }
-    //     }
-    //     Expr::Let(name, lhs, rest, name_span) => {
-    //         let new_decl = Vector::unit((name.clone(), name_span.clone()));
-
-    //         match get_definition_of_expr(lhs, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {
-    //                 get_definition_of_expr(rest, new_decl + definition_ass_list, ident_offset)
-    //             }
-    //             (true, Some(value)) => return (false, Some(value)),
-    //             (false, None) => return (false, None),
-    //             (false, Some(value)) => return (false, Some(value)),
-    //         }
-    //     }
-    //     Expr::Then(first, second) => {
-    //         match get_definition_of_expr(first, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {
-    //                 get_definition_of_expr(second, definition_ass_list.clone(), ident_offset)
-    //             }
-    //             (false, None) => (false, None),
-    //             (true, Some(value)) | (false, Some(value)) => (false, Some(value)),
-    //         }
-    //     }
-    //     Expr::Binary(lhs, _, rhs) => {
-    //         match get_definition_of_expr(lhs, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {
-    //                 get_definition_of_expr(rhs, definition_ass_list.clone(), ident_offset)
-    //             }
-    //             (false, None) => (false, None),
-    //             (true, Some(value)) | (false, Some(value)) => (false, Some(value)),
-    //         }
-    //     }
-    //     Expr::Call(callee, args) => {
-    //         match get_definition_of_expr(callee, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {}
-    //             (true, Some(value)) => return (false, Some(value)),
-    //             (false, None) => return (false, None),
-    //             (false, Some(value)) => return (false, Some(value)),
-    //         }
-    //         for expr in &args.0 {
-    //             match get_definition_of_expr(&expr, definition_ass_list.clone(), ident_offset) {
-    //                 (true, None) => continue,
-    //                 (true, Some(value)) => return (false, Some(value)),
-    //                 (false, None) => return (false, None),
-    //                 (false, Some(value)) => return (false, Some(value)),
-    //             }
-    //         }
-    //         (true, None)
-    //     }
-    //     Expr::If(test, consequent, alternative) => {
-    //         match get_definition_of_expr(test, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {}
-    //             (true, Some(value)) => return (false, Some(value)),
-    //             (false, None) => return (false, None),
-    //             (false, Some(value)) => return (false, Some(value)),
-    //         }
-    //         match get_definition_of_expr(consequent, definition_ass_list.clone(), ident_offset) {
-    //             (true, None) => {}
-    //             (true, Some(value)) => return (false, Some(value)),
-    //             (false, None) => return (false, None),
-    //             (false, Some(value)) => return (false, Some(value)),
-    //         }
-    //         match get_definition_of_expr(alternative, definition_ass_list, ident_offset) {
-    //             (true, None) => return (true, None),
-    //             (true, Some(value)) => return (false, Some(value)),
-    //             (false, None) => return (false, None),
-    //             (false, Some(value)) => return (false, Some(value)),
-    //         }
-    //     }
-    //     Expr::Print(expr) => get_definition_of_expr(expr, definition_ass_list, ident_offset),
-    //     Expr::List(lst) => {
-    //         for expr in lst {
-    //             match get_definition_of_expr(expr, definition_ass_list.clone(), ident_offset) {
-    //                 (true, None) => continue,
-    //                 (true, Some(value)) => return (false, Some(value)),
-    //                 (false, None) => return (false, None),
-    //                 (false, Some(value)) => return (false, Some(value)),
-    //             }
-    //         }
-    //         (true, None)
-    //     }
-    // }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::schema::Profile;
+
+    // `nickName` is in both sections, as when an org shadows a base
+    // attribute with a custom one.
+    const SCHEMA: &str = r#"{
+  "definitions": {
+    "base": {
+      "properties": {
+        "login": { "title": "Username", "type": "string" },
+        "nickName": { "type": "string" }
+      }
+    },
+    "custom": {
+      "properties": {
+        "nickName": { "type": "string" },
+        "level": { "type": "integer", "description": "login" }
+      }
+    }
+  }
+}"#;
+
+    const GROUPS_JSON: &str = r#"{
+  "groups": [
+    { "id": "00g1", "name": "Engineering" },
+    { "id": "00g2", "name": "AD \"Admins\"" }
+  ],
+  "users": [{ "id": "00u1", "profile": { "name": "Engineering" } }]
+}"#;
+
+    const GROUPS_YAML: &str = "\
+# Fixture
+users:
+  - id: 00u1
+    name: Engineering
+groups:
+  - id: 00g1
+    name: Engineering
+  - name: 'AD Admins'
+    id: 00g2
+";
+
+    fn text(src: &str, span: Option<(usize, usize)>) -> Option<&str> {
+        span.map(|(start, end)| &src[start..end])
+    }
+
+    /// Writes `src` to a file named after the test.
+    fn write(test: &str, extension: &str, src: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("oel-{}-{}.{}", test, std::process::id(), extension));
+        std::fs::write(&path, src).unwrap();
+        path
+    }
+
+    #[test]
+    fn scans_members_with_their_paths() {
+        let src = r#"{ "a": [1, { "b\"": "c" }], "d": true }"#;
+        let scanned = members(src);
+        let keys = scanned
+            .iter()
+            .map(|member| format!("{}:{}", member.path.join("."), member.key))
+            .collect::<Vec<_>>();
+        assert_eq!(keys, ["a.1:b\"", ":a", ":d"]);
+        assert_eq!(text(src, Some(scanned[0].key_span)), Some(r#""b\"""#));
+        assert_eq!(
+            scanned[0].value.as_ref().map(|(value, _)| value.as_str()),
+            Some("c")
+        );
+        // Scanning stops at the syntax error, keeping what came before it.
+        assert_eq!(members(r#"{ "a": 1, "b" 2, "c": 3 }"#).len(), 1);
+    }
+
+    #[test]
+    fn properties_are_found_in_their_section() {
+        let key = |attribute, custom| text(SCHEMA, property(SCHEMA, attribute, custom));
+        assert_eq!(key("login", Some(false)), Some("\"login\""));
+        assert_eq!(key("login", Some(true)), None);
+        let base = SCHEMA.find("\"nickName\"").unwrap();
+        let custom = SCHEMA.rfind("\"nickName\"").unwrap();
+        assert_eq!(
+            property(SCHEMA, "nickName", Some(false)).map(|(start, _)| start),
+            Some(base)
+        );
+        assert_eq!(
+            property(SCHEMA, "nickName", Some(true)).map(|(start, _)| start),
+            Some(custom)
+        );
+        assert_eq!(
+            property(SCHEMA, "nickName", None).map(|(start, _)| start),
+            Some(base)
+        );
+        assert_eq!(key("type", None), None);
+    }
+
+    #[test]
+    fn groups_are_found_by_name() {
+        let json = |name| text(GROUPS_JSON, json_group(GROUPS_JSON, name));
+        assert_eq!(json("Engineering"), Some("\"Engineering\""));
+        assert!(
+            json_group(GROUPS_JSON, "Engineering").unwrap().0 < GROUPS_JSON.find("users").unwrap()
+        );
+        assert_eq!(json("AD \"Admins\""), Some(r#""AD \"Admins\"""#));
+        assert_eq!(json("00g1"), None);
+
+        let yaml = |name| text(GROUPS_YAML, yaml_group(GROUPS_YAML, name));
+        assert_eq!(yaml("Engineering"), Some("Engineering"));
+        assert!(
+            yaml_group(GROUPS_YAML, "Engineering").unwrap().0
+                > GROUPS_YAML.find("groups:").unwrap()
+        );
+        assert_eq!(yaml("AD Admins"), Some("'AD Admins'"));
+        assert_eq!(yaml("Sales"), None);
+    }
+
+    #[test]
+    fn builtin_documents() {
+        let registry = Registry::default();
+        let uri = builtin_uri("String.len").unwrap();
+        assert_eq!(uri.as_str(), "oel-builtin:/String.len.md");
+        let document = builtin_document(&uri, &registry).unwrap();
+        assert!(document.starts_with("# String.len\n\n"));
+        assert_eq!(
+            builtin_document(&builtin_uri("String.nope").unwrap(), &registry),
+            None
+        );
+        let file = Url::parse("file:///String.len.md").unwrap();
+        assert_eq!(builtin_document(&file, &registry), None);
+    }
+
+    #[test]
+    fn definitions() {
+        let schema_path = write("definitions", "json", SCHEMA);
+        let mut schema = Schema::default();
+        schema.insert("user", Profile::load(&schema_path).unwrap());
+        let yaml = write("definitions-groups", "yaml", GROUPS_YAML);
+        let json = write("definitions-groups", "json", GROUPS_JSON);
+
+        let workspace = WorkspaceIndex::default();
+        let uri = Url::parse("file:///a.oel").unwrap();
+        let src =
+            "isMemberOfGroupName('Engineering') && user.nickName == String.toUpperCase(user.login)";
+        workspace.insert(
+            uri.clone(),
+            FileIndex::parse(src.to_string(), Dialect::Classic),
+        );
+        let registry = Registry::default();
+        let definition = |directory: Option<&Path>, at: &str| {
+            let character = src.find(at).unwrap() as u32 + 1;
+            get_definition(
+                &workspace,
+                &registry,
+                &schema,
+                directory,
+                &uri,
+                Position::new(0, character),
+                PositionEncoding::Utf16,
+            )
+        };
+        let range =
+            |line, start, end| Range::new(Position::new(line, start), Position::new(line, end));
+
+        let location = definition(None, "nickName").unwrap();
+        assert_eq!(location.uri, Url::from_file_path(&schema_path).unwrap());
+        assert_eq!(location.range, range(10, 8, 18));
+        assert_eq!(definition(None, "login").unwrap().range, range(4, 8, 15));
+
+        let location = definition(Some(&yaml), "Engineering").unwrap();
+        assert_eq!(location.uri, Url::from_file_path(&yaml).unwrap());
+        assert_eq!(location.range, range(6, 10, 21));
+        let location = definition(Some(&json), "Engineering").unwrap();
+        assert_eq!(location.range, range(2, 28, 41));
+        assert_eq!(definition(None, "Engineering"), None);
+
+        let location = definition(None, "toUpperCase").unwrap();
+        assert_eq!(location.uri.as_str(), "oel-builtin:/String.toUpperCase.md");
+        assert_eq!(location.range, range(0, 0, 0));
+    }
 }
@@ -1,5 +1,23 @@
+pub mod builtins;
+pub mod catalogue;
 pub mod completion;
+pub mod config;
+pub mod dialect;
+pub mod directory;
+pub mod document;
+pub mod document_symbol;
+pub mod eval;
+pub mod expression_context;
+pub mod formatter;
+pub mod hover;
 pub mod jump_definition;
 pub mod oel;
+pub mod position;
 pub mod reference;
+pub mod rename;
+pub mod schema;
 pub mod semantic_token;
+pub mod signature_help;
+pub mod syntax_error;
+pub mod typecheck;
+pub mod workspace;
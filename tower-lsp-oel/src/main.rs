@@ -1,29 +1,119 @@
-use std::collections::HashMap;
-
 use dashmap::DashMap;
-use oel_language_server::oel::{parse, ImCompleteSemanticToken, Span};
-use oel_language_server::semantic_token::{semantic_token_from_ast, LEGEND_TYPE};
+use oel_language_server::builtins::Registry;
+use oel_language_server::catalogue::Catalogue;
+use oel_language_server::completion::{completion, ImCompleteCompletionItem};
+use oel_language_server::config::Config;
+use oel_language_server::dialect::{self, Dialect};
+use oel_language_server::document::apply_change;
+use oel_language_server::document_symbol::document_symbols;
+use oel_language_server::expression_context::{self, pragma_edit, ExpressionContext};
+use oel_language_server::hover::hover;
+use oel_language_server::jump_definition::{
+    builtin_document, get_definition, BUILTIN_DOCUMENT_METHOD,
+};
+use oel_language_server::oel::{analyze, entry_at, parse_tree, Entry, ImCompleteSemanticToken};
+use oel_language_server::position::{LineIndex, PositionEncoding};
+use oel_language_server::reference::{get_highlights, get_reference};
+use oel_language_server::rename::{prepare_rename, rename};
+use oel_language_server::schema::Schema;
+use oel_language_server::semantic_token::{
+    delta, encode, semantic_tokens, LEGEND_MODIFIER, LEGEND_TYPE,
+};
+use oel_language_server::signature_help::signature_help;
+use oel_language_server::typecheck::{self, Fix};
+use oel_language_server::workspace::{is_oel_file, oel_files, FileIndex, WorkspaceIndex};
+use ropey::Rope;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::RwLock;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
+use tree_sitter::Tree;
 #[derive(Debug)]
 struct Backend {
     client: Client,
-    ast_map: DashMap<String, HashMap<String, Span>>,
+    ast_map: DashMap<String, Vec<Entry>>,
+    document_map: DashMap<String, Rope>,
+    /// The version of each open document. Notifications are handled
+    /// concurrently, so results computed for an older version are dropped.
+    version_map: DashMap<String, i32>,
+    /// The tree-sitter tree of each document, reused when reparsing after
+    /// an edit.
+    tree_map: DashMap<String, Tree>,
     semantic_token_map: DashMap<String, Vec<ImCompleteSemanticToken>>,
+    /// The result id and data last sent for each document, to compute deltas
+    /// against.
+    semantic_token_results: DashMap<String, (String, Vec<SemanticToken>)>,
+    next_result_id: AtomicU64,
+    registry: Registry,
+    config: RwLock<Config>,
+    /// Negotiated in `initialize`.
+    position_encoding: RwLock<PositionEncoding>,
+    schema: RwLock<Schema>,
+    workspace_folders: RwLock<Vec<PathBuf>>,
+    /// The `.oel` files of the workspace folders, open or not.
+    workspace: WorkspaceIndex,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
-        // //println!("initalizing");
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        let root = params
+            .root_uri
+            .as_ref()
+            .and_then(|uri| uri.to_file_path().ok());
+        let mut config = match root.as_deref().map(Config::discover) {
+            Some(Ok(Some(config))) => config,
+            Some(Err(err)) => {
+                self.client.log_message(MessageType::ERROR, err).await;
+                Config::default()
+            }
+            _ => Config::default(),
+        };
+        if let Some(options) = params.initialization_options {
+            match Config::from_json(options) {
+                Ok(options) => {
+                    let options = match &root {
+                        Some(root) => options.resolve(root),
+                        None => options,
+                    };
+                    config = config.merge(options);
+                }
+                Err(err) => {
+                    self.client
+                        .log_message(
+                            MessageType::ERROR,
+                            format!("invalid initialization options: {}", err),
+                        )
+                        .await
+                }
+            }
+        }
+        *self.config.write().unwrap() = config;
+        *self.workspace_folders.write().unwrap() = match &params.workspace_folders {
+            Some(folders) => folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect(),
+            None => root.into_iter().collect(),
+        };
+        let encoding = PositionEncoding::negotiate(
+            params
+                .capabilities
+                .general
+                .as_ref()
+                .and_then(|general| general.position_encodings.as_deref()),
+        );
+        *self.position_encoding.write().unwrap() = encoding;
         Ok(InitializeResult {
             server_info: None,
             offset_encoding: None,
             capabilities: ServerCapabilities {
+                position_encoding: Some(encoding.kind()),
                 workspace: Some(WorkspaceServerCapabilities {
                     workspace_folders: Some(WorkspaceFoldersServerCapabilities {
                         supported: Some(true),
@@ -31,8 +121,33 @@ impl LanguageServer for Backend {
                     }),
                     file_operations: None,
                 }),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                definition_provider: Some(OneOf::Left(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions::default(),
+                })),
+                code_action_provider: Some(CodeActionProviderCapability::Options(
+                    CodeActionOptions {
+                        code_action_kinds: Some(vec![CodeActionKind::QUICKFIX]),
+                        ..CodeActionOptions::default()
+                    },
+                )),
+                completion_provider: Some(CompletionOptions {
+                    trigger_characters: Some(vec![".".to_string()]),
+                    ..CompletionOptions::default()
+                }),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: Default::default(),
+                }),
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
+                    TextDocumentSyncKind::INCREMENTAL,
                 )),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensRegistrationOptions(
@@ -50,10 +165,10 @@ impl LanguageServer for Backend {
                                 work_done_progress_options: WorkDoneProgressOptions::default(),
                                 legend: SemanticTokensLegend {
                                     token_types: LEGEND_TYPE.into(),
-                                    token_modifiers: vec![],
+                                    token_modifiers: LEGEND_MODIFIER.into(),
                                 },
                                 range: Some(true),
-                                full: Some(SemanticTokensFullOptions::Bool(true)),
+                                full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
                             },
                             static_registration_options: StaticRegistrationOptions::default(),
                         },
@@ -64,218 +179,213 @@ impl LanguageServer for Backend {
         })
     }
     async fn initialized(&self, _: InitializedParams) {
-        //println!("init");
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
+        let (schema, errors) = self.config.read().unwrap().schema();
+        *self.schema.write().unwrap() = schema;
+        for err in errors {
+            self.client
+                .show_message(
+                    MessageType::WARNING,
+                    format!("could not load schema: {}", err),
+                )
+                .await;
+        }
+        let folders = self.workspace_folders.read().unwrap().clone();
+        for folder in &folders {
+            self.index_folder(folder);
+        }
+        let watchers = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.oel".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "oel-files".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(watchers).ok(),
+        };
+        if let Err(err) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("cannot watch .oel files: {}", err),
+                )
+                .await;
+        }
     }
 
     async fn shutdown(&self) -> Result<()> {
-        //println!("shutdown");
         Ok(())
     }
 
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        //println!("did open");
-        self.client
-            .log_message(MessageType::INFO, "file opened!")
-            .await;
+        let uri = params.text_document.uri.to_string();
+        self.document_map
+            .insert(uri.clone(), Rope::from_str(&params.text_document.text));
+        self.version_map.insert(uri, params.text_document.version);
         self.on_change(TextDocumentItem {
             uri: params.text_document.uri,
             text: params.text_document.text,
             version: params.text_document.version,
+            old_tree: None,
         })
-        .await
-    }
-
-    async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
-        //println!("did change");
+        .await;
         self.client
-            .log_message(MessageType::INFO, "did change")
+            .log_message(MessageType::INFO, "file opened!")
             .await;
+    }
+
+    async fn did_change(&self, params: DidChangeTextDocumentParams) {
+        // Edits only apply to the version they were made against, so they
+        // are applied, and the document reparsed, before the first `await`
+        // lets another change in.
+        let uri = params.text_document.uri.to_string();
+        let encoding = self.position_encoding();
+        let (text, old_tree) = {
+            let mut rope = self.document_map.entry(uri.clone()).or_default();
+            self.version_map
+                .insert(uri.clone(), params.text_document.version);
+            let mut tree = self.tree_map.remove(&uri).map(|(_, tree)| tree);
+            for change in &params.content_changes {
+                match apply_change(&mut rope, change, encoding) {
+                    Some(edit) => {
+                        if let Some(tree) = tree.as_mut() {
+                            tree.edit(&edit);
+                        }
+                    }
+                    None => tree = None,
+                }
+            }
+            (rope.to_string(), tree)
+        };
         self.on_change(TextDocumentItem {
             uri: params.text_document.uri,
-            text: std::mem::take(&mut params.content_changes[0].text),
+            text,
             version: params.text_document.version,
+            old_tree,
         })
-        .await
+        .await;
     }
 
     async fn did_save(&self, _: DidSaveTextDocumentParams) {
-        //println!("did save");
         self.client
             .log_message(MessageType::INFO, "file saved!")
             .await;
     }
-    async fn did_close(&self, _: DidCloseTextDocumentParams) {
-        //println!("did close");
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.client
             .log_message(MessageType::INFO, "file closed!")
             .await;
+        let uri = params.text_document.uri;
+        self.document_map.remove(&uri.to_string());
+        self.version_map.remove(&uri.to_string());
+        self.ast_map.remove(&uri.to_string());
+        self.tree_map.remove(&uri.to_string());
+        self.semantic_token_map.remove(&uri.to_string());
+        self.semantic_token_results.remove(&uri.to_string());
+        // Unsaved changes are gone, so go back to the file on disk.
+        match uri.to_file_path() {
+            Ok(path) => self.index_file(&path),
+            Err(_) => self.workspace.remove(&uri),
+        }
     }
 
     async fn goto_definition(
         &self,
         params: GotoDefinitionParams,
     ) -> Result<Option<GotoDefinitionResponse>> {
-        //println!("goto_definition");
-        // let definition = async {
-        //     let uri = params.text_document_position_params.text_document.uri;
-        //     let ast = self.ast_map.get(uri.as_str())?;
-
-        //     let position = params.text_document_position_params.position;
-        //     let char = rope.try_line_to_char(position.line as usize).ok()?;
-        //     let offset = char + position.character as usize;
-        //     // self.client.log_message(MessageType::INFO, &format!("{:#?}, {}", ast.value(), offset)).await;
-        //     let span = get_definition(&ast, position);
-        //     self.client
-        //         .log_message(MessageType::INFO, &format!("{:?}, ", span))
-        //         .await;
-        //     span.and_then(|range| Some(GotoDefinitionResponse::Scalar(Location::new(uri, range))))
-        // }
-        // .await;
-        // Ok(definition)
-        Ok(None)
+        let directory = self.config.read().unwrap().directory.clone();
+        let location = get_definition(
+            &self.workspace,
+            &self.registry,
+            &self.schema.read().unwrap(),
+            directory.as_deref(),
+            &params.text_document_position_params.text_document.uri,
+            params.text_document_position_params.position,
+            self.position_encoding(),
+        );
+        Ok(location.map(GotoDefinitionResponse::Scalar))
+    }
+    async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
+        Ok(get_reference(
+            &self.workspace,
+            &params.text_document_position.text_document.uri,
+            params.text_document_position.position,
+            self.position_encoding(),
+        ))
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> Result<Option<Vec<DocumentHighlight>>> {
+        Ok(get_highlights(
+            &self.workspace,
+            &params.text_document_position_params.text_document.uri,
+            params.text_document_position_params.position,
+            self.position_encoding(),
+        ))
     }
-    // async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
-    //     let reference_list = || -> Option<Vec<Location>> {
-    //         let uri = params.text_document_position.text_document.uri;
-    //         let ast = self.ast_map.get(&uri.to_string())?;
-    //         let rope = self.document_map.get(&uri.to_string())?;
-
-    //         let position = params.text_document_position.position;
-    //         let char = rope.try_line_to_char(position.line as usize).ok()?;
-    //         let offset = char + position.character as usize;
-    //         let reference_list = get_reference(&ast, offset, false);
-    //         let ret = reference_list
-    //             .into_iter()
-    //             .filter_map(|(_, range)| {
-    //                 let start_position = offset_to_position(range.start, &rope)?;
-    //                 let end_position = offset_to_position(range.end, &rope)?;
-
-    //                 let range = Range::new(start_position, end_position);
-
-    //                 Some(Location::new(uri.clone(), range))
-    //             })
-    //             .collect::<Vec<_>>();
-    //         Some(ret)
-    //     }();
-    //     Ok(reference_list)
-    // }
 
     async fn semantic_tokens_full(
         &self,
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         let uri = params.text_document.uri.to_string();
-        self.client
-            .log_message(MessageType::LOG, "semantic_token_full")
-            .await;
+        let tokens = self.semantic_tokens(&uri, None).map(|data| {
+            SemanticTokensResult::Tokens(SemanticTokens {
+                result_id: Some(self.remember_semantic_tokens(&uri, &data)),
+                data,
+            })
+        });
+        Ok(tokens)
+    }
 
-        let toks = self
-            .semantic_token_map
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = params.text_document.uri.to_string();
+        let data = match self.semantic_tokens(&uri, None) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let previous = self
+            .semantic_token_results
             .get(&uri)
-            .iter()
-            .map(|tok| format!("tok: {:#?}", tok))
-            .collect::<Vec<_>>()
-            .join(", ");
-        self.client
-            .log_message(MessageType::LOG, format!("t: {}", toks))
-            .await;
-
-        let semantic_tokens = || -> Option<Vec<SemanticToken>> {
-            let mut im_complete_tokens = self.semantic_token_map.get_mut(&uri)?;
-            let ast = self.ast_map.get(&uri)?;
-            let extends_tokens = semantic_token_from_ast(&ast);
-            im_complete_tokens.extend(extends_tokens);
-            im_complete_tokens.sort_by(|a, b| a.start.cmp(&b.start));
-            let mut pre_line = 0;
-            let mut pre_start = 0;
-
-            let semantic_tokens = im_complete_tokens
-                .iter()
-                .filter_map(|token| {
-                    // TODO: CAST?
-                    let line = token.start.row as u32;
-                    let start = token.start.column as u32;
-                    let delta_line = line - pre_line;
-                    let delta_start = if delta_line == 0 {
-                        start - pre_start
-                    } else {
-                        start
-                    };
-                    let ret = Some(SemanticToken {
-                        delta_line,
-                        delta_start,
-                        length: (token.end.column - token.start.column) as u32,
-                        token_type: token.token_type as u32,
-                        token_modifiers_bitset: 0,
-                    });
-                    pre_line = line;
-                    pre_start = start;
-                    ret
-                })
-                .collect::<Vec<_>>();
-            Some(semantic_tokens)
-        }();
-        if let Some(semantic_token) = semantic_tokens {
-            return Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
-                result_id: None,
-                data: semantic_token,
-            })));
-        }
-        Ok(None)
+            .filter(|previous| previous.0 == params.previous_result_id)
+            .map(|previous| previous.1.clone());
+        let result_id = Some(self.remember_semantic_tokens(&uri, &data));
+        Ok(Some(match previous {
+            Some(previous) => SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                result_id,
+                edits: delta(&previous, &data),
+            }),
+            None => SemanticTokensFullDeltaResult::Tokens(SemanticTokens { result_id, data }),
+        }))
     }
 
     async fn semantic_tokens_range(
         &self,
         params: SemanticTokensRangeParams,
     ) -> Result<Option<SemanticTokensRangeResult>> {
-        //println!("semantic_tokens_range");
-        // let uri = params.text_document.uri.to_string();
-        // let semantic_tokens = || -> Option<Vec<SemanticToken>> {
-        //     let im_complete_tokens = self.semantic_token_map.get(&uri)?;
-        //     let rope = self.document_map.get(&uri)?;
-        //     let mut pre_line = 0;
-        //     let mut pre_start = 0;
-        //     let semantic_tokens = im_complete_tokens
-        //         .iter()
-        //         .filter_map(|token| {
-        //             let line = rope.try_byte_to_line(token.start as usize).ok()? as u32;
-        //             let first = rope.try_line_to_char(line as usize).ok()? as u32;
-        //             let start = rope.try_byte_to_char(token.start as usize).ok()? as u32 - first;
-        //             let ret = Some(SemanticToken {
-        //                 delta_line: line - pre_line,
-        //                 delta_start: if start >= pre_start {
-        //                     start - pre_start
-        //                 } else {
-        //                     start
-        //                 },
-        //                 length: token.length as u32,
-        //                 token_type: token.token_type as u32,
-        //                 token_modifiers_bitset: 0,
-        //             });
-        //             pre_line = line;
-        //             pre_start = start;
-        //             ret
-        //         })
-        //         .collect::<Vec<_>>();
-        //     Some(semantic_tokens)
-        // }();
-        // if let Some(semantic_token) = semantic_tokens {
-        //     return Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
-        //         result_id: None,
-        //         data: semantic_token,
-        //     })));
-        // }
-        Ok(None)
+        let uri = params.text_document.uri.to_string();
+        let tokens = self.semantic_tokens(&uri, Some(params.range)).map(|data| {
+            SemanticTokensRangeResult::Tokens(SemanticTokens {
+                result_id: None,
+                data,
+            })
+        });
+        Ok(tokens)
     }
 
     async fn inlay_hint(
         &self,
         params: tower_lsp::lsp_types::InlayHintParams,
     ) -> Result<Option<Vec<InlayHint>>> {
-        //println!("inlay_hint");
         // self.client
         //     .log_message(MessageType::INFO, "inlay hint")
         //     .await;
@@ -339,111 +449,292 @@ impl LanguageServer for Backend {
         Ok(None)
     }
 
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> Result<Option<DocumentSymbolResponse>> {
+        let uri = params.text_document.uri;
+        let symbols = || -> Option<Vec<DocumentSymbol>> {
+            let src = self.document_map.get(&uri.to_string())?.to_string();
+            let index = LineIndex::new(&src, self.position_encoding());
+            let entries = self.ast_map.get(&uri.to_string())?;
+            Some(document_symbols(&entries, &index))
+        }();
+        Ok(symbols.map(DocumentSymbolResponse::Nested))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let hover = || -> Option<Hover> {
+            let src = self.document_map.get(&uri.to_string())?.to_string();
+            let index = LineIndex::new(&src, self.position_encoding());
+            let entries = self.ast_map.get(&uri.to_string())?;
+            let position = index.point(position);
+            let (markdown, span) = hover(
+                &entry_at(&entries, position)?.value,
+                position,
+                &self.registry,
+                &self.schema.read().unwrap(),
+            )?;
+            Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: markdown,
+                }),
+                range: Some(index.range(span.start, span.end)),
+            })
+        }();
+        Ok(hover)
+    }
+
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
-        //println!("completion");
-        // let uri = params.text_document_position.text_document.uri;
-        // let position = params.text_document_position.position;
-        // let completions = || -> Option<Vec<CompletionItem>> {
-        //     let ast = self.ast_map.get(&uri.to_string())?;
-        //     let offset = char + position.character as usize;
-        //     let completions = completion(&ast, offset);
-        //     let mut ret = Vec::with_capacity(completions.len());
-        //     for (_, item) in completions {
-        //         match item {
-        //             oel_language_server::completion::ImCompleteCompletionItem::Variable(var) => {
-        //                 ret.push(CompletionItem {
-        //                     label: var.clone(),
-        //                     insert_text: Some(var.clone()),
-        //                     kind: Some(CompletionItemKind::VARIABLE),
-        //                     detail: Some(var),
-        //                     ..Default::default()
-        //                 });
-        //             }
-        //             oel_language_server::completion::ImCompleteCompletionItem::Function(
-        //                 name,
-        //                 args,
-        //             ) => {
-        //                 ret.push(CompletionItem {
-        //                     label: name.clone(),
-        //                     kind: Some(CompletionItemKind::FUNCTION),
-        //                     detail: Some(name.clone()),
-        //                     insert_text: Some(format!(
-        //                         "{}({})",
-        //                         name,
-        //                         args.iter()
-        //                             .enumerate()
-        //                             .map(|(index, item)| { format!("${{{}:{}}}", index + 1, item) })
-        //                             .collect::<Vec<_>>()
-        //                             .join(",")
-        //                     )),
-        //                     insert_text_format: Some(InsertTextFormat::SNIPPET),
-        //                     ..Default::default()
-        //                 });
-        //             }
-        //         }
-        //     }
-        //     Some(ret)
-        // }();
-        // Ok(completions.map(CompletionResponse::Array))
-        Ok(None)
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let completions = || -> Option<Vec<CompletionItem>> {
+            let src = self.document_map.get(&uri.to_string())?.to_string();
+            let index = LineIndex::new(&src, self.position_encoding());
+            let entries = self.ast_map.get(&uri.to_string());
+            let position = index.point(position);
+            let completions = completion(
+                entries
+                    .as_deref()
+                    .and_then(|entries| entry_at(entries, position))
+                    .map(|entry| &entry.value),
+                &src,
+                position,
+                &self.registry,
+                &self.schema.read().unwrap(),
+                self.expression_context(&uri, &src),
+                self.dialect(&uri, &src),
+            );
+            let mut ret = Vec::with_capacity(completions.len());
+            for (label, item) in completions {
+                ret.push(match item {
+                    ImCompleteCompletionItem::Variable(var) => CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::VARIABLE),
+                        detail: Some(var),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Attribute { name, ty, title } => CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::FIELD),
+                        detail: Some(ty.to_string()),
+                        documentation: title.map(Documentation::String),
+                        insert_text: Some(name),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Namespace(namespace) => CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::MODULE),
+                        detail: Some(namespace),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Function { name, signature } => CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::FUNCTION),
+                        detail: Some(signature.to_string()),
+                        insert_text: Some(ImCompleteCompletionItem::snippet(&name, &signature)),
+                        insert_text_format: Some(InsertTextFormat::SNIPPET),
+                        ..Default::default()
+                    },
+                    ImCompleteCompletionItem::Method { name, signature } => CompletionItem {
+                        label,
+                        kind: Some(CompletionItemKind::METHOD),
+                        detail: Some(signature.to_string()),
+                        insert_text: Some(ImCompleteCompletionItem::snippet(&name, &signature)),
+                        insert_text_format: Some(InsertTextFormat::SNIPPET),
+                        ..Default::default()
+                    },
+                });
+            }
+            Some(ret)
+        }();
+        Ok(completions.map(CompletionResponse::Array))
+    }
+
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+        let help = || -> Option<SignatureHelp> {
+            let src = self.document_map.get(&uri.to_string())?.to_string();
+            let index = LineIndex::new(&src, self.position_encoding());
+            let entries = self.ast_map.get(&uri.to_string());
+            let position = index.point(position);
+            let help = signature_help(
+                entries
+                    .as_deref()
+                    .and_then(|entries| entry_at(entries, position))
+                    .map(|entry| &entry.value),
+                &src,
+                position,
+                &self.registry,
+                &self.schema.read().unwrap(),
+            )?;
+            let documentation = Catalogue::get().function(&help.name).map(|doc| {
+                Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: doc.description.clone(),
+                })
+            });
+            let signatures = help
+                .signatures
+                .iter()
+                .map(|signature| SignatureInformation {
+                    label: format!("{}{}", help.name, signature),
+                    documentation: documentation.clone(),
+                    parameters: Some(
+                        signature
+                            .params
+                            .iter()
+                            .map(|param| ParameterInformation {
+                                label: ParameterLabel::Simple(param.to_string()),
+                                documentation: None,
+                            })
+                            .collect(),
+                    ),
+                    active_parameter: None,
+                })
+                .collect();
+            Some(SignatureHelp {
+                signatures,
+                active_signature: Some(help.active_signature as u32),
+                active_parameter: Some(help.active_parameter as u32),
+            })
+        }();
+        Ok(help)
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let actions = || -> Option<CodeActionResponse> {
+            let src = self.document_map.get(&uri.to_string())?.to_string();
+            let index = LineIndex::new(&src, self.position_encoding());
+            let mut actions = Vec::new();
+            for diagnostic in params.context.diagnostics {
+                let fixes: Vec<Fix> = match diagnostic
+                    .data
+                    .clone()
+                    .and_then(|data| serde_json::from_value(data).ok())
+                {
+                    Some(fixes) => fixes,
+                    None => continue,
+                };
+                for fix in fixes {
+                    let edit = match &fix {
+                        Fix::Replace(text) => TextEdit::new(diagnostic.range, text.clone()),
+                        Fix::SetContext(context) => {
+                            let (start, end, text) =
+                                pragma_edit(&src, expression_context::PRAGMA, context.id());
+                            TextEdit::new(index.range(start, end), text)
+                        }
+                        Fix::SetDialect(dialect) => {
+                            let (start, end, text) =
+                                pragma_edit(&src, dialect::PRAGMA, dialect.id());
+                            TextEdit::new(index.range(start, end), text)
+                        }
+                    };
+                    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title: fix.title(),
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(WorkspaceEdit::new(
+                            [(uri.clone(), vec![edit])].into_iter().collect(),
+                        )),
+                        ..CodeAction::default()
+                    }));
+                }
+            }
+            Some(actions)
+        }();
+        Ok(actions)
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> Result<Option<PrepareRenameResponse>> {
+        prepare_rename(
+            &self.workspace,
+            &self.registry,
+            &params.text_document.uri,
+            params.position,
+            self.position_encoding(),
+        )
+        .map(|prepared| {
+            prepared.map(
+                |(range, placeholder)| PrepareRenameResponse::RangeWithPlaceholder {
+                    range,
+                    placeholder,
+                },
+            )
+        })
+        .map_err(tower_lsp::jsonrpc::Error::invalid_params)
     }
 
     async fn rename(&self, params: RenameParams) -> Result<Option<WorkspaceEdit>> {
-        //println!("rename");
-        // let workspace_edit = || -> Option<WorkspaceEdit> {
-        //     let uri = params.text_document_position.text_document.uri;
-        //     let ast = self.ast_map.get(&uri.to_string())?;
-        //     let rope = self.document_map.get(&uri.to_string())?;
-
-        //     let position = params.text_document_position.position;
-        //     let char = rope.try_line_to_char(position.line as usize).ok()?;
-        //     let offset = char + position.character as usize;
-        //     let reference_list = get_reference(&ast, position, true);
-        //     let new_name = params.new_name;
-        //     if reference_list.len() > 0 {
-        //         let edit_list = reference_list
-        //             .into_iter()
-        //             .filter_map(|(_, range)| {
-        //                 // let start_position = offset_to_position(range.start, &rope)?;
-        //                 // let end_position = offset_to_position(range.end, &rope)?;
-        //                 Some(TextEdit::new(range, new_name.clone()))
-        //             })
-        //             .collect::<Vec<_>>();
-        //         let mut map = HashMap::new();
-        //         map.insert(uri, edit_list);
-        //         let workspace_edit = WorkspaceEdit::new(map);
-        //         Some(workspace_edit)
-        //     } else {
-        //         None
-        //     }
-        // }();
-        // Ok(workspace_edit)
-        Ok(None)
+        rename(
+            &self.workspace,
+            &self.registry,
+            &params.text_document_position.text_document.uri,
+            params.text_document_position.position,
+            &params.new_name,
+            self.position_encoding(),
+        )
+        .map_err(tower_lsp::jsonrpc::Error::invalid_params)
     }
 
     async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
-        //println!("did_change_configuration");
         self.client
             .log_message(MessageType::INFO, "configuration changed!")
             .await;
     }
 
-    async fn did_change_workspace_folders(&self, _: DidChangeWorkspaceFoldersParams) {
-        //println!("did_change_workspace_folders");
+    async fn did_change_workspace_folders(&self, params: DidChangeWorkspaceFoldersParams) {
         self.client
             .log_message(MessageType::INFO, "workspace folders changed!")
             .await;
+        for folder in params.event.removed {
+            if let Ok(path) = folder.uri.to_file_path() {
+                self.workspace.remove_folder(&path);
+                self.workspace_folders
+                    .write()
+                    .unwrap()
+                    .retain(|folder| *folder != path);
+            }
+        }
+        for folder in params.event.added {
+            if let Ok(path) = folder.uri.to_file_path() {
+                self.index_folder(&path);
+                self.workspace_folders.write().unwrap().push(path);
+            }
+        }
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
-        //println!("did_change_watched_files");
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
         self.client
             .log_message(MessageType::INFO, "watched files have changed!")
             .await;
+        for change in params.changes {
+            match change.uri.to_file_path() {
+                Ok(path) if is_oel_file(&path) => self.index_file(&path),
+                _ => {}
+            }
+        }
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        Ok(Some(
+            self.workspace
+                .symbols(&params.query, self.position_encoding()),
+        ))
     }
 
     async fn execute_command(&self, _: ExecuteCommandParams) -> Result<Option<Value>> {
-        //println!("execute_command");
         self.client
             .log_message(MessageType::INFO, "command executed!")
             .await;
@@ -467,65 +758,180 @@ impl Notification for CustomNotification {
     type Params = InlayHintParams;
     const METHOD: &'static str = "custom/notification";
 }
+#[derive(Debug, Deserialize)]
+struct BuiltinDocumentParams {
+    uri: Url,
+}
+
 struct TextDocumentItem {
     uri: Url,
     text: String,
     version: i32,
+    /// The tree of the previous version, edited to match `text`.
+    old_tree: Option<Tree>,
 }
 impl Backend {
+    /// Answers [`BUILTIN_DOCUMENT_METHOD`], for the documents go to
+    /// definition opens for built-in functions.
+    async fn builtin_document(&self, params: BuiltinDocumentParams) -> Result<Option<String>> {
+        Ok(builtin_document(&params.uri, &self.registry))
+    }
+
+    fn expression_context(&self, uri: &Url, src: &str) -> ExpressionContext {
+        let default = self.config.read().unwrap().context.unwrap_or_default();
+        ExpressionContext::detect(uri.to_file_path().ok().as_deref(), src, default)
+    }
+
+    fn dialect(&self, uri: &Url, src: &str) -> Dialect {
+        let default = self.config.read().unwrap().dialect;
+        let context = self.expression_context(uri, src);
+        Dialect::detect(uri.to_file_path().ok().as_deref(), src, default, context)
+    }
+
+    /// Indexes a file from disk, or forgets it if it is gone. Open documents
+    /// are indexed as they change instead.
+    fn index_file(&self, path: &Path) {
+        let uri = match Url::from_file_path(path) {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        if self.document_map.contains_key(&uri.to_string()) {
+            return;
+        }
+        match std::fs::read_to_string(path) {
+            Ok(src) => {
+                let dialect = self.dialect(&uri, &src);
+                self.workspace.insert(uri, FileIndex::parse(src, dialect));
+            }
+            Err(_) => self.workspace.remove(&uri),
+        }
+    }
+
+    fn index_folder(&self, folder: &Path) {
+        for path in oel_files(folder) {
+            self.index_file(&path);
+        }
+    }
+
+    fn position_encoding(&self) -> PositionEncoding {
+        *self.position_encoding.read().unwrap()
+    }
+
+    fn semantic_tokens(&self, uri: &str, range: Option<Range>) -> Option<Vec<SemanticToken>> {
+        let src = self.document_map.get(uri)?.to_string();
+        let index = LineIndex::new(&src, self.position_encoding());
+        let tokens = self.semantic_token_map.get(uri)?;
+        Some(encode(&tokens, &index, range))
+    }
+
+    /// Stores `data` as the latest tokens of `uri` and returns its result id.
+    fn remember_semantic_tokens(&self, uri: &str, data: &[SemanticToken]) -> String {
+        let result_id = self
+            .next_result_id
+            .fetch_add(1, Ordering::Relaxed)
+            .to_string();
+        self.semantic_token_results
+            .insert(uri.to_string(), (result_id.clone(), data.to_vec()));
+        result_id
+    }
+
     async fn on_change(&self, params: TextDocumentItem) {
-        //println!("on_change");
-        self.client
-            .log_message(MessageType::INFO, format!("parsing ast: {}", params.uri))
-            .await;
-        let (ast, errors, semantic_tokens) = parse(&params.text);
-        self.client
-            .log_message(MessageType::INFO, format!("errors: {:?}", errors))
-            .await;
-        self.client
-            .log_message(
-                MessageType::INFO,
-                format!("semantic_tokens: {:?}", semantic_tokens),
-            )
-            .await;
+        let uri = params.uri.to_string();
+        let index = LineIndex::new(&params.text, self.position_encoding());
+        let tree = parse_tree(&params.text, params.old_tree.as_ref());
+        let (entries, errors) = match &tree {
+            Some(tree) => analyze(tree, &params.text),
+            None => (Vec::new(), Vec::new()),
+        };
+        let semantic_tokens = tree
+            .as_ref()
+            .map(|tree| {
+                semantic_tokens(
+                    tree,
+                    &params.text,
+                    &self.registry,
+                    &self.schema.read().unwrap(),
+                )
+            })
+            .unwrap_or_default();
+        // Logged at debug level only, as this runs on every keystroke.
+        log::debug!(
+            "{}: {} syntax errors, {} semantic tokens",
+            params.uri,
+            errors.len(),
+            semantic_tokens.len()
+        );
+        let context = self.expression_context(&params.uri, &params.text);
+        let dialect = self.dialect(&params.uri, &params.text);
+        let mut type_errors = tree
+            .as_ref()
+            .map(|tree| dialect::check(tree, &params.text, dialect))
+            .unwrap_or_default();
+        type_errors.extend(typecheck::check_entries(
+            &entries,
+            &self.registry,
+            &self.schema.read().unwrap(),
+            context,
+            dialect,
+        ));
+
+        // A newer version has been applied, or the document was closed,
+        // since this one was handed over: its results are out of date, and
+        // its tree no longer matches the text the next edit applies to.
+        if self.version_map.get(&uri).map(|version| *version) != Some(params.version) {
+            return;
+        }
+        if let Some(tree) = tree {
+            self.tree_map.insert(uri.clone(), tree);
+        }
+        self.workspace.insert(
+            params.uri.clone(),
+            FileIndex::new(params.text.clone(), entries.clone(), dialect),
+        );
+        if !entries.is_empty() {
+            self.ast_map.insert(uri.clone(), entries);
+        }
+        self.semantic_token_map.insert(uri, semantic_tokens);
+
         let diagnostics = errors
             .into_iter()
-            .filter_map(|item: oel_language_server::oel::ErrorToken| {
-                let diagnostic = || -> Option<Diagnostic> {
-                    // TODO: OMG
-                    Some(Diagnostic::new_simple(
-                        Range::new(
-                            Position {
-                                line: item.start.row.try_into().unwrap(),
-                                character: item.start.column.try_into().unwrap(),
-                            },
-                            Position {
-                                line: item.end.row.try_into().unwrap(),
-                                character: item.end.row.try_into().unwrap(),
-                            },
+            .map(|item| {
+                let related_information = item
+                    .related
+                    .into_iter()
+                    .map(|related| DiagnosticRelatedInformation {
+                        location: Location::new(
+                            params.uri.clone(),
+                            index.range(related.start, related.end),
                         ),
-                        item.message,
-                    ))
-                }();
-                diagnostic
+                        message: related.message,
+                    })
+                    .collect::<Vec<_>>();
+                Diagnostic {
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    related_information: Some(related_information)
+                        .filter(|related| !related.is_empty()),
+                    ..Diagnostic::new_simple(index.range(item.start, item.end), item.message)
+                }
             })
+            .chain(type_errors.into_iter().map(|error| {
+                Diagnostic {
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    // Read back by `code_action`.
+                    data: Some(error.fixes)
+                        .filter(|fixes| !fixes.is_empty())
+                        .and_then(|fixes| serde_json::to_value(fixes).ok()),
+                    ..Diagnostic::new_simple(
+                        index.range(error.span.start, error.span.end),
+                        error.msg,
+                    )
+                }
+            }))
             .collect::<Vec<_>>();
 
         self.client
             .publish_diagnostics(params.uri.clone(), diagnostics, Some(params.version))
             .await;
-
-        if let Some(ast) = ast {
-            self.ast_map.insert(params.uri.to_string(), ast);
-        }
-        self.client
-            .log_message(
-                MessageType::INFO,
-                &format!("semantic tokens: {:?}", semantic_tokens),
-            )
-            .await;
-        self.semantic_token_map
-            .insert(params.uri.to_string(), semantic_tokens);
     }
 }
 
@@ -537,12 +943,23 @@ async fn main() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    // //println!("Starting server...");
-
-    let (service, socket) = LspService::new(|client| Backend {
+    let (service, socket) = LspService::build(|client| Backend {
         client,
         ast_map: DashMap::new(),
+        document_map: DashMap::new(),
+        version_map: DashMap::new(),
+        tree_map: DashMap::new(),
         semantic_token_map: DashMap::new(),
-    });
+        semantic_token_results: DashMap::new(),
+        next_result_id: AtomicU64::new(0),
+        registry: Registry::default(),
+        config: RwLock::new(Config::default()),
+        position_encoding: RwLock::new(PositionEncoding::default()),
+        schema: RwLock::new(Schema::default()),
+        workspace_folders: RwLock::new(Vec::new()),
+        workspace: WorkspaceIndex::default(),
+    })
+    .custom_method(BUILTIN_DOCUMENT_METHOD, Backend::builtin_document)
+    .finish();
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -1,16 +1,14 @@
-use crate::semantic_token::LEGEND_TYPE;
+use crate::syntax_error::syntax_errors;
 use chumsky::{prelude::*, stream::Stream};
 use core::fmt;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use tower_lsp::lsp_types::{Position, Range, SemanticTokenType};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 
 use std::collections::HashSet;
 use std::iter::FromIterator;
 use tree_sitter::{Node, Point, Tree};
 
-use tree_sitter_traversal::{traverse, traverse_tree, Order};
-
 /// This is the parser and interpreter for the 'Foo' language. See `tutorial.md` in the repository's root to learn
 /// about it.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
@@ -20,17 +18,37 @@ pub struct Span {
     pub end: Point,
 }
 
+impl From<Node<'_>> for Span {
+    fn from(node: Node<'_>) -> Self {
+        Span {
+            id: node.id(),
+            start: node.start_position(),
+            end: node.end_position(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ImCompleteSemanticToken {
     pub start: Point,
     pub end: Point,
     pub token_type: usize,
+    /// Bitset over [`LEGEND_MODIFIER`](crate::semantic_token::LEGEND_MODIFIER).
+    pub modifiers: u32,
 }
 #[derive(Debug)]
 pub struct ErrorToken {
     pub start: Point,
     pub end: Point,
     pub message: String,
+    /// Other places the error relates to, e.g. the `(` that was never closed.
+    pub related: Vec<RelatedSpan>,
+}
+#[derive(Debug)]
+pub struct RelatedSpan {
+    pub start: Point,
+    pub end: Point,
+    pub message: String,
 }
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Token {
@@ -71,17 +89,75 @@ impl fmt::Display for Token {
 pub enum Value {
     Null,
     Bool(bool),
+    Int(i64),
     Num(f64),
     Str(String),
     List(Vec<Value>),
+    Object(BTreeMap<String, Value>),
     Func(String),
 }
 
+impl Value {
+    /// The Okta name of the value's type, used in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Bool(_) => "Boolean",
+            Self::Int(_) => "Integer",
+            Self::Num(_) => "Number",
+            Self::Str(_) => "String",
+            Self::List(_) => "Array",
+            Self::Object(_) => "Object",
+            Self::Func(_) => "Function",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Null => serde_json::Value::Null,
+            Self::Bool(x) => serde_json::Value::Bool(*x),
+            Self::Int(x) => serde_json::Value::from(*x),
+            Self::Num(x) => serde_json::Value::from(*x),
+            Self::Str(x) => serde_json::Value::String(x.clone()),
+            Self::List(xs) => serde_json::Value::Array(xs.iter().map(Value::to_json).collect()),
+            Self::Object(fields) => serde_json::Value::Object(
+                fields
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.to_json()))
+                    .collect(),
+            ),
+            Self::Func(name) => serde_json::Value::String(name.clone()),
+        }
+    }
+}
+
+impl From<serde_json::Value> for Value {
+    fn from(json: serde_json::Value) -> Self {
+        match json {
+            serde_json::Value::Null => Self::Null,
+            serde_json::Value::Bool(x) => Self::Bool(x),
+            serde_json::Value::Number(n) => match n.as_i64() {
+                Some(x) => Self::Int(x),
+                None => Self::Num(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            serde_json::Value::String(x) => Self::Str(x),
+            serde_json::Value::Array(xs) => Self::List(xs.into_iter().map(Value::from).collect()),
+            serde_json::Value::Object(fields) => Self::Object(
+                fields
+                    .into_iter()
+                    .map(|(k, v)| (k, Value::from(v)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
 impl std::fmt::Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             Self::Null => write!(f, "null"),
             Self::Bool(x) => write!(f, "{}", x),
+            Self::Int(x) => write!(f, "{}", x),
             Self::Num(x) => write!(f, "{}", x),
             Self::Str(x) => write!(f, "{}", x),
             Self::List(xs) => write!(
@@ -92,222 +168,835 @@ impl std::fmt::Display for Value {
                     .collect::<Vec<_>>()
                     .join(", ")
             ),
+            Self::Object(fields) => write!(
+                f,
+                "{{{}}}",
+                fields
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", k, v))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
             Self::Func(name) => write!(f, "<function: {}>", name),
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum BinaryOp {
     Add,
     Sub,
     Mul,
     Div,
+    Mod,
     Eq,
     NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+    /// Identity Engine regular expression match, `x matches '[a-z]+'`.
+    Matches,
 }
 
-pub type Spanned<T> = (T, Range);
-// A function node in the AST.
+impl BinaryOp {
+    /// Maps the `operator` field of a `binary_expression` onto its operator.
+    /// The Identity Engine spellings (`and`, `eq`, ...) map onto the same
+    /// operators as their Classic counterparts.
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "+" => Some(Self::Add),
+            "-" => Some(Self::Sub),
+            "*" => Some(Self::Mul),
+            "/" => Some(Self::Div),
+            "%" => Some(Self::Mod),
+            "==" | "eq" => Some(Self::Eq),
+            "!=" | "ne" => Some(Self::NotEq),
+            "<" | "lt" => Some(Self::Lt),
+            "<=" | "le" => Some(Self::LtEq),
+            ">" | "gt" => Some(Self::Gt),
+            ">=" | "ge" => Some(Self::GtEq),
+            "AND" | "and" | "&&" => Some(Self::And),
+            "OR" | "or" | "||" => Some(Self::Or),
+            "matches" => Some(Self::Matches),
+            _ => None,
+        }
+    }
+}
 
-pub fn parse(
-    src: &str,
-) -> (
-    Option<HashMap<String, Span>>,
-    Vec<ErrorToken>,
-    Vec<ImCompleteSemanticToken>,
-) {
-    let mut parser = tree_sitter::Parser::new();
-    let set_language_result = parser.set_language(tree_sitter_oel::language());
-    if set_language_result.is_err() {
-        return (None, Vec::new(), Vec::new());
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let token = match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Mod => "%",
+            Self::Eq => "==",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Matches => "matches",
+        };
+        write!(f, "{}", token)
     }
+}
 
-    let tree = parser.parse(src, None);
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+    Not,
+    Neg,
+}
 
-    return if let Some(tree) = tree {
-        let preorder: Vec<Node<'_>> = traverse(tree.walk(), Order::Pre).collect::<Vec<_>>();
+impl UnaryOp {
+    /// Maps the `operator` field of a `unary_expression` onto its operator.
+    pub fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "!" => Some(Self::Not),
+            "-" => Some(Self::Neg),
+            _ => None,
+        }
+    }
+}
 
-        let semantic_tokens = preorder
-            .iter()
-            .filter_map(|token| {
-                // println!("token kind: {}", token.kind());
-                match token.kind() {
-                    "null" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::KEYWORD)
-                            .unwrap(),
-                    }),
-                    "bool" => None,
-                    "arguments" => None,
-                    "array" => None,
-                    "binary_expression" => None,
-                    "boolean" => None,
-                    "call_expression" => None,
-                    "expression" => None,
-                    "member_expression" => None,
-                    "nested_identifier" => None,
-                    "parenthesized_expression" => None,
-                    "primary_expression" => None,
-                    "primitive" => None,
-                    "source_file" => None,
-                    "string" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::STRING)
-                            .unwrap(),
-                    }),
-                    "subscript_expression" => None,
-                    "ternary_expression" => None,
-                    "unary_expression" => None,
-                    "!" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "!=" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "\"" => None,
-                    "'" => None,
-                    "(" => None,
-                    ")" => None,
-                    "+" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "," => None,
-                    "." => None,
-                    ":" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "<" => None,
-                    "<=" => None,
-                    "==" => None,
-                    ">" => None,
-                    ">=" => None,
-                    "?" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "AND" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "OR" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::OPERATOR)
-                            .unwrap(),
-                    }),
-                    "[" => None,
-                    "]" => None,
-                    "false" => None,
-                    "float" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::NUMBER)
-                            .unwrap(),
-                    }),
-                    "identifier" => None,
-                    "integer" => Some(ImCompleteSemanticToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        token_type: LEGEND_TYPE
-                            .iter()
-                            .position(|item| item == &SemanticTokenType::NUMBER)
-                            .unwrap(),
-                    }),
-                    "property_identifier" => None,
-                    "string_fragment" => None,
-                    "true" => None,
-                    "{" => None,
-                    "}" => None,
-                    _ => None,
+impl fmt::Display for UnaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Not => write!(f, "!"),
+            Self::Neg => write!(f, "-"),
+        }
+    }
+}
+
+pub type Spanned<T> = (T, Span);
+
+/// A key of an Identity Engine map literal.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MapKey {
+    pub name: String,
+    /// Whether the key was a string literal rather than an identifier or an
+    /// integer.
+    pub quoted: bool,
+}
+
+/// The typed AST, lowered from the tree-sitter CST by [`lower`].
+///
+/// Field names follow the fields in `grammar.js`. Regions that tree-sitter
+/// had to recover from (`ERROR` and `MISSING` nodes) become [`Expr::Error`]
+/// so that the rest of the expression is still usable.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Error,
+    Value(Value),
+    Ident(String),
+    Array(Vec<Spanned<Expr>>),
+    /// An Identity Engine map literal. Keys may be strings, identifiers or
+    /// integers.
+    Map(Vec<(Spanned<MapKey>, Spanned<Expr>)>),
+    Member {
+        object: Box<Spanned<Expr>>,
+        property: Spanned<String>,
+    },
+    Subscript {
+        object: Box<Spanned<Expr>>,
+        index: Box<Spanned<Expr>>,
+    },
+    Call {
+        function: Box<Spanned<Expr>>,
+        arguments: Vec<Spanned<Expr>>,
+    },
+    Unary {
+        op: UnaryOp,
+        argument: Box<Spanned<Expr>>,
+    },
+    Binary {
+        op: BinaryOp,
+        left: Box<Spanned<Expr>>,
+        right: Box<Spanned<Expr>>,
+    },
+    Ternary {
+        condition: Box<Spanned<Expr>>,
+        consequence: Box<Spanned<Expr>>,
+        alternative: Box<Spanned<Expr>>,
+    },
+}
+
+impl Expr {
+    /// The direct sub-expressions, in source order.
+    pub fn children(&self) -> Vec<&Spanned<Expr>> {
+        match self {
+            Expr::Error | Expr::Value(_) | Expr::Ident(_) => Vec::new(),
+            Expr::Array(items) => items.iter().collect(),
+            Expr::Map(entries) => entries.iter().map(|(_, value)| value).collect(),
+            Expr::Member { object, .. } => vec![object],
+            Expr::Subscript { object, index } => vec![object, index],
+            Expr::Call {
+                function,
+                arguments,
+            } => std::iter::once(function.as_ref())
+                .chain(arguments.iter())
+                .collect(),
+            Expr::Unary { argument, .. } => vec![argument],
+            Expr::Binary { left, right, .. } => vec![left, right],
+            Expr::Ternary {
+                condition,
+                consequence,
+                alternative,
+            } => vec![condition, consequence, alternative],
+        }
+    }
+
+    /// The dotted name of an identifier or member chain, e.g. `String.len`
+    /// or `user.department`. `None` for anything else.
+    pub fn path(&self) -> Option<String> {
+        match self {
+            Expr::Ident(name) => Some(name.clone()),
+            Expr::Member { object, property } => {
+                Some(format!("{}.{}", object.0.path()?, property.0))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Pre-order walk over an expression and all of its sub-expressions.
+pub fn walk<'a>(expr: &'a Spanned<Expr>, f: &mut impl FnMut(&'a Spanned<Expr>)) {
+    f(expr);
+    for child in expr.0.children() {
+        walk(child, f);
+    }
+}
+
+fn node_text<'a>(node: Node<'_>, src: &'a str) -> &'a str {
+    node.utf8_text(src.as_bytes()).unwrap_or_default()
+}
+
+/// Lowers a required field, falling back to an error node at the end of the
+/// parent when tree-sitter could not recover it.
+fn lower_field(node: Node<'_>, field: &str, src: &str) -> Box<Spanned<Expr>> {
+    let lowered = match node.child_by_field_name(field) {
+        Some(child) => lower(child, src),
+        None => (
+            Expr::Error,
+            Span {
+                id: node.id(),
+                start: node.end_position(),
+                end: node.end_position(),
+            },
+        ),
+    };
+    Box::new(lowered)
+}
+
+fn lower_named_children(node: Node<'_>, src: &str) -> Vec<Spanned<Expr>> {
+    let mut cursor = node.walk();
+    let children = node
+        .named_children(&mut cursor)
+        .filter(|child| !child.is_extra())
+        .map(|child| lower(child, src))
+        .collect();
+    children
+}
+
+/// Lowers a tree-sitter node (usually the root of the tree) into the typed AST.
+pub fn lower(node: Node<'_>, src: &str) -> Spanned<Expr> {
+    let span = Span::from(node);
+    if node.is_error() || node.is_missing() {
+        return (Expr::Error, span);
+    }
+    let expr = match node.kind() {
+        // Wrapper rules with a single meaningful child.
+        "source_file"
+        | "expression"
+        | "primary_expression"
+        | "primitive"
+        | "boolean"
+        | "parenthesized_expression" => {
+            let mut cursor = node.walk();
+            let inner = node
+                .named_children(&mut cursor)
+                .find(|child| !child.is_error() && !child.is_extra())
+                .map(|child| lower(child, src));
+            return match inner {
+                // Keep the parentheses in the span so that diagnostics cover them.
+                Some((expr, _)) if node.kind() == "parenthesized_expression" => (expr, span),
+                Some(inner) => inner,
+                None => (Expr::Error, span),
+            };
+        }
+        "ternary_expression" => Expr::Ternary {
+            condition: lower_field(node, "condition", src),
+            consequence: lower_field(node, "consequence", src),
+            alternative: lower_field(node, "alternative", src),
+        },
+        "binary_expression" => {
+            let op = node
+                .child_by_field_name("operator")
+                .and_then(|op| BinaryOp::from_token(op.kind()));
+            match op {
+                Some(op) => Expr::Binary {
+                    op,
+                    left: lower_field(node, "left", src),
+                    right: lower_field(node, "right", src),
+                },
+                None => Expr::Error,
+            }
+        }
+        "unary_expression" => {
+            let op = node
+                .child_by_field_name("operator")
+                .and_then(|op| UnaryOp::from_token(op.kind()));
+            match op {
+                Some(op) => Expr::Unary {
+                    op,
+                    argument: lower_field(node, "argument", src),
+                },
+                None => Expr::Error,
+            }
+        }
+        "member_expression" => {
+            let object = lower_field(node, "object", src);
+            match node.child_by_field_name("property") {
+                Some(property) if !property.is_missing() => Expr::Member {
+                    object,
+                    property: (node_text(property, src).to_string(), Span::from(property)),
+                },
+                _ => Expr::Error,
+            }
+        }
+        "subscript_expression" => Expr::Subscript {
+            object: lower_field(node, "object", src),
+            index: lower_field(node, "index", src),
+        },
+        "call_expression" => Expr::Call {
+            function: lower_field(node, "function", src),
+            arguments: node
+                .child_by_field_name("arguments")
+                .map(|arguments| lower_named_children(arguments, src))
+                .unwrap_or_default(),
+        },
+        "array" => Expr::Array(lower_named_children(node, src)),
+        "map" => {
+            let mut cursor = node.walk();
+            let entries = node
+                .named_children(&mut cursor)
+                .filter(|pair| pair.kind() == "pair")
+                .filter_map(|pair| {
+                    let key = pair.child_by_field_name("key")?;
+                    let key_name = match lower(key, src) {
+                        (Expr::Value(Value::Str(name)), _) => MapKey { name, quoted: true },
+                        _ => MapKey {
+                            name: node_text(key, src).to_string(),
+                            quoted: false,
+                        },
+                    };
+                    Some((
+                        (key_name, Span::from(key)),
+                        *lower_field(pair, "value", src),
+                    ))
+                })
+                .collect();
+            Expr::Map(entries)
+        }
+        "identifier" | "nested_identifier" => Expr::Ident(node_text(node, src).to_string()),
+        "string" => Expr::Value(Value::Str(string_value(node, src).0)),
+        "integer" => match node_text(node, src).parse() {
+            Ok(value) => Expr::Value(Value::Int(value)),
+            Err(_) => Expr::Error,
+        },
+        "float" => match node_text(node, src).parse() {
+            Ok(value) => Expr::Value(Value::Num(value)),
+            Err(_) => Expr::Error,
+        },
+        "true" => Expr::Value(Value::Bool(true)),
+        "false" => Expr::Value(Value::Bool(false)),
+        "null" => Expr::Value(Value::Null),
+        _ => Expr::Error,
+    };
+    (expr, span)
+}
+
+/// The character a single escape sequence stands for, e.g. a newline for
+/// `\n`. `\uXXXX` escapes are UTF-16 code units and decoded by
+/// [`string_value`], which pairs up surrogates.
+fn unescape(escape: &str) -> Option<char> {
+    match escape {
+        "\\\"" => Some('"'),
+        "\\'" => Some('\''),
+        "\\\\" => Some('\\'),
+        "\\n" => Some('\n'),
+        "\\r" => Some('\r'),
+        "\\t" => Some('\t'),
+        _ => None,
+    }
+}
+
+/// The UTF-16 code unit of a well-formed `\uXXXX` escape.
+fn code_unit(escape: &str) -> Option<u16> {
+    let hex = escape.strip_prefix("\\u").filter(|hex| hex.len() == 4)?;
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Decodes a `string` node. Escape sequences that are not valid are kept
+/// as written and reported alongside.
+pub fn string_value(node: Node<'_>, src: &str) -> (String, Vec<ErrorToken>) {
+    let mut value = String::new();
+    let mut errors = Vec::new();
+    // Consecutive `\uXXXX` escapes, which may be surrogate pairs such as
+    // `\uD83D\uDE00`.
+    let mut units: Vec<(Node<'_>, u16)> = Vec::new();
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        let text = node_text(child, src);
+        match child.kind() {
+            "escape_sequence" => {
+                if let Some(unit) = code_unit(text) {
+                    units.push((child, unit));
+                    continue;
                 }
-            })
-            .collect::<Vec<_>>();
-        let parse_errs = preorder
-            .iter()
-            .filter_map(|token| {
-                // has_error indicates that children have errors,
-                // we only want leaf errors to avoid duplicate messages
-                if token.is_error() && !token.has_error() {
-                    Some(ErrorToken {
-                        start: token.start_position(),
-                        end: token.end_position(),
-                        message: String::from("Parse error"),
-                    })
+            }
+            "string_fragment" => {
+                decode_units(&mut units, src, &mut value, &mut errors);
+                value.push_str(text);
+                continue;
+            }
+            // The quotes.
+            _ => continue,
+        }
+        decode_units(&mut units, src, &mut value, &mut errors);
+        match unescape(text) {
+            Some(c) => value.push(c),
+            None => {
+                value.push_str(text);
+                let message = if text.starts_with("\\u") {
+                    format!(
+                        "invalid unicode escape `{}`: expected four hex digits",
+                        text
+                    )
                 } else {
-                    None
+                    format!("unknown escape sequence `{}`", text.escape_debug())
+                };
+                errors.push(ErrorToken {
+                    start: child.start_position(),
+                    end: child.end_position(),
+                    message,
+                    related: Vec::new(),
+                });
+            }
+        }
+    }
+    decode_units(&mut units, src, &mut value, &mut errors);
+    (value, errors)
+}
+
+fn decode_units<'a>(
+    units: &mut Vec<(Node<'a>, u16)>,
+    src: &str,
+    value: &mut String,
+    errors: &mut Vec<ErrorToken>,
+) {
+    let mut rest = &units[..];
+    while let Some(&(node, unit)) = rest.first() {
+        let low = rest.get(1).map(|&(_, low)| low);
+        match char::decode_utf16(std::iter::once(unit).chain(low)).next() {
+            Some(Ok(c)) => {
+                value.push(c);
+                rest = &rest[c.len_utf16()..];
+            }
+            _ => {
+                let text = node_text(node, src);
+                value.push_str(text);
+                errors.push(ErrorToken {
+                    start: node.start_position(),
+                    end: node.end_position(),
+                    message: format!("unpaired surrogate `{}` in unicode escape", text),
+                    related: Vec::new(),
+                });
+                rest = &rest[1..];
+            }
+        }
+    }
+    units.clear();
+}
+
+/// Writes `value` as a string literal between `quote`s, escaping what
+/// [`string_value`] decodes.
+pub fn escape(value: &str, quote: char) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push(quote);
+    for c in value.chars() {
+        match c {
+            '\\' => literal.push_str("\\\\"),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            c if c == quote => {
+                literal.push('\\');
+                literal.push(c);
+            }
+            c if c.is_control() => {
+                for unit in c.encode_utf16(&mut [0; 2]) {
+                    literal.push_str(&format!("\\u{:04X}", unit));
                 }
-            })
-            .collect();
-
-        // println!("{:#?}", ast);
-        // if let Some(funcs) = ast.filter(|_| errs.len() + parse_errs.len() == 0) {
-        //     if let Some(main) = funcs.get("main") {
-        //         assert_eq!(main.args.len(), 0);
-        //         match eval_expr(&main.body, &funcs, &mut Vec::new()) {
-        //             Ok(val) => println!("Return value: {}", val),
-        //             Err(e) => errs.push(Simple::custom(e.span, e.msg)),
-        //         }
-        //     } else {
-        //         panic!("No main function!");
-        //     }
-        // }
-
-        let ast_mapped: HashMap<_, _> = preorder
-            .iter()
-            .map(|&node| {
+            }
+            c => literal.push(c),
+        }
+    }
+    literal.push(quote);
+    literal
+}
+
+thread_local! {
+    static PARSER: RefCell<Option<tree_sitter::Parser>> = RefCell::new({
+        let mut parser = tree_sitter::Parser::new();
+        parser
+            .set_language(tree_sitter_oel::language())
+            .ok()
+            .map(|_| parser)
+    });
+}
+
+/// Parses `src` into a tree-sitter tree. `old_tree` is the tree of the
+/// previous version of the document, already [`Tree::edit`]ed to match
+/// `src`, and lets tree-sitter reuse the parts that did not change.
+pub fn parse_tree(src: &str, old_tree: Option<&Tree>) -> Option<Tree> {
+    PARSER.with(|parser| parser.borrow_mut().as_mut()?.parse(src, old_tree))
+}
+
+/// An expression of a file. A file holds either a single expression, one
+/// entry without a name, or several named ones:
+///
+/// ```text
+/// # Everyone in engineering
+/// rule "Engineering" = user.department == "Eng"
+///
+/// rule "Contractors" = user.userType == "contractor"
+/// ```
+#[derive(Clone, Debug)]
+pub struct Entry {
+    pub name: Option<Spanned<String>>,
+    pub value: Spanned<Expr>,
+    /// The expression as Okta takes it, without the comments.
+    pub source: String,
+    /// From `rule` to the end of the expression, or the whole file.
+    pub span: Span,
+}
+
+impl Entry {
+    /// Whether the entry spans `position`, ends included so that the
+    /// cursor right after an expression still belongs to it.
+    pub fn contains(&self, position: Point) -> bool {
+        self.span.start <= position && position <= self.span.end
+    }
+}
+
+/// The entry at `position`, for features that work on a single expression.
+pub fn entry_at(entries: &[Entry], position: Point) -> Option<&Entry> {
+    entries
+        .iter()
+        .find(|entry| entry.contains(position))
+        .or(match entries {
+            [entry] => Some(entry),
+            _ => None,
+        })
+}
+
+/// The entries of a `source_file` node.
+pub fn lower_entries(root: Node<'_>, src: &str) -> Vec<Entry> {
+    let mut cursor = root.walk();
+    let entries = root
+        .named_children(&mut cursor)
+        .filter(|child| child.kind() == "entry")
+        .collect::<Vec<_>>();
+    if entries.is_empty() {
+        return vec![Entry {
+            name: None,
+            value: lower(root, src),
+            source: strip_comments(root, src),
+            span: Span::from(root),
+        }];
+    }
+    entries
+        .into_iter()
+        .map(|entry| {
+            let value = entry.child_by_field_name("value");
+            Entry {
+                name: entry
+                    .child_by_field_name("name")
+                    .map(|name| (string_value(name, src).0, Span::from(name))),
+                value: *lower_field(entry, "value", src),
+                source: value
+                    .map(|value| strip_comments(value, src))
+                    .unwrap_or_default(),
+                span: Span::from(entry),
+            }
+        })
+        .collect()
+}
+
+/// The text of `node` without its comments. Lines left empty by a removed
+/// comment are dropped.
+pub fn strip_comments(node: Node<'_>, src: &str) -> String {
+    let text = node_text(node, src);
+    let offset = node.start_byte();
+    let mut comments = Vec::new();
+    collect_comments(node, &mut comments);
+    let mut stripped = String::with_capacity(text.len());
+    let mut from = 0;
+    for comment in comments {
+        let mut start = comment.start_byte() - offset;
+        let mut end = comment.end_byte() - offset;
+        start = text[..start].trim_end_matches([' ', '\t']).len();
+        let own_line = start == 0 || text[..start].ends_with('\n');
+        if own_line && text[end..].starts_with('\n') {
+            end += 1;
+        }
+        stripped.push_str(&text[from..start.max(from)]);
+        from = end;
+    }
+    stripped.push_str(&text[from.min(text.len())..]);
+    stripped.trim().to_string()
+}
+
+fn collect_comments<'a>(node: Node<'a>, comments: &mut Vec<Node<'a>>) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        if child.kind() == "comment" {
+            comments.push(child);
+        } else {
+            collect_comments(child, comments);
+        }
+    }
+}
+
+pub fn parse(src: &str) -> (Vec<Entry>, Vec<ErrorToken>) {
+    match parse_tree(src, None) {
+        Some(tree) => analyze(&tree, src),
+        None => (Vec::new(), Vec::new()),
+    }
+}
+
+/// The entries and syntax errors of a parsed tree.
+pub fn analyze(tree: &Tree, src: &str) -> (Vec<Entry>, Vec<ErrorToken>) {
+    let parse_errs = syntax_errors(tree.root_node(), src);
+
+    let entries = lower_entries(tree.root_node(), src);
+
+    (entries, parse_errs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lowered(src: &str) -> Expr {
+        let (entries, errors) = parse(src);
+        assert!(errors.is_empty(), "{}: {:?}", src, errors);
+        entries.into_iter().next().unwrap().value.0
+    }
+
+    fn string(src: &str) -> (String, Vec<String>) {
+        let tree = parse_tree(src, None).unwrap();
+        let mut node = tree.root_node();
+        while node.kind() != "string" {
+            node = node.named_child(0).unwrap();
+        }
+        let (value, errors) = string_value(node, src);
+        (
+            value,
+            errors.into_iter().map(|error| error.message).collect(),
+        )
+    }
+
+    /// The expression with every operation parenthesized.
+    fn grouped(expr: &Expr) -> String {
+        match expr {
+            Expr::Ident(name) => name.clone(),
+            Expr::Value(value) => value.to_string(),
+            Expr::Unary { op, argument } => format!("({}{})", op, grouped(&argument.0)),
+            Expr::Binary { op, left, right } => {
+                format!("({} {} {})", grouped(&left.0), op, grouped(&right.0))
+            }
+            expr => panic!("{:?}", expr),
+        }
+    }
+
+    #[test]
+    fn literals() {
+        assert_eq!(lowered("42"), Expr::Value(Value::Int(42)));
+        assert_eq!(lowered("1.5"), Expr::Value(Value::Num(1.5)));
+        assert_eq!(lowered("true"), Expr::Value(Value::Bool(true)));
+        assert_eq!(lowered("false"), Expr::Value(Value::Bool(false)));
+        assert_eq!(lowered("null"), Expr::Value(Value::Null));
+        assert_eq!(
+            lowered("\"Eng\""),
+            Expr::Value(Value::Str("Eng".to_string()))
+        );
+        assert!(matches!(
+            lowered("{1, \"a\"}"),
+            Expr::Array(items) if matches!(
+                &items[..],
+                [(Expr::Value(Value::Int(1)), _), (Expr::Value(Value::Str(a)), _)] if a == "a"
+            )
+        ));
+    }
+
+    #[test]
+    fn paths_and_calls() {
+        let (entries, _) = parse("String.len(user.login)");
+        let ast = &entries[0].value;
+        match &ast.0 {
+            Expr::Call {
+                function,
+                arguments,
+            } => {
+                assert_eq!(function.0.path().as_deref(), Some("String.len"));
+                assert_eq!(arguments[0].0.path().as_deref(), Some("user.login"));
+            }
+            expr => panic!("{:?}", expr),
+        }
+        let mut paths = Vec::new();
+        walk(ast, &mut |(expr, _)| paths.extend(expr.path()));
+        assert_eq!(paths, ["String.len", "String", "user.login", "user"]);
+    }
+
+    #[test]
+    fn spans_keep_the_parentheses() {
+        match lowered("(1 + 2) == 3") {
+            Expr::Binary { op, left, .. } => {
+                assert_eq!(op, BinaryOp::Eq);
+                assert_eq!((left.1.start.column, left.1.end.column), (0, 7));
+            }
+            expr => panic!("{:?}", expr),
+        }
+    }
+
+    #[test]
+    fn operator_precedence() {
+        let precedence = |src: &str| grouped(&lowered(src));
+        assert_eq!(precedence("a - b * c % d"), "(a - ((b * c) % d))");
+        assert_eq!(precedence("a - b - c"), "((a - b) - c)");
+        assert_eq!(precedence("-a * b"), "((-a) * b)");
+        assert_eq!(precedence("!a == b"), "((!a) == b)");
+        assert_eq!(precedence("!-a"), "(!(-a))");
+        assert_eq!(precedence("a && b || c"), "((a AND b) OR c)");
+        assert_eq!(precedence("a || b && c"), "(a OR (b AND c))");
+        assert_eq!(precedence("-a + b > c AND d"), "((((-a) + b) > c) AND d)");
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(string(r#""a\"b\\c\n""#), ("a\"b\\c\n".to_string(), vec![]));
+        assert_eq!(string(r"'é😀'"), ("é😀".to_string(), vec![]));
+        assert_eq!(
+            string(r"'\q'"),
+            (
+                r"\q".to_string(),
+                vec!["unknown escape sequence `\\q`".to_string()]
+            )
+        );
+        for value in ["plain", "it's", "tab\tand\nline", "back\\slash", "\u{1}"] {
+            let literal = escape(value, '\'');
+            assert_eq!(string(&literal), (value.to_string(), vec![]), "{}", literal);
+        }
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(
+            string(r"'\u00e9\u00C9'"),
+            ("\u{e9}\u{c9}".to_string(), vec![])
+        );
+        assert_eq!(
+            string(r"'\u12'"),
+            (
+                r"\u12".to_string(),
+                vec!["invalid unicode escape `\\u12`: expected four hex digits".to_string()]
+            )
+        );
+        assert_eq!(escape("\u{7}", '"'), r#""\u0007""#);
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(
+            string(r"'\uD83D\uDE00!'"),
+            ("\u{1F600}!".to_string(), vec![])
+        );
+        assert_eq!(
+            string(r"'\uD83D!'"),
+            (
+                r"\uD83D!".to_string(),
+                vec!["unpaired surrogate `\\uD83D` in unicode escape".to_string()]
+            )
+        );
+        assert_eq!(
+            string(r"'\uDE00\uD83D'"),
+            (
+                r"\uDE00\uD83D".to_string(),
+                vec![
+                    "unpaired surrogate `\\uDE00` in unicode escape".to_string(),
+                    "unpaired surrogate `\\uD83D` in unicode escape".to_string()
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn a_trailing_backslash_does_not_continue_the_line() {
+        let (_, errors) = parse("'a\\\nb'");
+        assert!(!errors.is_empty());
+        let (_, errors) = parse("\"a\\\r\nb\"");
+        assert!(!errors.is_empty());
+        assert_eq!(string(r"'a\\'"), ("a\\".to_string(), vec![]));
+    }
+
+    #[test]
+    fn quotes_of_the_other_kind_need_no_escape() {
+        assert_eq!(string(r#""it's""#), ("it's".to_string(), vec![]));
+        assert_eq!(string(r#"'say "hi"'"#), ("say \"hi\"".to_string(), vec![]));
+        assert_eq!(string(r#"'it\'s "A"'"#), ("it's \"A\"".to_string(), vec![]));
+        assert_eq!(string(r#""\"it's\"""#), ("\"it's\"".to_string(), vec![]));
+        match lowered(r#""a'" + 'b"'"#) {
+            Expr::Binary { left, right, .. } => assert_eq!(
+                (left.0, right.0),
                 (
-                    node.id().to_string(),
-                    Span {
-                        // TODO: No unwrap
-                        id: node.id(),
-                        start: node.start_position(),
-                        end: node.end_position(),
-                    },
+                    Expr::Value(Value::Str("a'".to_string())),
+                    Expr::Value(Value::Str("b\"".to_string()))
                 )
-            })
-            .collect();
+            ),
+            expr => panic!("{:?}", expr),
+        }
+    }
 
-        (Some(ast_mapped), parse_errs, semantic_tokens)
-    } else {
-        (None, Vec::new(), Vec::new())
-    };
+    #[test]
+    fn named_entries() {
+        let (entries, errors) = parse("rule \"A\" = 1\nrule \"B\" = user.login\n");
+        assert!(errors.is_empty(), "{:?}", errors);
+        let names = entries
+            .iter()
+            .map(|entry| entry.name.as_ref().map(|(name, _)| name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(names, [Some("A"), Some("B")]);
+        assert_eq!(entries[1].source, "user.login");
+        assert!(entries[0].contains(Point::new(0, 12)));
+        assert!(!entries[0].contains(Point::new(1, 0)));
+    }
+
+    #[test]
+    fn comments_are_not_part_of_the_source() {
+        let src = "# Engineering\nrule \"A\" = user.department # the team\n    == 'Eng'\n";
+        let (entries, errors) = parse(src);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].source, "user.department\n    == 'Eng'");
+        let (entries, _) = parse("# only a comment\n");
+        assert_eq!(entries[0].source, "");
+    }
 }
@@ -0,0 +1,240 @@
+//! Conversions between tree-sitter points, whose columns count bytes, and
+//! LSP positions, whose characters count code units of the `positionEncoding`
+//! negotiated with the client (UTF-16 unless it offers another).
+//!
+//! Rows are split on `\n` only, as tree-sitter does.
+
+use tower_lsp::lsp_types::{Position, PositionEncodingKind, Range};
+use tree_sitter::Point;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    Utf8,
+    /// The encoding every client supports, and the default.
+    #[default]
+    Utf16,
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// The client's most preferred encoding we support.
+    pub fn negotiate(offered: Option<&[PositionEncodingKind]>) -> Self {
+        offered
+            .unwrap_or_default()
+            .iter()
+            .find_map(|kind| match kind.as_str() {
+                "utf-8" => Some(PositionEncoding::Utf8),
+                "utf-16" => Some(PositionEncoding::Utf16),
+                "utf-32" => Some(PositionEncoding::Utf32),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn kind(self) -> PositionEncodingKind {
+        match self {
+            PositionEncoding::Utf8 => PositionEncodingKind::UTF8,
+            PositionEncoding::Utf16 => PositionEncodingKind::UTF16,
+            PositionEncoding::Utf32 => PositionEncodingKind::UTF32,
+        }
+    }
+
+    fn units(self, c: char) -> usize {
+        match self {
+            PositionEncoding::Utf8 => c.len_utf8(),
+            PositionEncoding::Utf16 => c.len_utf16(),
+            PositionEncoding::Utf32 => 1,
+        }
+    }
+
+    /// Code units in the first `bytes` bytes of `line`.
+    pub fn units_in(self, line: impl IntoIterator<Item = char>, bytes: usize) -> u32 {
+        let mut offset = 0;
+        let mut units = 0;
+        for c in line {
+            if offset + c.len_utf8() > bytes {
+                break;
+            }
+            offset += c.len_utf8();
+            units += self.units(c);
+        }
+        units as u32
+    }
+
+    /// Bytes in the first `units` code units of `line`, stopping at the line
+    /// break and never splitting a character.
+    pub fn bytes_in(self, line: impl IntoIterator<Item = char>, units: u32) -> usize {
+        let mut offset = 0;
+        let mut counted = 0;
+        for c in line {
+            if c == '\n' || c == '\r' || counted + self.units(c) > units as usize {
+                break;
+            }
+            offset += c.len_utf8();
+            counted += self.units(c);
+        }
+        offset
+    }
+}
+
+/// Maps between points and positions in one version of a document.
+pub struct LineIndex<'a> {
+    src: &'a str,
+    /// Byte offset of the start of each row.
+    line_starts: Vec<usize>,
+    encoding: PositionEncoding,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(src: &'a str, encoding: PositionEncoding) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(src.match_indices('\n').map(|(at, _)| at + 1))
+            .collect();
+        LineIndex {
+            src,
+            line_starts,
+            encoding,
+        }
+    }
+
+    fn line(&self, row: usize) -> &'a str {
+        match self.line_starts.get(row) {
+            Some(&start) => {
+                let end = self
+                    .line_starts
+                    .get(row + 1)
+                    .copied()
+                    .unwrap_or(self.src.len());
+                &self.src[start..end]
+            }
+            None => "",
+        }
+    }
+
+    pub fn position(&self, point: Point) -> Position {
+        let character = self
+            .encoding
+            .units_in(self.line(point.row).chars(), point.column);
+        Position::new(point.row as u32, character)
+    }
+
+    pub fn point(&self, position: Position) -> Point {
+        let row = position.line as usize;
+        let column = self
+            .encoding
+            .bytes_in(self.line(row).chars(), position.character);
+        Point::new(row, column)
+    }
+
+    /// The point at byte `offset`.
+    pub fn point_at(&self, offset: usize) -> Point {
+        let row = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        Point::new(row, offset - self.line_starts[row])
+    }
+
+    /// Byte offset of `point`, clamped to the end of its line.
+    pub fn offset(&self, point: Point) -> usize {
+        match self.line_starts.get(point.row) {
+            Some(&start) => start + point.column.min(self.line(point.row).len()),
+            None => self.src.len(),
+        }
+    }
+
+    /// The source from `start` to `end`.
+    pub fn text(&self, start: Point, end: Point) -> &'a str {
+        let start = self.offset(start);
+        self.src
+            .get(start..self.offset(end).max(start))
+            .unwrap_or_default()
+    }
+
+    pub fn range(&self, start: Point, end: Point) -> Range {
+        Range::new(self.position(start), self.position(end))
+    }
+
+    /// Code units from `start` to `end`, or to the end of the line of `start`
+    /// if `end` is past it.
+    pub fn len(&self, start: Point, end: Point) -> u32 {
+        let line = self.line(start.row);
+        let line_end = line.trim_end_matches(['\n', '\r']).len();
+        let end = if end.row == start.row {
+            end.column.min(line_end)
+        } else {
+            line_end
+        };
+        self.encoding
+            .units_in(line.chars(), end)
+            .saturating_sub(self.encoding.units_in(line.chars(), start.column))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_the_first_supported_encoding() {
+        let offered = [
+            PositionEncodingKind::new("utf-7"),
+            PositionEncodingKind::UTF8,
+            PositionEncodingKind::UTF16,
+        ];
+        assert_eq!(
+            PositionEncoding::negotiate(Some(&offered)),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(PositionEncoding::negotiate(None), PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn counts_code_units_of_the_encoding() {
+        // `é` is two bytes, `😀` four bytes and two UTF-16 code units.
+        let src = "\"é😀\" + x";
+        let x = Point::new(0, src.find('x').unwrap());
+        let characters = |encoding| LineIndex::new(src, encoding).position(x).character;
+        assert_eq!(characters(PositionEncoding::Utf8), 11);
+        assert_eq!(characters(PositionEncoding::Utf16), 8);
+        assert_eq!(characters(PositionEncoding::Utf32), 7);
+        for encoding in [
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+            PositionEncoding::Utf32,
+        ] {
+            let index = LineIndex::new(src, encoding);
+            assert_eq!(index.point(index.position(x)), x);
+        }
+    }
+
+    #[test]
+    fn points_stay_on_their_line() {
+        let index = LineIndex::new("ab\r\ncd", PositionEncoding::Utf16);
+        assert_eq!(index.point(Position::new(0, 10)), Point::new(0, 2));
+        assert_eq!(index.point(Position::new(1, 1)), Point::new(1, 1));
+        // Inside a surrogate pair, which cannot be split.
+        let index = LineIndex::new("😀x", PositionEncoding::Utf16);
+        assert_eq!(index.point(Position::new(0, 1)), Point::new(0, 0));
+    }
+
+    #[test]
+    fn len_counts_code_units_to_the_end_of_the_line() {
+        let index = LineIndex::new("user.login\n  == 'é'", PositionEncoding::Utf16);
+        assert_eq!(index.len(Point::new(1, 5), Point::new(1, 9)), 3);
+        assert_eq!(index.len(Point::new(0, 5), Point::new(1, 2)), 5);
+    }
+
+    #[test]
+    fn text_and_offset() {
+        let index = LineIndex::new("user.login\n  == 'é'", PositionEncoding::Utf16);
+        assert_eq!(index.text(Point::new(0, 5), Point::new(0, 10)), "login");
+        assert_eq!(
+            index.text(Point::new(0, 5), Point::new(1, 4)),
+            "login\n  =="
+        );
+        assert_eq!(index.offset(Point::new(1, 2)), 13);
+        assert_eq!(index.offset(Point::new(7, 0)), 20);
+        assert_eq!(index.point_at(0), Point::new(0, 0));
+        assert_eq!(index.point_at(10), Point::new(0, 10));
+        assert_eq!(index.point_at(11), Point::new(1, 0));
+        assert_eq!(index.point_at(20), Point::new(1, 9));
+    }
+}
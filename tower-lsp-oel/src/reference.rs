@@ -1,220 +1,98 @@
-use std::collections::HashMap;
+//! References. OEL has no local variables, so the references of a profile
+//! attribute or group name are its uses anywhere in the workspace, as
+//! recorded by the [`WorkspaceIndex`].
 
-use im_rc::Vector;
+use tower_lsp::lsp_types::{DocumentHighlight, DocumentHighlightKind, Location, Position, Url};
 
-#[derive(Debug, Clone)]
-pub enum ReferenceSymbol {
-    Founded(Spanned<String>),
-    Founding(usize),
+use crate::position::{LineIndex, PositionEncoding};
+use crate::workspace::{Occurrence, WorkspaceIndex};
+
+/// The attribute or group name at `position` in the indexed file `uri`.
+pub fn name_at(
+    workspace: &WorkspaceIndex,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<Occurrence> {
+    let file = workspace.get(uri)?;
+    let point = LineIndex::new(&file.src, encoding).point(position);
+    file.occurrence_at(point).cloned()
 }
-use tower_lsp::lsp_types::Position;
-use tree_sitter::Node;
-use ReferenceSymbol::*;
 
-use crate::oel::{Span, Spanned};
+/// Every use of the name at `position`, across the workspace.
 pub fn get_reference(
-    ast: &HashMap<String, Span>,
-    ident_offset: Position,
-    include_self: bool,
-) -> Vec<Spanned<String>> {
-    // let mut vector = Vector::new();
-    // let mut reference_list = vec![];
-    // // for (_, v) in ast.iter() {
-    // //     if v.name.1.end < ident_offset {
-    // //         vector.push_back(v.name.clone());
-    // //     }
-    // // }
-    // let mut kv_list = ast.iter().collect::<Vec<_>>();
-    // kv_list.sort_by(|a, b| a.1.start_position().cmp(&b.1.start_position()));
-    // let mut reference_symbol = ReferenceSymbol::Founding(ident_offset);
-    // // let mut fn_vector = Vector::new();
-    // for (_, v) in kv_list {
-    //     if ident_offset.line >= v.start_position().row && ident_offset.line < v.end_position().row {
-    //         reference_symbol = ReferenceSymbol::Founded(v.name.clone());
-    //         if include_self {
-    //             reference_list.push(v.clone());
-    //         }
-    //     };
-    //     vector.push_back(v.name.clone());
-    //     let args = v
-    //         .args
-    //         .iter()
-    //         .map(|arg| {
-    //             if ident_offset >= arg.1.start && ident_offset < arg.1.end {
-    //                 reference_symbol = ReferenceSymbol::Founded(arg.clone());
-    //                 if include_self {
-    //                     reference_list.push(arg.clone());
-    //                 }
-    //             }
-    //             arg.clone()
-    //         })
-    //         .collect::<Vector<_>>();
-    //     get_reference_of_expr(
-    //         &v.body,
-    //         args + vector.clone(),
-    //         reference_symbol.clone(),
-    //         &mut reference_list,
-    //         include_self,
-    //     );
-    // }
-    // reference_list
-    Vec::new()
+    workspace: &WorkspaceIndex,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<Vec<Location>> {
+    let occurrence = name_at(workspace, uri, position, encoding)?;
+    Some(workspace.locations(&occurrence.name, encoding))
+}
+
+/// The uses of the name at `position` in the same file. Expressions only
+/// ever read attributes.
+pub fn get_highlights(
+    workspace: &WorkspaceIndex,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Option<Vec<DocumentHighlight>> {
+    let occurrence = name_at(workspace, uri, position, encoding)?;
+    let file = workspace.get(uri)?;
+    let index = LineIndex::new(&file.src, encoding);
+    let highlights = file
+        .occurrences
+        .iter()
+        .filter(|other| other.name == occurrence.name)
+        .map(|other| DocumentHighlight {
+            range: index.range(other.span.start, other.span.end),
+            kind: Some(DocumentHighlightKind::READ),
+        })
+        .collect();
+    Some(highlights)
 }
 
-pub fn get_reference_of_expr(
-    expr: &Spanned<Node>,
-    definition_ass_list: Vector<Spanned<String>>,
-    reference_symbol: ReferenceSymbol,
-    reference_list: &mut Vec<Spanned<String>>,
-    include_self: bool,
-) {
-    // match &expr.0 {
-    //     Expr::Error => {}
-    //     Expr::Value(_) => {}
-    //     Expr::Local((name, span)) => {
-    //         if let Founded((symbol_name, symbol_span)) = reference_symbol {
-    //             if &symbol_name == name {
-    //                 let index = definition_ass_list
-    //                     .iter()
-    //                     .position(|decl| decl.0 == symbol_name);
-    //                 if let Some(symbol) = index.map(|i| definition_ass_list.get(i).unwrap().clone())
-    //                 {
-    //                     if symbol == (symbol_name, symbol_span) {
-    //                         reference_list.push((name.clone(), span.clone()));
-    //                     }
-    //                 };
-    //             }
-    //         }
-    //         // if ident_offset >= local.1.start && ident_offset < local.1.end {
-    //         //     let index = definition_ass_list
-    //         //         .iter()
-    //         //         .position(|decl| decl.0 == local.0);
-    //         //     (
-    //         //         false,
-    //         //         index.map(|i| definition_ass_list.get(i).unwrap().clone()),
-    //         //     )
-    //         // } else {
-    //         //     (true, None)
-    //         // }
-    //     }
-    //     Expr::Let(name, lhs, rest, name_span) => {
-    //         let new_decl = Vector::unit((name.clone(), name_span.clone()));
-    //         let next_symbol = match reference_symbol {
-    //             Founding(ident) if ident >= name_span.start && ident < name_span.end => {
-    //                 let spanned_name = (name.clone(), name_span.clone());
-    //                 if include_self {
-    //                     reference_list.push(spanned_name.clone());
-    //                 }
-    //                 ReferenceSymbol::Founded(spanned_name)
-    //             }
-    //             _ => reference_symbol,
-    //         };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+    use crate::workspace::FileIndex;
+
+    #[test]
+    fn references_and_highlights() {
+        let workspace = WorkspaceIndex::default();
+        let a = Url::parse("file:///a.oel").unwrap();
+        let b = Url::parse("file:///b.oel").unwrap();
+        let src = "user.login == user.email || user.login == ''";
+        workspace.insert(
+            a.clone(),
+            FileIndex::parse(src.to_string(), Dialect::Classic),
+        );
+        workspace.insert(
+            b.clone(),
+            FileIndex::parse("user.login".to_string(), Dialect::Classic),
+        );
+        let encoding = PositionEncoding::Utf16;
+        let position = Position::new(0, 35);
+        let references = get_reference(&workspace, &a, position, encoding)
+            .unwrap()
+            .into_iter()
+            .map(|location| {
+                let range = location.range;
+                (location.uri, range.start.character, range.end.character)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            references,
+            [(a.clone(), 5, 10), (a.clone(), 33, 38), (b, 5, 10)]
+        );
 
-    //         get_reference_of_expr(
-    //             lhs,
-    //             definition_ass_list.clone(),
-    //             next_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         get_reference_of_expr(
-    //             rest,
-    //             new_decl + definition_ass_list.clone(),
-    //             next_symbol,
-    //             reference_list,
-    //             include_self,
-    //         );
-    //     }
-    //     Expr::Then(first, second) => {
-    //         get_reference_of_expr(
-    //             first,
-    //             definition_ass_list.clone(),
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         get_reference_of_expr(
-    //             second,
-    //             definition_ass_list.clone(),
-    //             reference_symbol,
-    //             reference_list,
-    //             include_self,
-    //         );
-    //     }
-    //     Expr::Binary(lhs, _op, rhs) => {
-    //         get_reference_of_expr(
-    //             lhs,
-    //             definition_ass_list.clone(),
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         get_reference_of_expr(
-    //             rhs,
-    //             definition_ass_list.clone(),
-    //             reference_symbol,
-    //             reference_list,
-    //             include_self,
-    //         );
-    //     }
-    //     Expr::Call(callee, args) => {
-    //         get_reference_of_expr(
-    //             callee,
-    //             definition_ass_list.clone(),
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         for expr in &args.0 {
-    //             get_reference_of_expr(
-    //                 &expr,
-    //                 definition_ass_list.clone(),
-    //                 reference_symbol.clone(),
-    //                 reference_list,
-    //                 include_self,
-    //             );
-    //         }
-    //     }
-    //     Expr::If(test, consequent, alternative) => {
-    //         get_reference_of_expr(
-    //             test,
-    //             definition_ass_list.clone(),
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         get_reference_of_expr(
-    //             consequent,
-    //             definition_ass_list.clone(),
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //         get_reference_of_expr(
-    //             alternative,
-    //             definition_ass_list,
-    //             reference_symbol.clone(),
-    //             reference_list,
-    //             include_self,
-    //         );
-    //     }
-    //     Expr::Print(expr) => get_reference_of_expr(
-    //         expr,
-    //         definition_ass_list,
-    //         reference_symbol,
-    //         reference_list,
-    //         include_self,
-    //     ),
-    //     Expr::List(lst) => {
-    //         for expr in lst {
-    //             get_reference_of_expr(
-    //                 expr,
-    //                 definition_ass_list.clone(),
-    //                 reference_symbol.clone(),
-    //                 reference_list,
-    //                 include_self,
-    //             );
-    //         }
-    //     }
-    // }
+        let highlights = get_highlights(&workspace, &a, position, encoding).unwrap();
+        assert_eq!(highlights.len(), 2);
+        assert_eq!(
+            get_reference(&workspace, &a, Position::new(0, 11), encoding),
+            None
+        );
+    }
 }
@@ -0,0 +1,259 @@
+//! Renaming a profile attribute or group name everywhere the workspace uses
+//! it, e.g. after the attribute was renamed in the Okta schema. Root objects
+//! and built-in functions belong to Okta and cannot be renamed.
+
+use std::collections::HashMap;
+
+use tower_lsp::lsp_types::{Position, Range, TextEdit, Url, WorkspaceEdit};
+use tree_sitter::Point;
+
+use crate::builtins::Registry;
+use crate::eval::ROOTS;
+use crate::oel::{escape, walk, Expr};
+use crate::position::{LineIndex, PositionEncoding};
+use crate::reference::name_at;
+use crate::workspace::{FileIndex, Name, WorkspaceIndex};
+
+/// The range and current text of the name at `position`. `Ok(None)` when
+/// there is nothing to rename there, and an error saying why when there is
+/// something that cannot be.
+pub fn prepare_rename(
+    workspace: &WorkspaceIndex,
+    registry: &Registry,
+    uri: &Url,
+    position: Position,
+    encoding: PositionEncoding,
+) -> Result<Option<(Range, String)>, String> {
+    let file = match workspace.get(uri) {
+        Some(file) => file,
+        None => return Ok(None),
+    };
+    let index = LineIndex::new(&file.src, encoding);
+    let point = index.point(position);
+    match file.occurrence_at(point) {
+        Some(occurrence) => {
+            let range = index.range(occurrence.span.start, occurrence.span.end);
+            let placeholder = match &occurrence.name {
+                Name::Attribute { attribute, .. } => attribute.clone(),
+                Name::Group(name) => name.clone(),
+            };
+            Ok(Some((range, placeholder)))
+        }
+        None => match not_renameable(&file, registry, point) {
+            Some(reason) => Err(reason),
+            None => Ok(None),
+        },
+    }
+}
+
+/// The edit renaming the name at `position` to `new_name` in every file of
+/// the workspace.
+pub fn rename(
+    workspace: &WorkspaceIndex,
+    registry: &Registry,
+    uri: &Url,
+    position: Position,
+    new_name: &str,
+    encoding: PositionEncoding,
+) -> Result<Option<WorkspaceEdit>, String> {
+    if prepare_rename(workspace, registry, uri, position, encoding)?.is_none() {
+        return Ok(None);
+    }
+    let occurrence = match name_at(workspace, uri, position, encoding) {
+        Some(occurrence) => occurrence,
+        None => return Ok(None),
+    };
+    if let Name::Attribute { .. } = occurrence.name {
+        if !is_identifier(new_name) {
+            return Err(format!("`{}` is not a valid attribute name", new_name));
+        }
+    }
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for (location, text) in workspace.uses(&occurrence.name, encoding) {
+        let new_text = match occurrence.name {
+            Name::Attribute { .. } => new_name.to_string(),
+            // Keep the quotes the literal was written with.
+            Name::Group(_) => escape(new_name, text.chars().next().unwrap_or('"')),
+        };
+        changes
+            .entry(location.uri)
+            .or_default()
+            .push(TextEdit::new(location.range, new_text));
+    }
+    Ok(Some(WorkspaceEdit::new(changes)))
+}
+
+/// Why the identifier at `point` cannot be renamed, if it is a root object,
+/// a function or a method.
+fn not_renameable(file: &FileIndex, registry: &Registry, point: Point) -> Option<String> {
+    let mut reason = None;
+    for entry in &file.entries {
+        walk(&entry.value, &mut |(expr, span)| {
+            if !(span.start <= point && point <= span.end) {
+                return;
+            }
+            let path = match expr {
+                Expr::Ident(_) => expr.path(),
+                Expr::Member { property, .. }
+                    if property.1.start <= point && point <= property.1.end =>
+                {
+                    expr.path()
+                }
+                _ => None,
+            };
+            let path = match path {
+                Some(path) => path,
+                None => return,
+            };
+            if ROOTS.contains(&path.as_str()) {
+                reason = Some(format!("cannot rename the root object `{}`", path));
+            } else if registry.get(&path).is_some() {
+                reason = Some(format!("cannot rename the built-in function `{}`", path));
+            } else if registry.namespaces().contains(&path.as_str()) {
+                reason = Some(format!("cannot rename the built-in namespace `{}`", path));
+            } else if let Some(method) = path
+                .rsplit('.')
+                .next()
+                .and_then(|name| registry.any_method(name))
+            {
+                reason = Some(format!(
+                    "cannot rename the built-in method `{}`",
+                    method.name
+                ));
+            }
+        });
+    }
+    reason
+}
+
+/// Whether `name` can follow a `.` without quoting.
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map_or(false, |first| {
+        first.is_alphabetic() || first == '_' || first == '$'
+    }) && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dialect::Dialect;
+
+    fn workspace(files: &[(&str, &str)]) -> WorkspaceIndex {
+        let workspace = WorkspaceIndex::default();
+        for (uri, src) in files {
+            let file = FileIndex::parse(src.to_string(), Dialect::Classic);
+            workspace.insert(Url::parse(uri).unwrap(), file);
+        }
+        workspace
+    }
+
+    fn rename_at(
+        workspace: &WorkspaceIndex,
+        character: u32,
+        new_name: &str,
+    ) -> Result<Vec<(String, u32, u32, String)>, String> {
+        let uri = Url::parse("file:///a.oel").unwrap();
+        let position = Position::new(0, character);
+        let edit = rename(
+            workspace,
+            &Registry::default(),
+            &uri,
+            position,
+            new_name,
+            PositionEncoding::Utf16,
+        )?
+        .unwrap();
+        let mut edits = edit
+            .changes
+            .unwrap()
+            .into_iter()
+            .flat_map(|(uri, edits)| {
+                edits.into_iter().map(move |edit| {
+                    let range = edit.range;
+                    let path = uri.path().to_string();
+                    (
+                        path,
+                        range.start.character,
+                        range.end.character,
+                        edit.new_text,
+                    )
+                })
+            })
+            .collect::<Vec<_>>();
+        edits.sort();
+        Ok(edits)
+    }
+
+    #[test]
+    fn renames_attributes_across_files() {
+        let workspace = workspace(&[
+            ("file:///a.oel", "user.dept == 'Eng' || user.dept == 'Ops'"),
+            ("file:///b.oel", "String.len(user.dept) + user.login"),
+        ]);
+        let edit =
+            |path: &str, start, end| (path.to_string(), start, end, "department".to_string());
+        assert_eq!(
+            rename_at(&workspace, 6, "department"),
+            Ok(vec![
+                edit("/a.oel", 5, 9),
+                edit("/a.oel", 27, 31),
+                edit("/b.oel", 16, 20)
+            ])
+        );
+        assert_eq!(
+            rename_at(&workspace, 6, "cost-center"),
+            Err("`cost-center` is not a valid attribute name".to_string())
+        );
+    }
+
+    #[test]
+    fn renames_groups_keeping_their_quotes() {
+        let workspace = workspace(&[(
+            "file:///a.oel",
+            r#"isMemberOfGroupName('Admins') || isMemberOfGroupName("Admins")"#,
+        )]);
+        assert_eq!(
+            rename_at(&workspace, 22, "Ops' Admins"),
+            Ok(vec![
+                ("/a.oel".to_string(), 20, 28, r"'Ops\' Admins'".to_string()),
+                ("/a.oel".to_string(), 53, 61, "\"Ops' Admins\"".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn refuses_okta_names() {
+        let workspace = workspace(&[("file:///a.oel", "String.len(user.login)")]);
+        let uri = Url::parse("file:///a.oel").unwrap();
+        let prepare = |character| {
+            let position = Position::new(0, character);
+            prepare_rename(
+                &workspace,
+                &Registry::default(),
+                &uri,
+                position,
+                PositionEncoding::Utf16,
+            )
+        };
+        assert_eq!(
+            prepare(18)
+                .unwrap()
+                .map(|(range, placeholder)| (range.start.character, placeholder)),
+            Some((16, "login".to_string()))
+        );
+        assert_eq!(
+            prepare(13),
+            Err("cannot rename the root object `user`".to_string())
+        );
+        assert_eq!(
+            prepare(8),
+            Err("cannot rename the built-in function `String.len`".to_string())
+        );
+        assert_eq!(
+            prepare(2),
+            Err("cannot rename the built-in namespace `String`".to_string())
+        );
+        assert_eq!(prepare(22), Ok(None));
+    }
+}
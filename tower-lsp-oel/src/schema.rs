@@ -0,0 +1,323 @@
+//! Attribute tables for the `user`, `appuser` and other root objects.
+//!
+//! Profiles are loaded from the JSON returned by Okta's schema API, e.g.
+//! `GET /api/v1/meta/schemas/user/default` saved to disk:
+//!
+//! ```json
+//! {
+//!   "definitions": {
+//!     "base": { "properties": { "login": { "title": "Username", "type": "string" } } },
+//!     "custom": { "properties": { "costCenter": { "type": "string" } } }
+//!   }
+//! }
+//! ```
+//!
+//! Without a schema file, `user` falls back to the Okta base profile.
+
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use crate::typecheck::Type;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attribute {
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub ty: Type,
+    pub required: bool,
+    /// Defined in the `custom` section rather than the `base` one.
+    pub custom: bool,
+    /// `READ_WRITE`, `READ_ONLY`, ...
+    pub mutability: Option<String>,
+    /// Where the value is mastered: `PROFILE_MASTER` (the HR source or
+    /// directory the user is imported from), `OKTA` or `OVERRIDE`.
+    pub master: Option<String>,
+    /// Marked with the JSON Schema `deprecated` keyword.
+    pub deprecated: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    pub attributes: BTreeMap<String, Attribute>,
+    /// Loaded from a schema file, so attributes it does not list do not
+    /// exist. The built-in base profile is not complete, as every org adds
+    /// custom attributes to it.
+    pub complete: bool,
+    /// The schema file the profile was loaded from.
+    pub source: Option<PathBuf>,
+}
+
+/// Attributes of the Okta base user profile, all of which are Strings.
+const BASE_PROFILE: &[(&str, &str)] = &[
+    ("login", "Username"),
+    ("email", "Primary email"),
+    ("secondEmail", "Secondary email"),
+    ("firstName", "First name"),
+    ("lastName", "Last name"),
+    ("middleName", "Middle name"),
+    ("honorificPrefix", "Honorific prefix"),
+    ("honorificSuffix", "Honorific suffix"),
+    ("title", "Title"),
+    ("displayName", "Display name"),
+    ("nickName", "Nickname"),
+    ("profileUrl", "Profile Url"),
+    ("primaryPhone", "Primary phone"),
+    ("mobilePhone", "Mobile phone"),
+    ("streetAddress", "Street address"),
+    ("city", "City"),
+    ("state", "State"),
+    ("zipCode", "Zip code"),
+    ("countryCode", "Country code"),
+    ("postalAddress", "Postal Address"),
+    ("preferredLanguage", "Preferred language"),
+    ("locale", "Locale"),
+    ("timezone", "Time zone"),
+    ("userType", "User type"),
+    ("employeeNumber", "Employee number"),
+    ("costCenter", "Cost center"),
+    ("organization", "Organization"),
+    ("division", "Division"),
+    ("department", "Department"),
+    ("managerId", "ManagerId"),
+    ("manager", "Manager"),
+];
+
+impl Profile {
+    /// The Okta base user profile.
+    pub fn base() -> Self {
+        Profile {
+            attributes: BASE_PROFILE
+                .iter()
+                .map(|(name, title)| {
+                    (
+                        name.to_string(),
+                        Attribute {
+                            name: name.to_string(),
+                            title: Some(title.to_string()),
+                            description: None,
+                            ty: Type::String,
+                            required: matches!(*name, "login" | "email" | "firstName" | "lastName"),
+                            custom: false,
+                            mutability: None,
+                            master: None,
+                            deprecated: false,
+                        },
+                    )
+                })
+                .collect(),
+            complete: false,
+            source: None,
+        }
+    }
+
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        let json: serde_json::Value = serde_json::from_str(src).map_err(|err| err.to_string())?;
+        let definitions = json
+            .get("definitions")
+            .and_then(|definitions| definitions.as_object())
+            .ok_or("expected an Okta profile schema with `definitions`")?;
+        let mut attributes = BTreeMap::new();
+        for (section, definition) in definitions {
+            let required = definition
+                .get("required")
+                .and_then(|required| required.as_array())
+                .map(|required| {
+                    required
+                        .iter()
+                        .filter_map(|name| name.as_str())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default();
+            let properties = match definition
+                .get("properties")
+                .and_then(|properties| properties.as_object())
+            {
+                Some(properties) => properties,
+                None => continue,
+            };
+            for (name, property) in properties {
+                let text = |key: &str| {
+                    property
+                        .get(key)
+                        .and_then(|value| value.as_str())
+                        .map(str::to_string)
+                };
+                attributes.insert(
+                    name.clone(),
+                    Attribute {
+                        name: name.clone(),
+                        title: text("title"),
+                        description: text("description"),
+                        ty: property_type(property),
+                        required: required.contains(&name.as_str()),
+                        custom: section == "custom",
+                        mutability: text("mutability"),
+                        master: property
+                            .get("master")
+                            .and_then(|master| master.get("type"))
+                            .and_then(|ty| ty.as_str())
+                            .map(str::to_string),
+                        deprecated: property
+                            .get("deprecated")
+                            .and_then(|deprecated| deprecated.as_bool())
+                            .unwrap_or(false),
+                    },
+                );
+            }
+        }
+        Ok(Profile {
+            attributes,
+            complete: true,
+            source: None,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src =
+            std::fs::read_to_string(path).map_err(|err| format!("{}: {}", path.display(), err))?;
+        let profile =
+            Self::from_json(&src).map_err(|err| format!("{}: {}", path.display(), err))?;
+        Ok(Profile {
+            source: Some(path.to_path_buf()),
+            ..profile
+        })
+    }
+
+    pub fn get(&self, attribute: &str) -> Option<&Attribute> {
+        self.attributes.get(attribute)
+    }
+}
+
+/// Maps a JSON schema property onto an OEL type.
+fn property_type(property: &serde_json::Value) -> Type {
+    let scalar = |property: &serde_json::Value| match property
+        .get("type")
+        .and_then(|ty| ty.as_str())
+    {
+        Some("string") if property.get("format").and_then(|f| f.as_str()) == Some("date-time") => {
+            Type::Date
+        }
+        Some("string") => Type::String,
+        Some("integer") => Type::Integer,
+        Some("number") => Type::Number,
+        Some("boolean") => Type::Boolean,
+        Some("object") => Type::Object,
+        _ => Type::Any,
+    };
+    match property.get("type").and_then(|ty| ty.as_str()) {
+        Some("array") => Type::Array(Box::new(property.get("items").map_or(Type::Any, scalar))),
+        _ => scalar(property),
+    }
+}
+
+/// The profile of each root object that has one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    roots: HashMap<String, Profile>,
+}
+
+impl Default for Schema {
+    fn default() -> Self {
+        Schema {
+            roots: [("user".to_string(), Profile::base())]
+                .into_iter()
+                .collect(),
+        }
+    }
+}
+
+impl Schema {
+    /// Replaces the profile of `root`.
+    pub fn insert(&mut self, root: &str, profile: Profile) {
+        self.roots.insert(root.to_string(), profile);
+    }
+
+    pub fn profile(&self, root: &str) -> Option<&Profile> {
+        self.roots.get(root)
+    }
+
+    pub fn attribute(&self, root: &str, attribute: &str) -> Option<&Attribute> {
+        self.profile(root)?.get(attribute)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCHEMA: &str = r#"{
+      "definitions": {
+        "base": {
+          "required": ["login"],
+          "properties": {
+            "login": { "title": "Username", "type": "string" },
+            "lastLogin": { "type": "string", "format": "date-time" }
+          }
+        },
+        "custom": {
+          "properties": {
+            "level": { "type": "integer", "description": "Seniority" },
+            "score": { "type": "number" },
+            "active": { "type": "boolean" },
+            "tags": { "type": "array", "items": { "type": "string" } },
+            "extra": {}
+          }
+        }
+      }
+    }"#;
+
+    #[test]
+    fn loads_base_and_custom_properties() {
+        let profile = Profile::from_json(SCHEMA).unwrap();
+        assert!(profile.complete);
+        let login = profile.get("login").unwrap();
+        assert_eq!(login.title.as_deref(), Some("Username"));
+        assert!(login.required && !login.custom);
+        let level = profile.get("level").unwrap();
+        assert_eq!(level.description.as_deref(), Some("Seniority"));
+        assert!(!level.required && level.custom);
+        let types = profile
+            .attributes
+            .values()
+            .map(|attribute| (attribute.name.as_str(), attribute.ty.clone()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            types,
+            [
+                ("active", Type::Boolean),
+                ("extra", Type::Any),
+                ("lastLogin", Type::Date),
+                ("level", Type::Integer),
+                ("login", Type::String),
+                ("score", Type::Number),
+                ("tags", Type::Array(Box::new(Type::String))),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        assert_eq!(
+            Profile::from_json("{}").unwrap_err(),
+            "expected an Okta profile schema with `definitions`"
+        );
+        assert!(Profile::from_json("not json").is_err());
+    }
+
+    #[test]
+    fn user_defaults_to_the_base_profile() {
+        let schema = Schema::default();
+        let profile = schema.profile("user").unwrap();
+        assert!(!profile.complete);
+        assert_eq!(
+            schema
+                .attribute("user", "costCenter")
+                .unwrap()
+                .title
+                .as_deref(),
+            Some("Cost center")
+        );
+        assert!(schema.profile("appuser").is_none());
+    }
+}
@@ -1,102 +1,391 @@
+//! Semantic tokens, classified by the highlight query of the grammar
+//! (`tree-sitter-oel/queries/highlights.scm`) so they cannot drift from
+//! `grammar.js`: tree-sitter rejects the query if a node it names is gone.
+
 use std::collections::HashMap;
+use std::sync::OnceLock;
 
-use tower_lsp::lsp_types::SemanticTokenType;
-use tree_sitter::Node;
+use tower_lsp::lsp_types::{
+    Range, SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensEdit,
+};
+use tree_sitter::{Node, Point, Query, QueryCursor, Tree};
 
-use crate::oel::{ImCompleteSemanticToken, Span, Spanned};
+use crate::builtins::Registry;
+use crate::catalogue::Catalogue;
+use crate::eval::ROOTS;
+use crate::oel::ImCompleteSemanticToken;
+use crate::position::LineIndex;
+use crate::schema::Schema;
 
 pub const LEGEND_TYPE: &[SemanticTokenType] = &[
     SemanticTokenType::FUNCTION,
+    SemanticTokenType::METHOD,
     SemanticTokenType::VARIABLE,
+    SemanticTokenType::NAMESPACE,
+    SemanticTokenType::PROPERTY,
     SemanticTokenType::STRING,
-    SemanticTokenType::COMMENT,
     SemanticTokenType::NUMBER,
     SemanticTokenType::KEYWORD,
     SemanticTokenType::OPERATOR,
-    SemanticTokenType::PARAMETER,
+    SemanticTokenType::COMMENT,
+    ESCAPE_SEQUENCE,
+];
+
+/// An escape sequence in a string, e.g. `\n`. LSP has no standard type for
+/// them.
+pub const ESCAPE_SEQUENCE: SemanticTokenType = SemanticTokenType::new("escapeSequence");
+
+/// An attribute the loaded schema does not define.
+pub const UNKNOWN: SemanticTokenModifier = SemanticTokenModifier::new("unknown");
+
+/// Bit `i` of a token's modifiers stands for `LEGEND_MODIFIER[i]`.
+pub const LEGEND_MODIFIER: &[SemanticTokenModifier] = &[
+    SemanticTokenModifier::DEFAULT_LIBRARY,
+    SemanticTokenModifier::DEPRECATED,
+    SemanticTokenModifier::READONLY,
+    UNKNOWN,
 ];
 
-pub fn semantic_token_from_ast(ast: &HashMap<String, Span>) -> Vec<ImCompleteSemanticToken> {
-    // let mut semantic_tokens = vec![];
-
-    // ast.iter().for_each(|(_func_name, function)| {
-    //     function.args.iter().for_each(|(_, span)| {
-    //         semantic_tokens.push(ImCompleteSemanticToken {
-    //             start: span.start,
-    //             length: span.len(),
-    //             token_type: LEGEND_TYPE
-    //                 .iter()
-    //                 .position(|item| item == &SemanticTokenType::PARAMETER)
-    //                 .unwrap(),
-    //         });
-    //     });
-    //     let (_, span) = &function.name;
-    //     semantic_tokens.push(ImCompleteSemanticToken {
-    //         start: span.start,
-    //         length: span.len(),
-    //         token_type: LEGEND_TYPE
-    //             .iter()
-    //             .position(|item| item == &SemanticTokenType::FUNCTION)
-    //             .unwrap(),
-    //     });
-    //     semantic_token_from_expr(&function.body, &mut semantic_tokens);
-    // });
-
-    // semantic_tokens
-    Vec::new()
+static QUERY: OnceLock<Query> = OnceLock::new();
+
+fn query() -> &'static Query {
+    QUERY.get_or_init(|| {
+        Query::new(
+            tree_sitter_oel::language(),
+            include_str!("../../tree-sitter-oel/queries/highlights.scm"),
+        )
+        .expect("tree-sitter-oel/queries/highlights.scm does not match the grammar")
+    })
+}
+
+fn token_type(ty: SemanticTokenType) -> usize {
+    LEGEND_TYPE.iter().position(|item| item == &ty).unwrap()
+}
+
+fn modifier(modifier: SemanticTokenModifier) -> u32 {
+    1 << LEGEND_MODIFIER
+        .iter()
+        .position(|item| item == &modifier)
+        .unwrap()
+}
+
+/// The token type of a highlight capture, `None` for punctuation.
+fn capture_type(capture: &str) -> Option<usize> {
+    let ty = match capture {
+        "function" => SemanticTokenType::FUNCTION,
+        "function.method" => SemanticTokenType::METHOD,
+        "variable" | "variable.builtin" => SemanticTokenType::VARIABLE,
+        "namespace" => SemanticTokenType::NAMESPACE,
+        "property" => SemanticTokenType::PROPERTY,
+        "string" => SemanticTokenType::STRING,
+        "string.escape" => ESCAPE_SEQUENCE,
+        "number" => SemanticTokenType::NUMBER,
+        "constant.builtin" | "keyword" => SemanticTokenType::KEYWORD,
+        "operator" => SemanticTokenType::OPERATOR,
+        "comment" => SemanticTokenType::COMMENT,
+        _ => return None,
+    };
+    Some(token_type(ty))
+}
+
+/// Tokens of a parsed document, in document order.
+pub fn semantic_tokens(
+    tree: &Tree,
+    src: &str,
+    registry: &Registry,
+    schema: &Schema,
+) -> Vec<ImCompleteSemanticToken> {
+    let query = query();
+    // Where several patterns capture a node, the first one in the query wins.
+    let mut captures: HashMap<usize, (usize, Node, &str)> = HashMap::new();
+    let mut cursor = QueryCursor::new();
+    for query_match in cursor.matches(query, tree.root_node(), src.as_bytes()) {
+        for capture in query_match.captures {
+            let name = query.capture_names()[capture.index as usize].as_str();
+            captures
+                .entry(capture.node.id())
+                .and_modify(|first| {
+                    if query_match.pattern_index < first.0 {
+                        *first = (query_match.pattern_index, capture.node, name);
+                    }
+                })
+                .or_insert((query_match.pattern_index, capture.node, name));
+        }
+    }
+
+    let mut tokens = captures
+        .into_values()
+        .filter_map(|(_, node, capture)| {
+            let token_type = capture_type(capture)?;
+            let modifiers = modifiers(node, capture, src, registry, schema);
+            Some(
+                uncovered(node)
+                    .into_iter()
+                    .map(move |(start, end)| ImCompleteSemanticToken {
+                        start,
+                        end,
+                        token_type,
+                        modifiers,
+                    }),
+            )
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    tokens.sort_by_key(|token| token.start);
+    tokens
+}
+
+/// The parts of `node` outside its escape sequences, which are tokens of
+/// their own: LSP tokens may not overlap.
+fn uncovered(node: Node) -> Vec<(Point, Point)> {
+    let mut spans = Vec::new();
+    let mut start = node.start_position();
+    let mut cursor = node.walk();
+    for escape in node
+        .named_children(&mut cursor)
+        .filter(|child| child.kind() == "escape_sequence")
+    {
+        if start < escape.start_position() {
+            spans.push((start, escape.start_position()));
+        }
+        start = escape.end_position();
+    }
+    if start < node.end_position() {
+        spans.push((start, node.end_position()));
+    }
+    spans
+}
+
+fn modifiers(node: Node, capture: &str, src: &str, registry: &Registry, schema: &Schema) -> u32 {
+    let text = |node: Node| node.utf8_text(src.as_bytes()).unwrap_or_default();
+    match capture {
+        "variable.builtin" => modifier(SemanticTokenModifier::DEFAULT_LIBRARY),
+        "namespace" if registry.namespaces().contains(&text(node)) => {
+            modifier(SemanticTokenModifier::DEFAULT_LIBRARY)
+        }
+        "function" | "function.method" => {
+            // The whole callee, e.g. `String.len` for `len`.
+            let callee = match node.parent() {
+                Some(parent) if parent.kind() == "member_expression" => parent,
+                _ => node,
+            };
+            let name = text(callee);
+            let mut modifiers = 0;
+            // Methods of values are only known by their own name.
+            if registry.get(name).is_some()
+                || (capture == "function.method" && registry.any_method(text(node)).is_some())
+            {
+                modifiers |= modifier(SemanticTokenModifier::DEFAULT_LIBRARY);
+            }
+            if Catalogue::get()
+                .function(name)
+                .map_or(false, |doc| doc.deprecated.is_some())
+            {
+                modifiers |= modifier(SemanticTokenModifier::DEPRECATED);
+            }
+            modifiers
+        }
+        "property" => {
+            let root = node
+                .parent()
+                .and_then(|member| member.child_by_field_name("object"))
+                .map(text)
+                .filter(|root| ROOTS.contains(root));
+            let profile = match root.and_then(|root| schema.profile(root)) {
+                Some(profile) => profile,
+                None => return 0,
+            };
+            let attribute = match profile.get(text(node)) {
+                Some(attribute) => attribute,
+                None if profile.complete => return modifier(UNKNOWN),
+                None => return 0,
+            };
+            let mut modifiers = 0;
+            if attribute.mutability.as_deref() == Some("READ_ONLY") {
+                modifiers |= modifier(SemanticTokenModifier::READONLY);
+            }
+            if attribute.deprecated {
+                modifiers |= modifier(SemanticTokenModifier::DEPRECATED);
+            }
+            modifiers
+        }
+        _ => 0,
+    }
+}
+
+/// Encodes `tokens` relative to each other, as LSP expects, splitting the
+/// ones that span lines. With a `range`, only the tokens overlapping it are
+/// kept.
+pub fn encode(
+    tokens: &[ImCompleteSemanticToken],
+    index: &LineIndex,
+    range: Option<Range>,
+) -> Vec<SemanticToken> {
+    let mut data = Vec::new();
+    let mut pre_line = 0;
+    let mut pre_start = 0;
+    for token in tokens {
+        for row in token.start.row..=token.end.row {
+            let start = if row == token.start.row {
+                token.start
+            } else {
+                Point::new(row, 0)
+            };
+            let end = if row == token.end.row {
+                token.end
+            } else {
+                Point::new(row, usize::MAX)
+            };
+            let length = index.len(start, end);
+            if length == 0 {
+                continue;
+            }
+            let position = index.position(start);
+            if let Some(range) = range {
+                let end = position.character + length;
+                if position.line < range.start.line
+                    || position.line > range.end.line
+                    || (position.line == range.start.line && end <= range.start.character)
+                    || (position.line == range.end.line
+                        && position.character >= range.end.character)
+                {
+                    continue;
+                }
+            }
+            let delta_line = position.line - pre_line;
+            let delta_start = if delta_line == 0 {
+                position.character - pre_start
+            } else {
+                position.character
+            };
+            data.push(SemanticToken {
+                delta_line,
+                delta_start,
+                length,
+                token_type: token.token_type as u32,
+                token_modifiers_bitset: token.modifiers,
+            });
+            pre_line = position.line;
+            pre_start = position.character;
+        }
+    }
+    data
+}
+
+/// The single edit turning `old` into `new`, covering everything between
+/// their common prefix and suffix. Edit offsets count integers, five per
+/// token.
+pub fn delta(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(old, new)| old == new)
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(old, new)| old == new)
+        .count();
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: Some(inserted.to_vec()),
+    }]
 }
 
-pub fn semantic_token_from_expr(
-    expr: &Spanned<Node>,
-    semantic_tokens: &mut Vec<ImCompleteSemanticToken>,
-) {
-    // match &expr.0 {
-    //     Expr::Error => {}
-    //     Expr::Value(_) => {}
-    //     Expr::List(_) => {}
-    //     Expr::Local((_name, span)) => {
-    //         semantic_tokens.push(ImCompleteSemanticToken {
-    //             start: span.start,
-    //             length: span.len(),
-    //             token_type: LEGEND_TYPE
-    //                 .iter()
-    //                 .position(|item| item == &SemanticTokenType::VARIABLE)
-    //                 .unwrap(),
-    //         });
-    //     }
-    //     Expr::Let(_, rhs, rest, name_span) => {
-    //         semantic_tokens.push(ImCompleteSemanticToken {
-    //             start: name_span.start,
-    //             length: name_span.len(),
-    //             token_type: LEGEND_TYPE
-    //                 .iter()
-    //                 .position(|item| item == &SemanticTokenType::VARIABLE)
-    //                 .unwrap(),
-    //         });
-    //         semantic_token_from_expr(rhs, semantic_tokens);
-    //         semantic_token_from_expr(rest, semantic_tokens);
-    //     }
-    //     Expr::Then(first, rest) => {
-    //         semantic_token_from_expr(first, semantic_tokens);
-    //         semantic_token_from_expr(rest, semantic_tokens);
-    //     }
-    //     Expr::Binary(lhs, _op, rhs) => {
-    //         semantic_token_from_expr(lhs, semantic_tokens);
-    //         semantic_token_from_expr(rhs, semantic_tokens);
-    //     }
-    //     Expr::Call(expr, params) => {
-    //         semantic_token_from_expr(expr, semantic_tokens);
-    //         params.0.iter().for_each(|p| {
-    //             semantic_token_from_expr(p, semantic_tokens);
-    //         });
-    //     }
-    //     Expr::If(test, consequent, alternative) => {
-    //         semantic_token_from_expr(test, semantic_tokens);
-    //         semantic_token_from_expr(consequent, semantic_tokens);
-    //         semantic_token_from_expr(alternative, semantic_tokens);
-    //     }
-    //     Expr::Print(expr) => {
-    //         semantic_token_from_expr(expr, semantic_tokens);
-    //     }
-    // }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oel::parse_tree;
+    use tower_lsp::lsp_types::Position;
+
+    fn tokens(src: &str) -> Vec<(String, SemanticTokenType)> {
+        let tree = parse_tree(src, None).unwrap();
+        semantic_tokens(&tree, src, &Registry::default(), &Schema::default())
+            .into_iter()
+            .map(|token| {
+                let line = src.lines().nth(token.start.row).unwrap();
+                (
+                    line[token.start.column..token.end.column].to_string(),
+                    LEGEND_TYPE[token.token_type].clone(),
+                )
+            })
+            .collect()
+    }
+
+    fn token(start: (usize, usize), end: (usize, usize)) -> ImCompleteSemanticToken {
+        ImCompleteSemanticToken {
+            start: Point::new(start.0, start.1),
+            end: Point::new(end.0, end.1),
+            token_type: token_type(SemanticTokenType::STRING),
+            modifiers: 0,
+        }
+    }
+
+    fn data(tokens: &[SemanticToken]) -> Vec<(u32, u32, u32)> {
+        tokens
+            .iter()
+            .map(|token| (token.delta_line, token.delta_start, token.length))
+            .collect()
+    }
+
+    #[test]
+    fn highlights_query_matches_grammar() {
+        let captures = query().capture_names();
+        assert!(captures.iter().any(|capture| capture == "property"));
+        assert!(captures.iter().any(|capture| capture == "function.method"));
+    }
+
+    #[test]
+    fn properties_and_methods() {
+        let tokens = tokens("user.login AND String.len(user.email) > 0");
+        assert!(tokens.contains(&("login".to_string(), SemanticTokenType::PROPERTY)));
+        assert!(tokens.contains(&("len".to_string(), SemanticTokenType::METHOD)));
+        assert!(tokens.contains(&("String".to_string(), SemanticTokenType::NAMESPACE)));
+    }
+
+    #[test]
+    fn encodes_relative_positions_and_splits_lines() {
+        let src = "'é' + 'a\nbc'";
+        let index = LineIndex::new(src, Default::default());
+        let tokens = [token((0, 0), (0, 4)), token((0, 7), (1, 3))];
+        assert_eq!(
+            data(&encode(&tokens, &index, None)),
+            [(0, 0, 3), (0, 6, 2), (1, 0, 3)]
+        );
+        let second_line = Range::new(Position::new(1, 0), Position::new(1, 1));
+        assert_eq!(
+            data(&encode(&tokens, &index, Some(second_line))),
+            [(1, 0, 3)]
+        );
+    }
+
+    #[test]
+    fn delta_replaces_the_changed_tokens_only() {
+        let index = LineIndex::new("'a' 'b' 'c'", Default::default());
+        let old = encode(
+            &[
+                token((0, 0), (0, 3)),
+                token((0, 4), (0, 7)),
+                token((0, 8), (0, 11)),
+            ],
+            &index,
+            None,
+        );
+        assert!(delta(&old, &old).is_empty());
+        let new = encode(
+            &[token((0, 0), (0, 3)), token((0, 8), (0, 11))],
+            &index,
+            None,
+        );
+        let edits = delta(&old, &new);
+        assert_eq!((edits[0].start, edits[0].delete_count), (5, 10));
+        assert_eq!(data(edits[0].data.as_deref().unwrap()), [(0, 8, 3)]);
+    }
 }
@@ -0,0 +1,247 @@
+//! Signature help inside the argument list of a built-in call.
+
+use tree_sitter::Point;
+
+use crate::builtins::Registry;
+use crate::oel::{walk, Expr, Spanned};
+use crate::position::LineIndex;
+use crate::schema::Schema;
+use crate::typecheck::{Signature, Type, TypeChecker};
+
+pub struct ImCompleteSignatureHelp {
+    pub name: String,
+    pub signatures: Vec<Signature>,
+    pub active_signature: usize,
+    /// Index into the parameters of the active signature; a variadic
+    /// parameter stays active for all the arguments it takes.
+    pub active_parameter: usize,
+}
+
+/// Signature help for the call whose argument list contains `position`.
+pub fn signature_help(
+    ast: Option<&Spanned<Expr>>,
+    src: &str,
+    position: Point,
+    registry: &Registry,
+    schema: &Schema,
+) -> Option<ImCompleteSignatureHelp> {
+    let index = LineIndex::new(src, Default::default());
+    let cursor = index.offset(position);
+    let (name, open, arg_types) = ast
+        .and_then(|ast| call_at(ast, &index, src, cursor, registry, schema))
+        .or_else(|| call_before(src, cursor))?;
+    let builtin = registry.get(&name)?;
+    let signatures = Signature::all(builtin);
+
+    let active_argument = top_level_commas(&src[open + 1..cursor]);
+    // Only the arguments before the cursor count, and the one being typed
+    // may be incomplete.
+    let typed = &arg_types[..arg_types.len().min(active_argument)];
+    let active_signature = signatures
+        .iter()
+        .position(|signature| {
+            signature.accepts(typed)
+                && (signature.param(active_argument).is_some() || active_argument == 0)
+        })
+        .unwrap_or(0);
+    let active_parameter = match signatures[active_signature].params.len() {
+        0 => 0,
+        len => active_argument.min(len - 1),
+    };
+    Some(ImCompleteSignatureHelp {
+        name,
+        signatures,
+        active_signature,
+        active_parameter,
+    })
+}
+
+/// The innermost call in the tree whose `arguments` contain the cursor: its
+/// name, the offset of its `(` and the types of its arguments.
+fn call_at(
+    ast: &Spanned<Expr>,
+    index: &LineIndex,
+    src: &str,
+    cursor: usize,
+    registry: &Registry,
+    schema: &Schema,
+) -> Option<(String, usize, Vec<Type>)> {
+    let mut found = None;
+    walk(ast, &mut |(expr, span)| {
+        if let Expr::Call {
+            function,
+            arguments,
+        } = expr
+        {
+            let (open, end) = (index.offset(function.1.end), index.offset(span.end));
+            let open = match src[open..].find('(') {
+                Some(at) => open + at,
+                None => return,
+            };
+            let closed = src[..end].ends_with(')');
+            let inside = open < cursor && (cursor < end || (cursor == end && !closed));
+            if let (true, Some(name)) = (inside, function.0.path()) {
+                if registry.get(&name).is_some() {
+                    found = Some((name, open, arguments));
+                }
+            }
+        }
+    });
+    let (name, open, arguments) = found?;
+    let mut checker = TypeChecker::new(registry, schema);
+    let types = arguments
+        .iter()
+        .map(|argument| checker.infer(argument))
+        .collect();
+    Some((name, open, types))
+}
+
+/// Finds the unclosed `(` before the cursor when the call could not be
+/// parsed, e.g. `String.substringAfter(` on its own.
+fn call_before(src: &str, cursor: usize) -> Option<(String, usize, Vec<Type>)> {
+    let before = &src[..cursor];
+    let mut depth = 0usize;
+    let mut open = None;
+    for (at, c) in before.char_indices().rev() {
+        match c {
+            ')' | '}' => depth += 1,
+            '(' | '{' if depth > 0 => depth -= 1,
+            '(' => {
+                open = Some(at);
+                break;
+            }
+            '{' => return None,
+            _ => {}
+        }
+    }
+    let open = open?;
+    let name_start = before[..open]
+        .char_indices()
+        .rev()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_' || *c == '.'))
+        .map_or(0, |(at, c)| at + c.len_utf8());
+    let name = before[name_start..open].trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some((name.to_string(), open, Vec::new()))
+}
+
+/// Commas separating arguments, ignoring those in nested calls, arrays and
+/// strings.
+fn top_level_commas(args: &str) -> usize {
+    let mut depth = 0usize;
+    let mut quote = None;
+    // Whether the previous character was a backslash escaping this one.
+    let mut escaped = false;
+    let mut commas = 0;
+    for c in args.chars() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '(' | '{' | '[') => depth += 1,
+            (None, ')' | '}' | ']') => depth = depth.saturating_sub(1),
+            (None, ',') if depth == 0 => commas += 1,
+            _ => {}
+        }
+    }
+    commas
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oel::parse;
+
+    /// The call, active signature and active parameter at the end of `src`,
+    /// from the text alone as while the call is being typed.
+    fn help_at_end(src: &str) -> Option<(String, usize, usize)> {
+        let row = src.lines().count().max(1) - 1;
+        let column = src.lines().last().map_or(0, str::len);
+        let help = signature_help(
+            None,
+            src,
+            Point::new(row, column),
+            &Registry::default(),
+            &Schema::default(),
+        )?;
+        Some((help.name, help.active_signature, help.active_parameter))
+    }
+
+    #[test]
+    fn counts_commas_outside_nesting_and_strings() {
+        assert_eq!(top_level_commas(""), 0);
+        assert_eq!(top_level_commas("a, f(b, c), {1, 2}, d[0]"), 3);
+        assert_eq!(top_level_commas(r#"'x,y', "a,b""#), 1);
+        assert_eq!(top_level_commas(r#""\",", a"#), 1);
+        assert_eq!(top_level_commas(r#""\"", a, b"#), 2);
+        assert_eq!(top_level_commas(r"'it\'s, ok', b"), 1);
+        assert_eq!(top_level_commas(r#""\\", b"#), 1);
+        assert_eq!(top_level_commas(r#"'"', "'", x"#), 2);
+    }
+
+    #[test]
+    fn active_parameter_while_typing() {
+        let help = |src: &str| help_at_end(src).map(|(name, _, parameter)| (name, parameter));
+        assert_eq!(
+            help("String.substring(user.login, "),
+            Some(("String.substring".to_string(), 1))
+        );
+        assert_eq!(help("String.len("), Some(("String.len".to_string(), 0)));
+        // The variadic parameter stays active.
+        assert_eq!(
+            help("String.join(\",\",\n  a, b, "),
+            Some(("String.join".to_string(), 1))
+        );
+        assert_eq!(
+            help(r#"String.join("\",", a, "#),
+            Some(("String.join".to_string(), 1))
+        );
+        assert_eq!(
+            help(r#"String.substring("\"", "#),
+            Some(("String.substring".to_string(), 1))
+        );
+        assert_eq!(
+            help("'é' + String.len(String.substring(a, "),
+            Some(("String.substring".to_string(), 1))
+        );
+        assert_eq!(help("String.len(a) + "), None);
+        assert_eq!(help("{1, "), None);
+    }
+
+    #[test]
+    fn overloads_follow_the_number_of_arguments() {
+        let signature = |src: &str| help_at_end(src).map(|(_, signature, _)| signature);
+        assert_eq!(signature("Time.now("), Some(0));
+        assert_eq!(signature("Time.now('UTC', "), Some(2));
+        let help = help_at_end("Time.now('UTC', 'yyyy', ").unwrap();
+        assert_eq!((help.1, help.2), (0, 0));
+    }
+
+    #[test]
+    fn uses_the_types_of_parsed_arguments() {
+        let src = "String.substring(user.login, 1, 3)";
+        let (entries, _) = parse(src);
+        let help = signature_help(
+            Some(&entries[0].value),
+            src,
+            Point::new(0, 30),
+            &Registry::default(),
+            &Schema::default(),
+        )
+        .unwrap();
+        assert_eq!(help.name, "String.substring");
+        assert_eq!(help.active_parameter, 1);
+        assert!(signature_help(
+            Some(&entries[0].value),
+            src,
+            Point::new(0, src.len()),
+            &Registry::default(),
+            &Schema::default(),
+        )
+        .is_none());
+    }
+}
@@ -0,0 +1,370 @@
+//! Turns tree-sitter's `ERROR` and `MISSING` nodes into messages that say
+//! what is wrong, e.g. "unclosed '('" or "dangling operator AND", rather
+//! than a bare "Parse error".
+//!
+//! tree-sitter only records where it had to recover, so each message is
+//! derived from the tokens around the recovery point: the parent node a
+//! token is missing from, or the tokens an `ERROR` node swallowed.
+//!
+//! Escape sequences parse whatever follows the backslash, so invalid ones
+//! such as `\q` are reported here too.
+
+use tree_sitter::{Node, Point};
+
+use crate::oel::{string_value, BinaryOp, ErrorToken, RelatedSpan};
+
+const BRACKETS: &[(&str, &str)] = &[("(", ")"), ("[", "]"), ("{", "}")];
+const QUOTES: &[&str] = &["\"", "'"];
+
+/// Every syntax error in the tree rooted at `root`, in source order.
+pub fn syntax_errors(root: Node<'_>, src: &str) -> Vec<ErrorToken> {
+    let mut errors = Vec::new();
+    collect(root, src, &mut errors);
+    escapes(root, src, &mut errors);
+    errors.sort_by_key(|error| error.start);
+    errors
+}
+
+fn escapes(node: Node<'_>, src: &str, errors: &mut Vec<ErrorToken>) {
+    if node.kind() == "string" {
+        errors.extend(string_value(node, src).1);
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        escapes(child, src, errors);
+    }
+}
+
+fn collect(node: Node<'_>, src: &str, errors: &mut Vec<ErrorToken>) {
+    if node.is_missing() {
+        errors.push(missing(node));
+        return;
+    }
+    // Only the innermost ERROR nodes are reported, as an outer one would
+    // just repeat the message of the one it contains.
+    if node.is_error() && !contains_error(node) {
+        errors.push(unexpected(node, src));
+        return;
+    }
+    if !node.has_error() {
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect(child, src, errors);
+    }
+}
+
+fn contains_error(node: Node<'_>) -> bool {
+    let mut cursor = node.walk();
+    let children = node.children(&mut cursor).collect::<Vec<_>>();
+    children
+        .into_iter()
+        .any(|child| child.is_error() || (child.has_error() && contains_error(child)))
+}
+
+fn text<'a>(node: Node<'_>, src: &'a str) -> &'a str {
+    node.utf8_text(src.as_bytes()).unwrap_or("")
+}
+
+fn error(start: Point, end: Point, message: impl Into<String>) -> ErrorToken {
+    ErrorToken {
+        start,
+        end,
+        message: message.into(),
+        related: Vec::new(),
+    }
+}
+
+fn related(node: Node<'_>, message: impl Into<String>) -> RelatedSpan {
+    RelatedSpan {
+        start: node.start_position(),
+        end: node.end_position(),
+        message: message.into(),
+    }
+}
+
+/// The first anonymous child of `node` with the given kind, e.g. the `?` of a
+/// ternary.
+fn token<'a>(node: Node<'a>, kind: &str) -> Option<Node<'a>> {
+    let mut cursor = node.walk();
+    let found = node
+        .children(&mut cursor)
+        .find(|child| !child.is_missing() && child.kind() == kind);
+    found
+}
+
+fn is_binary_operator(kind: &str) -> bool {
+    BinaryOp::from_token(kind).is_some()
+}
+
+/// A token tree-sitter inserted to complete the parse.
+fn missing(node: Node<'_>) -> ErrorToken {
+    let position = node.start_position();
+    let parent = node.parent();
+    let kind = node.kind();
+
+    if let Some((open, _)) = BRACKETS.iter().find(|(_, close)| *close == kind) {
+        let mut err = error(position, position, format!("unclosed '{}'", open));
+        if let Some(open) = parent.and_then(|parent| token(parent, open)) {
+            err.related
+                .push(related(open, format!("'{}' opened here", open.kind())));
+        }
+        return err;
+    }
+    if QUOTES.contains(&kind) {
+        let mut err = error(position, position, "unterminated string literal");
+        if let Some(open) = parent.and_then(|parent| token(parent, kind)) {
+            err.related.push(related(open, "string starts here"));
+        }
+        return err;
+    }
+    if kind == ":" {
+        let mut err = error(
+            position,
+            position,
+            "expected ':' to complete ternary started here",
+        );
+        if let Some(question) = parent.and_then(|parent| token(parent, "?")) {
+            err.related.push(related(question, "ternary started here"));
+        }
+        return err;
+    }
+
+    // Anything else is a missing operand, described by the token before it.
+    match node.prev_sibling() {
+        Some(prev) if is_binary_operator(prev.kind()) => {
+            let mut err = error(
+                prev.start_position(),
+                prev.end_position(),
+                format!("dangling operator {}", prev.kind()),
+            );
+            err.related
+                .push(related(node, "expected an expression here"));
+            err
+        }
+        Some(prev) if prev.kind() == "." => {
+            error(position, position, "expected a property name after '.'")
+        }
+        Some(prev) if !prev.is_named() => error(
+            position,
+            position,
+            format!("expected an expression after '{}'", prev.kind()),
+        ),
+        _ => error(position, position, format!("expected {}", describe(kind))),
+    }
+}
+
+fn describe(kind: &str) -> String {
+    match kind {
+        "identifier" | "property_identifier" => "an identifier".to_string(),
+        "integer" => "an integer".to_string(),
+        kind => format!("'{}'", kind),
+    }
+}
+
+/// Explains an `ERROR` node from the tokens it contains.
+fn unexpected(node: Node<'_>, src: &str) -> ErrorToken {
+    let mut cursor = node.walk();
+    let children = node
+        .children(&mut cursor)
+        .filter(|child| !child.is_extra())
+        .collect::<Vec<_>>();
+
+    // An ERROR without children is text the lexer could not match at all.
+    if children.is_empty() {
+        let text = text(node, src);
+        return match text.chars().next() {
+            Some(c @ ('"' | '\'')) => error(
+                node.start_position(),
+                node.end_position(),
+                "unterminated string literal",
+            )
+            .with_note(format!("add a closing {} at the end of the string", c)),
+            Some(c) if text.chars().count() == 1 => error(
+                node.start_position(),
+                node.end_position(),
+                format!("unexpected character '{}'", c),
+            ),
+            _ => error(
+                node.start_position(),
+                node.end_position(),
+                format!("unexpected '{}'", abbreviate(text)),
+            ),
+        };
+    }
+
+    // Unmatched quotes and brackets, innermost first.
+    let mut open: Vec<Node<'_>> = Vec::new();
+    for child in &children {
+        let kind = child.kind();
+        if QUOTES.contains(&kind) {
+            match open.last() {
+                Some(last) if last.kind() == kind => {
+                    open.pop();
+                }
+                _ => open.push(*child),
+            }
+        } else if BRACKETS.iter().any(|(o, _)| *o == kind) {
+            open.push(*child);
+        } else if let Some((o, _)) = BRACKETS.iter().find(|(_, c)| *c == kind) {
+            if open.last().map(|last| last.kind()) == Some(*o) {
+                open.pop();
+            }
+        }
+    }
+    if let Some(opener) = open.pop() {
+        let end = node.end_position();
+        return if QUOTES.contains(&opener.kind()) {
+            let mut err = error(opener.start_position(), end, "unterminated string literal");
+            err.related.push(related(opener, "string starts here"));
+            err
+        } else {
+            let mut err = error(end, end, format!("unclosed '{}'", opener.kind()));
+            err.related
+                .push(related(opener, format!("'{}' opened here", opener.kind())));
+            err
+        };
+    }
+
+    if let Some(question) = children.iter().find(|child| child.kind() == "?") {
+        if !children.iter().any(|child| child.kind() == ":") {
+            let end = node.end_position();
+            let mut err = error(end, end, "expected ':' to complete ternary started here");
+            err.related.push(related(*question, "ternary started here"));
+            return err;
+        }
+    }
+
+    let first = children[0];
+    let last = children[children.len() - 1];
+    if is_binary_operator(last.kind()) {
+        return error(
+            last.start_position(),
+            last.end_position(),
+            format!("dangling operator {}", last.kind()),
+        )
+        .with_note("expected an expression after it");
+    }
+    if is_binary_operator(first.kind()) {
+        return error(
+            first.start_position(),
+            first.end_position(),
+            format!("operator {} is missing its left operand", first.kind()),
+        );
+    }
+    if last.kind() == "!" {
+        return error(
+            last.start_position(),
+            last.end_position(),
+            "expected an expression after '!'",
+        );
+    }
+    if last.kind() == "." {
+        return error(
+            last.start_position(),
+            last.end_position(),
+            "expected a property name after '.'",
+        );
+    }
+    error(
+        node.start_position(),
+        node.end_position(),
+        format!("unexpected '{}'", abbreviate(text(node, src))),
+    )
+}
+
+/// Keeps messages to a single short line.
+fn abbreviate(text: &str) -> String {
+    const MAX: usize = 20;
+    let line = text.lines().next().unwrap_or("").trim();
+    if line.chars().count() > MAX || line.len() < text.trim().len() {
+        format!("{}...", line.chars().take(MAX).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+impl ErrorToken {
+    fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.message = format!("{}: {}", self.message, note.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oel::parse;
+
+    fn errors(src: &str) -> Vec<ErrorToken> {
+        let (_, errors) = parse(src);
+        errors
+    }
+
+    /// The error whose message starts with `message`.
+    fn find(src: &str, message: &str) -> ErrorToken {
+        let errors = errors(src);
+        let messages = errors
+            .iter()
+            .map(|error| error.message.clone())
+            .collect::<Vec<_>>();
+        errors
+            .into_iter()
+            .find(|error| error.message.starts_with(message))
+            .unwrap_or_else(|| panic!("{:?}: no {:?} in {:?}", src, message, messages))
+    }
+
+    #[test]
+    fn valid_expressions_have_no_errors() {
+        assert!(errors("user.login == \"a\" AND !(1 < 2) ? {1, 2}[0] : null").is_empty());
+    }
+
+    #[test]
+    fn unclosed_brackets_point_at_the_opener() {
+        let error = find("String.len((user.login)", "unclosed '('");
+        assert_eq!(error.related.len(), 1);
+        assert_eq!(error.related[0].message, "'(' opened here");
+        assert_eq!(error.related[0].start, Point::new(0, 10));
+        find("{1, 2", "unclosed '{'");
+    }
+
+    #[test]
+    fn unterminated_strings() {
+        find("user.login == \"admin", "unterminated string literal");
+    }
+
+    #[test]
+    fn dangling_operators() {
+        let error = find("user.login ==", "dangling operator ==");
+        assert_eq!(error.start, Point::new(0, 11));
+        find("true AND", "dangling operator AND");
+    }
+
+    #[test]
+    fn incomplete_ternaries() {
+        let error = find("true ? 1", "expected ':' to complete ternary started here");
+        assert_eq!(error.related[0].message, "ternary started here");
+        assert_eq!(error.related[0].start, Point::new(0, 5));
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        let error = find("user.login == @", "unexpected character '@'");
+        assert_eq!(
+            (error.start, error.end),
+            (Point::new(0, 14), Point::new(0, 15))
+        );
+    }
+
+    #[test]
+    fn long_text_is_abbreviated() {
+        assert_eq!(abbreviate("short"), "short");
+        assert_eq!(abbreviate("first\nsecond"), "first...");
+        assert_eq!(
+            abbreviate("abcdefghijklmnopqrstuvwxyz"),
+            "abcdefghijklmnopqrst..."
+        );
+    }
+}
@@ -0,0 +1,1039 @@
+//! Static type checking, catching the mistakes Okta would otherwise only
+//! reject when the expression is saved: mixing types in operators and
+//! ternary branches, non-Boolean conditions and wrong arguments to built-in
+//! functions.
+//!
+//! Attributes of `user`, `app` and the other roots are typed by the
+//! [`Schema`]; anything it does not know about is [`Type::Any`] and never
+//! causes an error. Roots whose profile was loaded from a schema file also
+//! get "unknown attribute" errors.
+//!
+//! Roots and functions not available in the [`ExpressionContext`] of the file
+//! are errors too, with fixes suggesting a context that does allow them.
+//!
+//! In the Identity Engine [`Dialect`], calls on values are checked against
+//! the methods of their type and attributes are read from `user.profile`.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::builtins::{Builtin, Registry};
+use crate::dialect::{self, Dialect};
+use crate::eval::ROOTS;
+use crate::expression_context::{ExpressionContext, PRAGMA};
+use crate::oel::{BinaryOp, Entry, Expr, Span, Spanned, UnaryOp, Value};
+use crate::schema::Schema;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Type {
+    /// Unknown, compatible with everything.
+    Any,
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    /// An ISO 8601 string, as returned by the `Time` functions.
+    Date,
+    Object,
+    Array(Box<Type>),
+    /// The type parameter `T` of generic signatures such as `Arrays.get`.
+    Var,
+}
+
+impl Type {
+    fn parse(src: &str) -> Option<Type> {
+        Some(match src.trim() {
+            "Any" => Type::Any,
+            "Boolean" => Type::Boolean,
+            "Integer" => Type::Integer,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            "Date" => Type::Date,
+            "Object" => Type::Object,
+            "T" => Type::Var,
+            src => {
+                let element = src.strip_prefix("Array<")?.strip_suffix('>')?;
+                Type::Array(Box::new(Type::parse(element)?))
+            }
+        })
+    }
+
+    pub fn of(value: &Value) -> Type {
+        match value {
+            Value::Null => Type::Null,
+            Value::Bool(_) => Type::Boolean,
+            Value::Int(_) => Type::Integer,
+            Value::Num(_) => Type::Number,
+            Value::Str(_) => Type::String,
+            Value::List(items) => Type::Array(Box::new(
+                items
+                    .iter()
+                    .map(Type::of)
+                    .reduce(|a, b| join(&a, &b).unwrap_or(Type::Any))
+                    .unwrap_or(Type::Any),
+            )),
+            Value::Object(_) => Type::Object,
+            Value::Func(_) => Type::Any,
+        }
+    }
+
+    /// Types that say nothing about the value, so no error can be reported.
+    fn is_unknown(&self) -> bool {
+        matches!(self, Type::Any | Type::Null | Type::Var)
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, Type::Integer | Type::Number)
+    }
+
+    fn is_string(&self) -> bool {
+        matches!(self, Type::String | Type::Date)
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Any => write!(f, "Any"),
+            Type::Null => write!(f, "null"),
+            Type::Boolean => write!(f, "Boolean"),
+            Type::Integer => write!(f, "Integer"),
+            Type::Number => write!(f, "Number"),
+            Type::String => write!(f, "String"),
+            Type::Date => write!(f, "Date"),
+            Type::Object => write!(f, "Object"),
+            Type::Array(element) => write!(f, "Array<{}>", element),
+            Type::Var => write!(f, "T"),
+        }
+    }
+}
+
+/// The most specific type both `a` and `b` fit in, if any.
+fn join(a: &Type, b: &Type) -> Option<Type> {
+    match (a, b) {
+        _ if a == b => Some(a.clone()),
+        (Type::Any, _) | (_, Type::Any) => Some(Type::Any),
+        (Type::Null, other) | (other, Type::Null) => Some(other.clone()),
+        _ if a.is_numeric() && b.is_numeric() => Some(Type::Number),
+        _ if a.is_string() && b.is_string() => Some(Type::String),
+        (Type::Array(a), Type::Array(b)) => {
+            Some(Type::Array(Box::new(join(a, b).unwrap_or(Type::Any))))
+        }
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Param {
+    pub name: String,
+    pub ty: Type,
+    pub optional: bool,
+    /// Takes any number of arguments, e.g. `strings: String...`.
+    pub variadic: bool,
+}
+
+/// The parsed form of [`Builtin::signature`], e.g.
+/// `(timeZone?: String, format?: String) -> Date`. `?` marks an optional
+/// parameter, a trailing `...` a variadic one, and `T` a type shared between
+/// parameters and the result.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<Param>,
+    pub returns: Type,
+}
+
+impl Signature {
+    pub fn parse(src: &str) -> Option<Signature> {
+        let (params, returns) = src.split_once("->")?;
+        let params = params.trim().strip_prefix('(')?.strip_suffix(')')?;
+        let params = params
+            .split(',')
+            .map(str::trim)
+            .filter(|param| !param.is_empty())
+            .map(|param| {
+                let (name, ty) = param.split_once(':')?;
+                let (name, optional) = match name.trim().strip_suffix('?') {
+                    Some(name) => (name, true),
+                    None => (name.trim(), false),
+                };
+                let (ty, variadic) = match ty.trim().strip_suffix("...") {
+                    Some(ty) => (ty, true),
+                    None => (ty.trim(), false),
+                };
+                Some(Param {
+                    name: name.to_string(),
+                    ty: Type::parse(ty)?,
+                    optional,
+                    variadic,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(Signature {
+            params,
+            returns: Type::parse(returns)?,
+        })
+    }
+
+    /// The overloads of a built-in. Malformed overloads are skipped, and a
+    /// built-in left without any takes any arguments, so that a mistake in a
+    /// signature loses some checking rather than the server.
+    pub fn all(builtin: &Builtin) -> Vec<Signature> {
+        let signatures = builtin
+            .signature
+            .split(" | ")
+            .filter_map(Signature::parse)
+            .collect::<Vec<_>>();
+        if signatures.is_empty() {
+            return vec![Signature::unknown()];
+        }
+        signatures
+    }
+
+    /// Any number of arguments of any type, returning [`Type::Any`].
+    fn unknown() -> Signature {
+        Signature {
+            params: vec![Param {
+                name: "arguments".to_string(),
+                ty: Type::Any,
+                optional: true,
+                variadic: true,
+            }],
+            returns: Type::Any,
+        }
+    }
+
+    /// The first overload of a built-in.
+    pub fn of(builtin: &Builtin) -> Signature {
+        Signature::all(builtin).remove(0)
+    }
+
+    /// The overload that best fits arguments of the given types: the first
+    /// one accepting them all, or else the first taking that many arguments.
+    pub fn best<'a>(signatures: &'a [Signature], args: &[Type]) -> Option<&'a Signature> {
+        signatures
+            .iter()
+            .find(|signature| signature.accepts_arity(args.len()) && signature.accepts(args))
+            .or_else(|| {
+                signatures
+                    .iter()
+                    .find(|signature| signature.accepts_arity(args.len()))
+            })
+    }
+
+    fn accepts_arity(&self, args: usize) -> bool {
+        args >= self.min_args() && self.max_args().map_or(true, |max| args <= max)
+    }
+
+    /// Whether arguments of these types can be passed, ignoring their number.
+    pub fn accepts(&self, args: &[Type]) -> bool {
+        let mut var = None;
+        args.iter().enumerate().all(|(index, ty)| {
+            self.param(index)
+                .map_or(true, |param| accepts(&param.ty, ty, &mut var))
+        })
+    }
+
+    fn min_args(&self) -> usize {
+        self.params
+            .iter()
+            .filter(|param| !param.optional && !param.variadic)
+            .count()
+    }
+
+    fn max_args(&self) -> Option<usize> {
+        match self.params.last() {
+            Some(param) if param.variadic => None,
+            _ => Some(self.params.len()),
+        }
+    }
+
+    /// The parameter an argument is passed to.
+    pub fn param(&self, index: usize) -> Option<&Param> {
+        self.params
+            .get(index)
+            .or_else(|| self.params.last().filter(|param| param.variadic))
+    }
+}
+
+impl fmt::Display for Param {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}{}: {}{}",
+            self.name,
+            if self.optional { "?" } else { "" },
+            self.ty,
+            if self.variadic { "..." } else { "" }
+        )
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params = self
+            .params
+            .iter()
+            .map(Param::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        write!(f, "({}) -> {}", params, self.returns)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeError {
+    pub span: Span,
+    pub msg: String,
+    /// Changes that would resolve the error, offered as quick fixes.
+    pub fixes: Vec<Fix>,
+}
+
+/// A suggested change resolving a [`TypeError`].
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Fix {
+    /// Replace the span of the error with this text.
+    Replace(String),
+    /// Switch the file to a context in which the expression is valid.
+    SetContext(ExpressionContext),
+    /// Switch the file to a dialect in which the expression is valid.
+    SetDialect(Dialect),
+}
+
+impl Fix {
+    pub fn title(&self) -> String {
+        match self {
+            Fix::Replace(text) => format!("Replace with `{}`", text),
+            Fix::SetContext(context) => format!(
+                "Use it in {} (`// {} {}`)",
+                context.name().to_lowercase(),
+                PRAGMA,
+                context
+            ),
+            Fix::SetDialect(dialect) => format!(
+                "Switch to {} (`// {} {}`)",
+                dialect.name(),
+                dialect::PRAGMA,
+                dialect
+            ),
+        }
+    }
+}
+
+impl TypeError {
+    pub fn new(span: &Span, msg: impl Into<String>) -> Self {
+        TypeError {
+            span: span.clone(),
+            msg: msg.into(),
+            fixes: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}",
+            self.span.start.row + 1,
+            self.span.start.column + 1,
+            self.msg
+        )
+    }
+}
+
+pub fn check(
+    expr: &Spanned<Expr>,
+    registry: &Registry,
+    schema: &Schema,
+    context: ExpressionContext,
+    dialect: Dialect,
+) -> Vec<TypeError> {
+    let mut checker = TypeChecker::new(registry, schema)
+        .with_context(context)
+        .with_dialect(dialect);
+    checker.infer(expr);
+    checker.errors
+}
+
+/// Checks each entry of a file on its own, and that no two share a name.
+pub fn check_entries(
+    entries: &[Entry],
+    registry: &Registry,
+    schema: &Schema,
+    context: ExpressionContext,
+    dialect: Dialect,
+) -> Vec<TypeError> {
+    let mut errors = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some((name, span)) = &entry.name {
+            let duplicate = entries[..index]
+                .iter()
+                .any(|earlier| earlier.name.as_ref().map(|(name, _)| name) == Some(name));
+            if duplicate {
+                errors.push(TypeError::new(span, format!("duplicate rule \"{}\"", name)));
+            }
+        }
+        errors.extend(check(&entry.value, registry, schema, context, dialect));
+    }
+    errors
+}
+
+pub struct TypeChecker<'a> {
+    registry: &'a Registry,
+    schema: &'a Schema,
+    context: ExpressionContext,
+    dialect: Dialect,
+    pub errors: Vec<TypeError>,
+}
+
+impl<'a> TypeChecker<'a> {
+    pub fn new(registry: &'a Registry, schema: &'a Schema) -> Self {
+        TypeChecker {
+            registry,
+            schema,
+            context: ExpressionContext::Any,
+            dialect: Dialect::Classic,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn with_context(mut self, context: ExpressionContext) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_dialect(mut self, dialect: Dialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    fn error(&mut self, span: &Span, msg: impl Into<String>) {
+        self.errors.push(TypeError::new(span, msg));
+    }
+
+    /// Infers the type of `expr`, recording any errors on the way.
+    pub fn infer(&mut self, expr: &Spanned<Expr>) -> Type {
+        let (expr, span) = expr;
+        match expr {
+            Expr::Error => Type::Any,
+            Expr::Value(value) => Type::of(value),
+            Expr::Ident(name) if ROOTS.contains(&name.as_str()) => {
+                if !self.context.allows_root(name) {
+                    let mut fixes = Vec::new();
+                    if self.context.allows_root("user") {
+                        fixes.push(Fix::Replace("user".to_string()));
+                    }
+                    fixes.extend(
+                        ExpressionContext::with_root(name)
+                            .into_iter()
+                            .map(Fix::SetContext),
+                    );
+                    self.errors.push(TypeError {
+                        fixes,
+                        ..TypeError::new(
+                            span,
+                            format!(
+                                "`{}` is not available in {}",
+                                name,
+                                self.context.name().to_lowercase()
+                            ),
+                        )
+                    });
+                }
+                Type::Object
+            }
+            Expr::Ident(name) => {
+                self.error(span, format!("unknown identifier `{}`", name));
+                Type::Any
+            }
+            Expr::Array(items) => {
+                let element = items
+                    .iter()
+                    .map(|item| self.infer(item))
+                    .reduce(|a, b| join(&a, &b).unwrap_or(Type::Any))
+                    .unwrap_or(Type::Any);
+                Type::Array(Box::new(element))
+            }
+            Expr::Map(entries) => {
+                for (_, value) in entries {
+                    self.infer(value);
+                }
+                Type::Object
+            }
+            Expr::Member { object, property } => match self.infer(object) {
+                Type::Object => match (&object.0, self.dialect) {
+                    (Expr::Ident(root), Dialect::Classic) => self.attribute(root, property),
+                    (Expr::Ident(_), Dialect::IdentityEngine) if property.0 == "profile" => {
+                        Type::Object
+                    }
+                    (
+                        Expr::Member {
+                            object: root,
+                            property: (profile, _),
+                        },
+                        Dialect::IdentityEngine,
+                    ) if profile == "profile" => match &root.0 {
+                        Expr::Ident(root) if ROOTS.contains(&root.as_str()) => {
+                            self.attribute(root, property)
+                        }
+                        _ => Type::Any,
+                    },
+                    _ => Type::Any,
+                },
+                Type::Null => Type::Null,
+                ty if ty.is_unknown() => Type::Any,
+                ty => {
+                    self.error(
+                        &property.1,
+                        format!("cannot access `{}` on a value of type {}", property.0, ty),
+                    );
+                    Type::Any
+                }
+            },
+            Expr::Subscript { object, index } => {
+                let index_ty = self.infer(index);
+                if !index_ty.is_unknown() && index_ty != Type::Integer {
+                    self.error(
+                        &index.1,
+                        format!("expected an Integer index, found {}", index_ty),
+                    );
+                }
+                match self.infer(object) {
+                    Type::Array(element) => *element,
+                    ty if ty.is_unknown() => Type::Any,
+                    ty => {
+                        self.error(span, format!("cannot index a value of type {}", ty));
+                        Type::Any
+                    }
+                }
+            }
+            Expr::Call {
+                function,
+                arguments,
+            } => self.infer_call(function, arguments, span),
+            Expr::Unary { op, argument } => match op {
+                UnaryOp::Not => {
+                    self.expect_boolean(argument, "operand of `!`");
+                    Type::Boolean
+                }
+                UnaryOp::Neg => match self.infer(argument) {
+                    ty if ty.is_numeric() => ty,
+                    ty if ty.is_unknown() => Type::Any,
+                    ty => {
+                        self.error(
+                            &argument.1,
+                            format!("operand of `-` must be a number, found {}", ty),
+                        );
+                        Type::Any
+                    }
+                },
+            },
+            Expr::Binary { op, left, right } => self.infer_binary(*op, left, right, span),
+            Expr::Ternary {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.expect_boolean(condition, "condition of the ternary");
+                let consequence_ty = self.infer(consequence);
+                let alternative_ty = self.infer(alternative);
+                match join(&consequence_ty, &alternative_ty) {
+                    Some(ty) => ty,
+                    None => {
+                        self.error(
+                            &alternative.1,
+                            format!(
+                                "branches of the ternary have different types: {} and {}",
+                                consequence_ty, alternative_ty
+                            ),
+                        );
+                        Type::Any
+                    }
+                }
+            }
+        }
+    }
+
+    fn attribute(&mut self, root: &str, (name, span): &Spanned<String>) -> Type {
+        let profile = match self.schema.profile(root) {
+            Some(profile) => profile,
+            None => return Type::Any,
+        };
+        match profile.get(name) {
+            Some(attribute) => attribute.ty.clone(),
+            None => {
+                if profile.complete {
+                    self.error(span, format!("unknown attribute `{}` on {}", name, root));
+                }
+                Type::Any
+            }
+        }
+    }
+
+    fn expect_boolean(&mut self, expr: &Spanned<Expr>, what: &str) {
+        let ty = self.infer(expr);
+        if !ty.is_unknown() && ty != Type::Boolean {
+            self.error(&expr.1, format!("{} must be Boolean, found {}", what, ty));
+        }
+    }
+
+    fn infer_call(
+        &mut self,
+        function: &Spanned<Expr>,
+        arguments: &[Spanned<Expr>],
+        span: &Span,
+    ) -> Type {
+        let path = function.0.path();
+        if let (Dialect::IdentityEngine, Expr::Member { object, property }) =
+            (self.dialect, &function.0)
+        {
+            if path
+                .as_ref()
+                .map_or(true, |name| self.registry.get(name).is_none())
+            {
+                return self.infer_method(object, property, arguments, span);
+            }
+        }
+        let arg_types = arguments
+            .iter()
+            .map(|argument| self.infer(argument))
+            .collect::<Vec<_>>();
+        let name = match path {
+            Some(name) => name,
+            None => {
+                self.infer(function);
+                self.error(&function.1, "expression is not callable");
+                return Type::Any;
+            }
+        };
+        let builtin = match self.registry.get(&name) {
+            Some(builtin) => builtin,
+            None => {
+                self.error(&function.1, format!("unknown function `{}`", name));
+                return Type::Any;
+            }
+        };
+        if !self.context.allows_function(&name) {
+            self.errors.push(TypeError {
+                fixes: ExpressionContext::with_function(&name)
+                    .into_iter()
+                    .map(Fix::SetContext)
+                    .collect(),
+                ..TypeError::new(
+                    &function.1,
+                    format!(
+                        "`{}` is not available in {}",
+                        name,
+                        self.context.name().to_lowercase()
+                    ),
+                )
+            });
+        }
+        self.check_arguments(&name, builtin, arguments, &arg_types, span)
+    }
+
+    /// Checks an Identity Engine method call against the methods of the type
+    /// of `object`. Any method of that name is accepted when the type is not
+    /// known.
+    fn infer_method(
+        &mut self,
+        object: &Spanned<Expr>,
+        (method, method_span): &Spanned<String>,
+        arguments: &[Spanned<Expr>],
+        span: &Span,
+    ) -> Type {
+        let receiver_ty = self.infer(object);
+        let arg_types = arguments
+            .iter()
+            .map(|argument| self.infer(argument))
+            .collect::<Vec<_>>();
+        let receiver = match (&object.0, &receiver_ty) {
+            (Expr::Ident(root), _) if root == "user" => Some("User"),
+            (_, ty) if ty.is_string() => Some("String"),
+            (_, ty) if ty.is_unknown() => None,
+            (_, ty) => Some(match ty {
+                Type::Array(_) => "Array",
+                _ => "Object",
+            }),
+        };
+        let builtin = match receiver {
+            Some(receiver) => self.registry.method(receiver, method),
+            None => self.registry.any_method(method),
+        };
+        match builtin {
+            Some(builtin) => self.check_arguments(method, builtin, arguments, &arg_types, span),
+            None => {
+                let on = match receiver {
+                    Some(receiver) => format!(" on {}", receiver),
+                    None => String::new(),
+                };
+                self.error(method_span, format!("unknown method `{}`{}", method, on));
+                Type::Any
+            }
+        }
+    }
+
+    /// Checks the number and types of the arguments of a call to `builtin`
+    /// and returns the type of its result.
+    fn check_arguments(
+        &mut self,
+        name: &str,
+        builtin: &Builtin,
+        arguments: &[Spanned<Expr>],
+        arg_types: &[Type],
+        span: &Span,
+    ) -> Type {
+        let signatures = Signature::all(builtin);
+        let min = signatures
+            .iter()
+            .map(Signature::min_args)
+            .min()
+            .unwrap_or(0);
+        let max = signatures
+            .iter()
+            .map(Signature::max_args)
+            .try_fold(0, |max, other| other.map(|other| max.max(other)));
+        let signature = Signature::best(&signatures, arg_types)
+            .unwrap_or(&signatures[0])
+            .clone();
+
+        if arguments.len() < min {
+            self.error(
+                span,
+                format!(
+                    "{} expects at least {} argument{}, found {}",
+                    name,
+                    min,
+                    if min == 1 { "" } else { "s" },
+                    arguments.len()
+                ),
+            );
+        }
+        if let Some(max) = max.filter(|max| arguments.len() > *max) {
+            self.error(
+                &arguments[max].1,
+                format!(
+                    "{} expects at most {} argument{}, found {}",
+                    name,
+                    max,
+                    if max == 1 { "" } else { "s" },
+                    arguments.len()
+                ),
+            );
+        }
+
+        // `T` is bound by the first argument that mentions it.
+        let mut var = None;
+        for (index, (argument, ty)) in arguments.iter().zip(arg_types).enumerate() {
+            let param = match signature.param(index) {
+                Some(param) => param,
+                None => break,
+            };
+            if !accepts(&param.ty, ty, &mut var) {
+                self.error(
+                    &argument.1,
+                    format!(
+                        "argument {} of {} must be {}, found {}",
+                        index + 1,
+                        name,
+                        substitute(&param.ty, &var),
+                        ty
+                    ),
+                );
+            }
+        }
+        substitute(&signature.returns, &var)
+    }
+
+    fn infer_binary(
+        &mut self,
+        op: BinaryOp,
+        left: &Spanned<Expr>,
+        right: &Spanned<Expr>,
+        span: &Span,
+    ) -> Type {
+        if let BinaryOp::And | BinaryOp::Or = op {
+            let what = format!("operand of {}", op);
+            self.expect_boolean(left, &what);
+            self.expect_boolean(right, &what);
+            return Type::Boolean;
+        }
+
+        let lhs = self.infer(left);
+        let rhs = self.infer(right);
+        let unknown = lhs.is_unknown() || rhs.is_unknown();
+        match op {
+            BinaryOp::Eq | BinaryOp::NotEq => {
+                if !unknown && join(&lhs, &rhs).is_none() {
+                    self.error(span, format!("cannot compare {} with {}", lhs, rhs));
+                }
+                Type::Boolean
+            }
+            BinaryOp::Matches => {
+                let matchable = lhs.is_string() && rhs.is_string();
+                if !unknown && !matchable {
+                    self.error(
+                        span,
+                        format!("cannot apply `matches` to {} and {}", lhs, rhs),
+                    );
+                }
+                Type::Boolean
+            }
+            BinaryOp::Lt | BinaryOp::LtEq | BinaryOp::Gt | BinaryOp::GtEq => {
+                let comparable =
+                    (lhs.is_numeric() && rhs.is_numeric()) || (lhs.is_string() && rhs.is_string());
+                if !unknown && !comparable {
+                    self.error(span, format!("cannot compare {} with {}", lhs, rhs));
+                }
+                Type::Boolean
+            }
+            // `+` concatenates as soon as either side is a String.
+            BinaryOp::Add if lhs.is_string() || rhs.is_string() => Type::String,
+            _ if unknown => Type::Any,
+            _ if lhs == Type::Integer && rhs == Type::Integer => Type::Integer,
+            _ if lhs.is_numeric() && rhs.is_numeric() => Type::Number,
+            _ => {
+                self.error(
+                    span,
+                    format!("cannot apply `{}` to {} and {}", op, lhs, rhs),
+                );
+                Type::Any
+            }
+        }
+    }
+}
+
+/// Whether a parameter of type `expected` accepts an argument of type
+/// `actual`, binding `T` on the way.
+fn accepts(expected: &Type, actual: &Type, var: &mut Option<Type>) -> bool {
+    match (expected, actual) {
+        (Type::Var, _) => match var.clone() {
+            Some(bound) => accepts(&bound, actual, var),
+            None => {
+                if !actual.is_unknown() {
+                    *var = Some(actual.clone());
+                }
+                true
+            }
+        },
+        (Type::Any, _) | (_, Type::Any) | (_, Type::Null) | (_, Type::Var) => true,
+        (Type::Number, Type::Integer) => true,
+        _ if expected.is_string() && actual.is_string() => true,
+        (Type::Array(expected), Type::Array(actual)) => accepts(expected, actual, var),
+        _ => expected == actual,
+    }
+}
+
+fn substitute(ty: &Type, var: &Option<Type>) -> Type {
+    match ty {
+        Type::Var => var.clone().unwrap_or(Type::Any),
+        Type::Array(element) => Type::Array(Box::new(substitute(element, var))),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builtins::{groups, string, Call};
+    use crate::eval::Result;
+    use crate::oel::parse;
+
+    fn builtin(signature: &'static str) -> Builtin {
+        fn func(_: &Call) -> Result<Value> {
+            Ok(Value::Null)
+        }
+        Builtin {
+            name: "Test.f",
+            signature,
+            func,
+        }
+    }
+
+    fn errors(src: &str, context: ExpressionContext) -> Vec<(String, Vec<Fix>)> {
+        let (entries, _) = parse(src);
+        let registry = Registry::default();
+        check(
+            &entries[0].value,
+            &registry,
+            &Schema::default(),
+            context,
+            Dialect::Classic,
+        )
+        .into_iter()
+        .map(|error| (error.msg, error.fixes))
+        .collect()
+    }
+
+    fn messages(src: &str) -> Vec<String> {
+        errors(src, ExpressionContext::Any)
+            .into_iter()
+            .map(|(msg, _)| msg)
+            .collect()
+    }
+
+    #[test]
+    fn parses_signatures() {
+        let signature =
+            Signature::parse("(string: String, start: Integer, end?: Integer) -> String").unwrap();
+        assert_eq!((signature.min_args(), signature.max_args()), (2, Some(3)));
+        assert!(signature.params[2].optional);
+        assert_eq!(signature.returns, Type::String);
+        assert_eq!(
+            signature.to_string(),
+            "(string: String, start: Integer, end?: Integer) -> String"
+        );
+
+        let variadic =
+            Signature::parse("(separator: String, strings: String...) -> String").unwrap();
+        assert_eq!((variadic.min_args(), variadic.max_args()), (1, None));
+        assert_eq!(variadic.param(7).unwrap().name, "strings");
+
+        let generic = Signature::parse("(array: Array<T>, index: Integer) -> T").unwrap();
+        assert_eq!(generic.params[0].ty, Type::Array(Box::new(Type::Var)));
+        assert_eq!(generic.returns, Type::Var);
+        assert_eq!(Signature::parse("() -> Date").unwrap().params, []);
+
+        for malformed in [
+            "(string String) -> String",
+            "(string: Text) -> String",
+            "(string: String) String",
+            "string: String -> String",
+            "(array: Array<String) -> Integer",
+        ] {
+            assert_eq!(Signature::parse(malformed), None, "{}", malformed);
+        }
+    }
+
+    #[test]
+    fn generic_parameters_bind_to_the_first_argument() {
+        let signature = Signature::parse("(array: Array<T>, value: T) -> Boolean").unwrap();
+        let strings = Type::Array(Box::new(Type::String));
+        assert!(signature.accepts(&[strings.clone(), Type::String]));
+        assert!(signature.accepts(&[strings.clone(), Type::Date]));
+        assert!(!signature.accepts(&[strings.clone(), Type::Integer]));
+        assert!(signature.accepts(&[strings, Type::Null]));
+        assert!(signature.accepts(&[Type::Any, Type::Integer]));
+        assert!(!signature.accepts(&[Type::String]));
+        // Too many arguments are the arity check's business.
+        assert!(Signature::parse("(value: Integer) -> Integer")
+            .unwrap()
+            .accepts(&[Type::Integer, Type::Boolean]));
+    }
+
+    #[test]
+    fn best_picks_the_overload_that_fits() {
+        let signatures = Signature::all(&builtin(
+            "(value: String) -> String | (value: Integer, digits: Integer) -> Number",
+        ));
+        assert_eq!(signatures.len(), 2);
+        let best =
+            |args: &[Type]| Signature::best(&signatures, args).map(|best| best.returns.clone());
+        assert_eq!(best(&[Type::String]), Some(Type::String));
+        assert_eq!(best(&[Type::Integer, Type::Integer]), Some(Type::Number));
+        // The right number of arguments, but of the wrong types.
+        assert_eq!(best(&[Type::Boolean]), Some(Type::String));
+        assert_eq!(best(&[]), None);
+        assert_eq!(best(&[Type::Any, Type::Any, Type::Any]), None);
+    }
+
+    #[test]
+    fn malformed_signatures_do_not_panic() {
+        let partly = Signature::all(&builtin(
+            "(value: Text) -> String | (value: String) -> Integer",
+        ));
+        assert_eq!(partly.len(), 1);
+        assert_eq!(partly[0].returns, Type::Integer);
+        let broken = Signature::of(&builtin("value -> String"));
+        assert_eq!(broken.returns, Type::Any);
+        assert!(broken.accepts_arity(0) && broken.accepts_arity(5));
+        assert!(broken.accepts(&[Type::Boolean, Type::Object]));
+    }
+
+    #[test]
+    fn every_builtin_signature_parses() {
+        let registry = Registry::default();
+        let methods = [string::METHODS, groups::METHODS];
+        let builtins = registry
+            .functions()
+            .chain(methods.iter().flat_map(|table| table.iter()));
+        for builtin in builtins {
+            for overload in builtin.signature.split(" | ") {
+                assert!(
+                    Signature::parse(overload).is_some(),
+                    "malformed signature for {}: {}",
+                    builtin.name,
+                    overload
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn operators() {
+        assert!(messages("1 + 2.5 > 3 AND 'a' + 1 == 'a1'").is_empty());
+        assert!(messages("user.login + 1").is_empty());
+        assert_eq!(
+            messages("true + 1"),
+            ["cannot apply `+` to Boolean and Integer"]
+        );
+        assert_eq!(messages("1 < 'a'"), ["cannot compare Integer with String"]);
+        assert_eq!(
+            messages("1 == true"),
+            ["cannot compare Integer with Boolean"]
+        );
+        assert_eq!(
+            messages("1 AND true"),
+            ["operand of AND must be Boolean, found Integer"]
+        );
+        assert_eq!(
+            messages("'a' * 2 - 1"),
+            ["cannot apply `*` to String and Integer"]
+        );
+    }
+
+    #[test]
+    fn context_errors_offer_fixes() {
+        assert_eq!(
+            errors("appuser.login", ExpressionContext::GroupRule),
+            [(
+                "`appuser` is not available in group rules".to_string(),
+                vec![
+                    Fix::Replace("user".to_string()),
+                    Fix::SetContext(ExpressionContext::ProfileMapping),
+                    Fix::SetContext(ExpressionContext::AttributeStatement),
+                ]
+            )]
+        );
+        let fixes = errors("getManagerUser('ad')", ExpressionContext::TokenClaim)
+            .into_iter()
+            .flat_map(|(_, fixes)| fixes)
+            .collect::<Vec<_>>();
+        assert_eq!(fixes, [Fix::SetContext(ExpressionContext::ProfileMapping)]);
+        assert!(errors("appuser.login", ExpressionContext::ProfileMapping).is_empty());
+    }
+
+    #[test]
+    fn entries_are_checked_and_need_unique_names() {
+        let src = "rule \"A\" = 1\nrule \"B\" = foo()\nrule \"A\" = 2\n";
+        let (entries, _) = parse(src);
+        let errors = check_entries(
+            &entries,
+            &Registry::default(),
+            &Schema::default(),
+            ExpressionContext::Any,
+            Dialect::Classic,
+        );
+        let messages = errors
+            .iter()
+            .map(|error| error.msg.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert!(messages[0].contains("foo"), "{:?}", messages);
+        assert_eq!(messages[1], "duplicate rule \"A\"");
+        assert_eq!(errors[1].span.start.row, 2);
+    }
+}
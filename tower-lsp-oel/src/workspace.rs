@@ -0,0 +1,443 @@
+//! An index of the `.oel` files in the workspace folders, kept up to date
+//! with the open documents: the entries of each file, and the profile
+//! attributes and group names they use. OEL has no variables, so these
+//! names are what workspace symbols, references and rename work on.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use dashmap::mapref::one::Ref;
+use dashmap::DashMap;
+use tower_lsp::lsp_types::{Location, SymbolInformation, SymbolKind, Url};
+use tree_sitter::Point;
+
+use crate::dialect::Dialect;
+use crate::eval::ROOTS;
+use crate::oel::{parse, walk, Entry, Expr, Span, Spanned, Value};
+use crate::position::{LineIndex, PositionEncoding};
+
+pub const EXTENSION: &str = "oel";
+
+/// Directories never worth indexing.
+const SKIPPED_DIRS: &[&str] = &["node_modules", "target"];
+
+/// Something an expression refers to by name.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Name {
+    /// A profile attribute, `user.costCenter`, or `user.profile.costCenter`
+    /// in Identity Engine expressions.
+    Attribute { root: String, attribute: String },
+    /// A group name literal, as in `isMemberOfGroupName("Admins")`.
+    Group(String),
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Name::Attribute { root, attribute } => write!(f, "{}.{}", root, attribute),
+            Name::Group(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// A use of a [`Name`]. The span covers the attribute's identifier or the
+/// whole string literal of the group name, quotes included.
+#[derive(Clone, Debug)]
+pub struct Occurrence {
+    pub name: Name,
+    pub span: Span,
+}
+
+/// What the index knows about one file.
+#[derive(Clone, Debug, Default)]
+pub struct FileIndex {
+    pub src: String,
+    pub entries: Vec<Entry>,
+    /// In source order.
+    pub occurrences: Vec<Occurrence>,
+}
+
+impl FileIndex {
+    pub fn new(src: String, entries: Vec<Entry>, dialect: Dialect) -> Self {
+        let mut occurrences = entries
+            .iter()
+            .flat_map(|entry| occurrences(&entry.value, dialect))
+            .collect::<Vec<_>>();
+        occurrences.sort_by_key(|occurrence| occurrence.span.start);
+        FileIndex {
+            src,
+            entries,
+            occurrences,
+        }
+    }
+
+    pub fn parse(src: String, dialect: Dialect) -> Self {
+        let (entries, _) = parse(&src);
+        Self::new(src, entries, dialect)
+    }
+
+    /// The use of a name at `position`, ends included.
+    pub fn occurrence_at(&self, position: Point) -> Option<&Occurrence> {
+        self.occurrences
+            .iter()
+            .find(|occurrence| occurrence.span.start <= position && position <= occurrence.span.end)
+    }
+
+    /// The named entry containing `span`.
+    fn entry_name(&self, span: &Span) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|entry| entry.contains(span.start))
+            .and_then(|entry| entry.name.as_ref())
+            .map(|(name, _)| name.as_str())
+    }
+}
+
+/// The attributes and group names `expr` uses.
+pub fn occurrences(expr: &Spanned<Expr>, dialect: Dialect) -> Vec<Occurrence> {
+    let mut occurrences = Vec::new();
+    // A callee such as `user.profile.trim` names a method, not an attribute.
+    // Calls are visited before their callees.
+    let mut callees: Vec<&Spanned<Expr>> = Vec::new();
+    walk(expr, &mut |spanned| match &spanned.0 {
+        Expr::Member { .. } if callees.iter().any(|callee| std::ptr::eq(*callee, spanned)) => {}
+        Expr::Member { object, property } => {
+            if let Some(root) = attribute_root(&object.0, dialect) {
+                occurrences.push(Occurrence {
+                    name: Name::Attribute {
+                        root,
+                        attribute: property.0.clone(),
+                    },
+                    span: property.1.clone(),
+                });
+            }
+        }
+        Expr::Call {
+            function,
+            arguments,
+        } => {
+            callees.push(function);
+            for (group, span) in group_names(&function.0, arguments) {
+                occurrences.push(Occurrence {
+                    name: Name::Group(group),
+                    span,
+                });
+            }
+        }
+        _ => {}
+    });
+    occurrences
+}
+
+/// The root object of `object.property` when it reads a profile attribute.
+fn attribute_root(object: &Expr, dialect: Dialect) -> Option<String> {
+    let root = match (dialect, object) {
+        (Dialect::Classic, Expr::Ident(root)) => root,
+        (Dialect::IdentityEngine, Expr::Member { object, property }) if property.0 == "profile" => {
+            match &object.0 {
+                Expr::Ident(root) => root,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+    ROOTS.contains(&root.as_str()).then(|| root.clone())
+}
+
+/// The group names passed to a call: the argument of
+/// `isMemberOfGroupName`, or the `group.profile.name` of the filter of the
+/// Identity Engine `isMemberOf` and `getGroups` methods.
+fn group_names(function: &Expr, arguments: &[Spanned<Expr>]) -> Vec<(String, Span)> {
+    let literal = |(expr, span): &Spanned<Expr>| match expr {
+        Expr::Value(Value::Str(name)) => Some((name.clone(), span.clone())),
+        _ => None,
+    };
+    match (function, arguments) {
+        (Expr::Ident(name), [argument]) if name == "isMemberOfGroupName" => {
+            literal(argument).into_iter().collect()
+        }
+        (Expr::Member { property, .. }, [(Expr::Map(filter), _)])
+            if property.0 == "isMemberOf" || property.0 == "getGroups" =>
+        {
+            filter
+                .iter()
+                .filter(|((key, _), _)| key.name == "group.profile.name")
+                .flat_map(|(_, value)| match &value.0 {
+                    Expr::Array(names) => names.iter().filter_map(literal).collect(),
+                    _ => literal(value).into_iter().collect::<Vec<_>>(),
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// The `.oel` files under `dir`, skipping hidden directories and the
+/// `node_modules` and `target` build directories.
+pub fn oel_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return files,
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        match entry.file_type() {
+            Ok(ty)
+                if ty.is_dir()
+                    && !name.starts_with('.')
+                    && !SKIPPED_DIRS.contains(&name.as_ref()) =>
+            {
+                files.extend(oel_files(&path))
+            }
+            Ok(ty) if ty.is_file() && is_oel_file(&path) => files.push(path),
+            _ => {}
+        }
+    }
+    files.sort();
+    files
+}
+
+pub fn is_oel_file(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |extension| extension == EXTENSION)
+}
+
+#[derive(Debug, Default)]
+pub struct WorkspaceIndex {
+    files: DashMap<Url, FileIndex>,
+}
+
+impl WorkspaceIndex {
+    pub fn insert(&self, uri: Url, file: FileIndex) {
+        self.files.insert(uri, file);
+    }
+
+    pub fn remove(&self, uri: &Url) {
+        self.files.remove(uri);
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<Ref<'_, Url, FileIndex>> {
+        self.files.get(uri)
+    }
+
+    /// Every use of `name` in the workspace, by file and in source order.
+    pub fn locations(&self, name: &Name, encoding: PositionEncoding) -> Vec<Location> {
+        self.uses(name, encoding)
+            .into_iter()
+            .map(|(location, _)| location)
+            .collect()
+    }
+
+    /// Like [`locations`](Self::locations), with the text of each use as
+    /// written, e.g. `'Admins'` with its quotes.
+    pub fn uses(&self, name: &Name, encoding: PositionEncoding) -> Vec<(Location, String)> {
+        let mut uses = Vec::new();
+        for file in self.files.iter() {
+            let (uri, file) = file.pair();
+            let index = LineIndex::new(&file.src, encoding);
+            uses.extend(
+                file.occurrences
+                    .iter()
+                    .filter(|occurrence| occurrence.name == *name)
+                    .map(|occurrence| {
+                        let (start, end) = (occurrence.span.start, occurrence.span.end);
+                        let location = Location::new(uri.clone(), index.range(start, end));
+                        (location, index.text(start, end).to_string())
+                    }),
+            );
+        }
+        uses.sort_by(|(a, _), (b, _)| {
+            (a.uri.as_str(), a.range.start).cmp(&(b.uri.as_str(), b.range.start))
+        });
+        uses
+    }
+
+    /// Forgets the files under `folder`, when it leaves the workspace.
+    pub fn remove_folder(&self, folder: &Path) {
+        self.files.retain(|uri, _| {
+            !uri.to_file_path()
+                .map_or(false, |path| path.starts_with(folder))
+        });
+    }
+
+    /// Entries, attributes and group names whose name contains `query`,
+    /// ignoring case. Attributes and group names are listed once per entry
+    /// using them.
+    pub fn symbols(&self, query: &str, encoding: PositionEncoding) -> Vec<SymbolInformation> {
+        let query = query.to_lowercase();
+        let matches = |name: &str| name.to_lowercase().contains(&query);
+        let mut symbols = Vec::new();
+        for file in self.files.iter() {
+            let (uri, file) = file.pair();
+            let index = LineIndex::new(&file.src, encoding);
+            let location =
+                |span: &Span| Location::new(uri.clone(), index.range(span.start, span.end));
+            for (name, span) in file.entries.iter().filter_map(|entry| entry.name.as_ref()) {
+                if matches(name) {
+                    symbols.push(symbol_information(
+                        name.clone(),
+                        SymbolKind::FUNCTION,
+                        location(span),
+                        None,
+                    ));
+                }
+            }
+            let mut seen = HashSet::new();
+            for occurrence in &file.occurrences {
+                let name = occurrence.name.to_string();
+                let entry = file.entry_name(&occurrence.span);
+                if !matches(&name) || !seen.insert((&occurrence.name, entry)) {
+                    continue;
+                }
+                let kind = match occurrence.name {
+                    Name::Attribute { .. } => SymbolKind::FIELD,
+                    Name::Group(_) => SymbolKind::STRING,
+                };
+                symbols.push(symbol_information(
+                    name,
+                    kind,
+                    location(&occurrence.span),
+                    entry.map(str::to_string),
+                ));
+            }
+        }
+        symbols
+    }
+}
+
+fn symbol_information(
+    name: String,
+    kind: SymbolKind,
+    location: Location,
+    container_name: Option<String>,
+) -> SymbolInformation {
+    // `deprecated` is superseded by `tags`, but has to be set.
+    #[allow(deprecated)]
+    SymbolInformation {
+        name,
+        kind,
+        tags: None,
+        deprecated: None,
+        location,
+        container_name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(root: &str, attribute: &str) -> Name {
+        Name::Attribute {
+            root: root.to_string(),
+            attribute: attribute.to_string(),
+        }
+    }
+
+    /// The names used in `src` and the text each span covers.
+    fn uses(src: &str, dialect: Dialect) -> Vec<(Name, String)> {
+        let file = FileIndex::parse(src.to_string(), dialect);
+        let index = LineIndex::new(&file.src, PositionEncoding::Utf16);
+        file.occurrences
+            .iter()
+            .map(|occurrence| {
+                let text = index.text(occurrence.span.start, occurrence.span.end);
+                (occurrence.name.clone(), text.to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn attribute_spans_cover_the_identifier() {
+        assert_eq!(
+            uses("user.login + String.len(user.email)", Dialect::Classic),
+            [
+                (attribute("user", "login"), "login".to_string()),
+                (attribute("user", "email"), "email".to_string()),
+            ]
+        );
+        assert_eq!(
+            uses(
+                "user.profile.login + user.profile.trim()",
+                Dialect::IdentityEngine
+            ),
+            [(attribute("user", "login"), "login".to_string())]
+        );
+        assert_eq!(uses("user.profile.login", Dialect::Classic), []);
+    }
+
+    #[test]
+    fn group_spans_include_the_quotes() {
+        assert_eq!(
+            uses("isMemberOfGroupName('Admins')", Dialect::Classic),
+            [(Name::Group("Admins".to_string()), "'Admins'".to_string())]
+        );
+        assert_eq!(
+            uses(
+                r#"user.getGroups({'group.profile.name': {"A", "B"}})"#,
+                Dialect::IdentityEngine
+            ),
+            [
+                (Name::Group("A".to_string()), "\"A\"".to_string()),
+                (Name::Group("B".to_string()), "\"B\"".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn locations_across_files() {
+        let workspace = WorkspaceIndex::default();
+        let a = Url::parse("file:///rules/a.oel").unwrap();
+        let b = Url::parse("file:///rules/b.oel").unwrap();
+        workspace.insert(
+            b.clone(),
+            FileIndex::parse(
+                "rule \"Sales\" = user.department".to_string(),
+                Dialect::Classic,
+            ),
+        );
+        workspace.insert(
+            a.clone(),
+            FileIndex::parse("user.login + user.department".to_string(), Dialect::Classic),
+        );
+        let locations = workspace
+            .locations(&attribute("user", "department"), PositionEncoding::Utf16)
+            .into_iter()
+            .map(|location| (location.uri, location.range.start.character))
+            .collect::<Vec<_>>();
+        assert_eq!(locations, [(a, 18), (b, 20)]);
+    }
+
+    #[test]
+    fn symbols_across_files() {
+        let workspace = WorkspaceIndex::default();
+        let a = Url::parse("file:///rules/a.oel").unwrap();
+        let b = Url::parse("file:///rules/b.oel").unwrap();
+        workspace.insert(
+            b.clone(),
+            FileIndex::parse(
+                "rule \"Sales\" = user.department".to_string(),
+                Dialect::Classic,
+            ),
+        );
+        workspace.insert(
+            a.clone(),
+            FileIndex::parse("user.login + user.department".to_string(), Dialect::Classic),
+        );
+        let symbols = workspace.symbols("sales", PositionEncoding::Utf16);
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].kind, SymbolKind::FUNCTION);
+        assert_eq!(symbols[0].location.uri, b);
+        let symbols = workspace.symbols("DEPARTMENT", PositionEncoding::Utf16);
+        let containers = symbols
+            .iter()
+            .map(|symbol| symbol.container_name.as_deref())
+            .collect::<Vec<_>>();
+        assert_eq!(containers.len(), 2);
+        assert!(containers.contains(&Some("Sales")));
+    }
+}
@@ -8,6 +8,7 @@ module.exports = grammar({
       "member",
       "call",
       "unary_void",
+      "binary_times",
       "binary_concat",
       "binary_relation",
       "binary_equality",
@@ -22,10 +23,27 @@ module.exports = grammar({
 
   //   inline: ($) => [$.expression],
 
+  extras: ($) => [/\s/, $.comment],
+
+  // Keywords such as `and` and `eq` are lexed as identifiers first, so that
+  // `android` or `equal` are not split into a keyword and the rest.
+  word: ($) => $.identifier,
+
   conflicts: ($) => [],
 
   rules: {
-    source_file: ($) => $.expression,
+    // Either a single expression, or named entries so that one file can
+    // hold several related rules, each annotated with comments.
+    source_file: ($) => choice($.expression, repeat1($.entry)),
+
+    entry: ($) =>
+      seq(
+        "rule",
+        field("name", $.string),
+        "=",
+        field("value", $.expression),
+        optional(";")
+      ),
 
     ternary_expression: ($) =>
       prec.right(
@@ -56,6 +74,7 @@ module.exports = grammar({
         $.identifier,
         $.primitive,
         $.array,
+        $.map,
         $.call_expression
         // TODO: other kinds of expressions
       ),
@@ -63,7 +82,7 @@ module.exports = grammar({
     unary_expression: ($) =>
       prec.left(
         "unary_void",
-        seq(field("operator", "!"), field("argument", $.expression))
+        seq(field("operator", choice("!", "-")), field("argument", $.expression))
       ),
 
     binary_expression: ($) =>
@@ -72,12 +91,28 @@ module.exports = grammar({
           ["AND", "logical_and"],
           ["OR", "logical_or"],
           ["+", "binary_concat"],
+          ["-", "binary_concat"],
+          ["*", "binary_times"],
+          ["/", "binary_times"],
+          ["%", "binary_times"],
           ["<", "binary_relation"],
           ["<=", "binary_relation"],
           ["==", "binary_equality"],
           ["!=", "binary_equality"],
           [">=", "binary_relation"],
           [">", "binary_relation"],
+          // Identity Engine (SpEL) spellings
+          ["and", "logical_and"],
+          ["&&", "logical_and"],
+          ["or", "logical_or"],
+          ["||", "logical_or"],
+          ["eq", "binary_equality"],
+          ["ne", "binary_equality"],
+          ["lt", "binary_relation"],
+          ["le", "binary_relation"],
+          ["gt", "binary_relation"],
+          ["ge", "binary_relation"],
+          ["matches", "binary_relation"],
         ].map(([operator, precedence, associativity]) =>
           (associativity === "right" ? prec.right : prec.left)(
             precedence,
@@ -98,7 +133,7 @@ module.exports = grammar({
         seq(
           field("object", choice($.expression, $.primary_expression)),
           ".",
-          field("property", alias($.identifier, "property_identifier"))
+          field("property", alias($.identifier, $.property_identifier))
         )
       ),
 
@@ -135,18 +170,36 @@ module.exports = grammar({
     false: ($) => "false",
     null: ($) => "null",
     array: ($) => seq("{", commaSep($.expression), "}"),
+    // Identity Engine map literals, e.g. `{'group.profile.name': 'Admins'}`.
+    // `{:}` is the empty map, as `{}` is the empty array.
+    map: ($) => seq("{", choice(":", commaSep1($.pair)), "}"),
+    pair: ($) =>
+      seq(
+        field("key", choice($.string, $.identifier, $.integer)),
+        ":",
+        field("value", $.expression)
+      ),
 
-    // TODO: Can OEL have escape characters?
     string: ($) =>
       choice(
         seq(
           '"',
-          repeat(alias($.unescaped_double_string_fragment, "string_fragment")),
+          repeat(
+            choice(
+              alias($.unescaped_double_string_fragment, $.string_fragment),
+              $.escape_sequence
+            )
+          ),
           '"'
         ),
         seq(
           "'",
-          repeat(alias($.unescaped_double_string_fragment, "string_fragment")),
+          repeat(
+            choice(
+              alias($.unescaped_single_string_fragment, $.string_fragment),
+              $.escape_sequence
+            )
+          ),
           "'"
         )
       ),
@@ -158,6 +211,18 @@ module.exports = grammar({
     unescaped_double_string_fragment: ($) =>
       token.immediate(prec(1, /[^"\\]+/)),
 
+    // same here
+    unescaped_single_string_fragment: ($) =>
+      token.immediate(prec(1, /[^'\\]+/)),
+
+    // Any character but a line break may follow the backslash so that
+    // unknown escapes such as `\q`, or `\u` without its four hex digits, are
+    // still part of the string and can be reported by the language server.
+    // A backslash at the end of a line is a syntax error, not a line
+    // continuation.
+    escape_sequence: ($) =>
+      token.immediate(seq("\\", choice(/u[0-9a-fA-F]{0,4}/, /[^u\r\n]/))),
+
     identifier: ($) => {
       const alpha =
         /[^\x00-\x1F\s\p{Zs}0-9:;`"'@#.,|^&<=>+\-*/\\%?!~()\[\]{}\uFEFF\u2060\u200B]|\\u[0-9a-fA-F]{4}|\\u\{[0-9a-fA-F]+\}/;
@@ -167,6 +232,10 @@ module.exports = grammar({
     },
 
     primitive: ($) => choice($.boolean, $.null, $.float, $.integer, $.string),
+
+    // Line comments, also used for pragmas such as `// @context group-rule`.
+    // Okta accepts neither kind, so they are stripped before upload.
+    comment: ($) => token(seq(choice("//", "#"), /[^\n]*/)),
   },
 });
 
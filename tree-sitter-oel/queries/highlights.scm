@@ -7,17 +7,27 @@
 ;  (#match? @constant "^[A-Z_][A-Z\\d_]+$"))
 
 ((identifier) @variable.builtin
- (#match? @variable.builtin "^(user|appuser|idpuser|app|org|session)$"))
+ (#match? @variable.builtin "^(user|appuser|idpuser|app|org|session|source|access|security|device)$"))
+
+((member_expression
+   object: (identifier) @namespace)
+ (#match? @namespace "^[A-Z]"))
 
 ; Function and method calls
 ;--------------------------
 
-; (call_expression
-;   function: (identifier) @function)
+(call_expression
+  function: (identifier) @function)
+
+(call_expression
+  function: (member_expression
+    property: (property_identifier) @function.method))
 
-; (call_expression
-;   function: (member_expression
-;     property: (property_identifier) @function.method))
+; Map keys
+;---------
+
+(pair
+  key: (identifier) @property)
 
 ; Variables
 ;----------
@@ -27,7 +37,12 @@
 ; Properties
 ;-----------
 
-; (property_identifier) @property
+(property_identifier) @property
+
+; Comments
+;---------
+
+(comment) @comment
 
 ; Literals
 ;---------
@@ -39,6 +54,8 @@
   (null)
 ] @constant.builtin
 
+(escape_sequence) @string.escape
+
 [
   (string)
 ] @string
@@ -55,6 +72,10 @@
 
 [
   "+"
+  "-"
+  "*"
+  "/"
+  "%"
   "<"
   "<="
   "=="
@@ -64,8 +85,24 @@
   ">="
   "AND"
   "OR"
+  "&&"
+  "||"
+  "and"
+  "or"
+  "eq"
+  "ne"
+  "lt"
+  "le"
+  "gt"
+  "ge"
+  "matches"
+  "?"
+  ":"
+  "="
 ] @operator
 
+"rule" @keyword
+
 [
   "("
   ")"